@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN progress,
+  DROP COLUMN fps,
+  DROP COLUMN speed,
+  DROP COLUMN bitrate,
+  DROP COLUMN started_at,
+  DROP COLUMN completed_at,
+  DROP COLUMN encode_time,
+  DROP COLUMN output_size,
+  DROP COLUMN average_speed;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN progress DOUBLE PRECISION,
+  ADD COLUMN fps DOUBLE PRECISION,
+  ADD COLUMN speed DOUBLE PRECISION,
+  ADD COLUMN bitrate DOUBLE PRECISION,
+  ADD COLUMN started_at TIMESTAMPTZ,
+  ADD COLUMN completed_at TIMESTAMPTZ,
+  ADD COLUMN encode_time DOUBLE PRECISION,
+  ADD COLUMN output_size BIGINT,
+  ADD COLUMN average_speed DOUBLE PRECISION;
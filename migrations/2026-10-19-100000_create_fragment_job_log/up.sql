@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS transcoding_fragment_job_log (
+  transcoding_fragment_job_log_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  transcoding_fragment_job_id UUID REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE CASCADE NOT NULL,
+  exit_status INT,
+  message TEXT NOT NULL,
+  log TEXT NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL
+);
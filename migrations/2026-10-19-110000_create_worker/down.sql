@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job DROP COLUMN worker_id;
+DROP TABLE IF EXISTS worker;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS worker (
+  worker_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  hostname TEXT NOT NULL,
+  ffmpeg_version TEXT,
+  encoders TEXT[] NOT NULL DEFAULT '{}',
+  filters TEXT[] NOT NULL DEFAULT '{}',
+  cpu_count INT NOT NULL,
+  template_variables JSONB NOT NULL DEFAULT '{}',
+  free_disk_space BIGINT,
+  current_fragment_job_id UUID REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE SET NULL,
+  started_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  last_seen_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  updated_at TIMESTAMPTZ DEFAULT now() NOT NULL
+);
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN worker_id UUID REFERENCES worker(worker_id) ON DELETE SET NULL;
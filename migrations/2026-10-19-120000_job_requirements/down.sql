@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_job
+  DROP COLUMN required_encoders,
+  DROP COLUMN required_variables,
+  DROP COLUMN required_tags,
+  DROP COLUMN min_memory;
+
+ALTER TABLE worker
+  DROP COLUMN tags,
+  DROP COLUMN memory;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN required_encoders TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN required_variables TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN required_tags TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN min_memory BIGINT;
+
+ALTER TABLE worker
+  ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN memory BIGINT;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_job
+  DROP COLUMN priority,
+  DROP COLUMN submitter;
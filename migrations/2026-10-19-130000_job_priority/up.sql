@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN priority INT NOT NULL DEFAULT 0,
+  ADD COLUMN submitter TEXT;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE fragment
+  DROP COLUMN duration;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN deadline;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE fragment
+  ADD COLUMN duration DOUBLE PRECISION;
+
+ALTER TABLE transcoding_job
+  ADD COLUMN deadline TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE worker
+  ADD COLUMN draining BOOLEAN NOT NULL DEFAULT FALSE;
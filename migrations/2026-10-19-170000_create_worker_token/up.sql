@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS worker_token (
+  token_hash TEXT PRIMARY KEY,
+  worker_id UUID NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL
+);
+
+CREATE INDEX worker_token_worker_id ON worker_token (worker_id);
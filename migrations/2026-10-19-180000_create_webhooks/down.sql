@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS webhook_delivery;
+DROP TYPE IF EXISTS webhook_delivery_status;
+DROP TABLE IF EXISTS webhook_event;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN webhook_url;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN webhook_url TEXT;
+
+CREATE TABLE IF NOT EXISTS webhook_event (
+  webhook_event_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  event TEXT NOT NULL,
+  transcoding_job_id UUID REFERENCES transcoding_job(transcoding_job_id) ON DELETE CASCADE NOT NULL,
+  transcoding_fragment_job_id UUID REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE CASCADE,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  dispatched_at TIMESTAMPTZ
+);
+
+CREATE INDEX webhook_event_undispatched ON webhook_event (created_at) WHERE dispatched_at IS NULL;
+
+CREATE TYPE webhook_delivery_status AS ENUM ('pending', 'delivered', 'failed');
+
+CREATE TABLE IF NOT EXISTS webhook_delivery (
+  webhook_delivery_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  webhook_event_id UUID REFERENCES webhook_event(webhook_event_id) ON DELETE CASCADE NOT NULL,
+  url TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  status webhook_delivery_status NOT NULL DEFAULT 'pending',
+  attempts INT NOT NULL DEFAULT 0,
+  response_status INT,
+  error TEXT,
+  next_attempt_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX webhook_delivery_pending ON webhook_delivery (next_attempt_at) WHERE status = 'pending';
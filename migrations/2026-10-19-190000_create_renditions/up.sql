@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS transcoding_rendition (
+  transcoding_rendition_id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  transcoding_job_id UUID REFERENCES transcoding_job(transcoding_job_id) ON DELETE CASCADE NOT NULL,
+  name TEXT NOT NULL,
+  ffmpeg_command TEXT NOT NULL,
+  position INT NOT NULL,
+  created_at TIMESTAMPTZ DEFAULT now() NOT NULL,
+  UNIQUE (transcoding_job_id, name)
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN trim_start,
+  DROP COLUMN trim_end;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN overlap;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN overlap DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN trim_start DOUBLE PRECISION,
+  ADD COLUMN trim_end DOUBLE PRECISION;
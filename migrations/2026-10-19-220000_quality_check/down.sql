@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN quality_flagged,
+  DROP COLUMN quality_score;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN fail_below_threshold,
+  DROP COLUMN quality_threshold,
+  DROP COLUMN quality_metric;
+
+DROP TYPE IF EXISTS quality_metric;
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TYPE quality_metric AS ENUM ('vmaf', 'ssim', 'psnr');
+
+ALTER TABLE transcoding_job
+  ADD COLUMN quality_metric quality_metric,
+  ADD COLUMN quality_threshold DOUBLE PRECISION,
+  ADD COLUMN fail_below_threshold BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN quality_score DOUBLE PRECISION,
+  ADD COLUMN quality_flagged BOOLEAN NOT NULL DEFAULT FALSE;
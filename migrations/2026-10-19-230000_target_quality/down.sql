@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN crf;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN crf_max,
+  DROP COLUMN crf_min,
+  DROP COLUMN target_vmaf;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN target_vmaf DOUBLE PRECISION,
+  ADD COLUMN crf_min INT NOT NULL DEFAULT 10,
+  ADD COLUMN crf_max INT NOT NULL DEFAULT 51;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN crf INT;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE webhook_event
+  DROP COLUMN fragment_status,
+  DROP COLUMN total_fragments,
+  DROP COLUMN completed_fragments,
+  DROP COLUMN job_status;
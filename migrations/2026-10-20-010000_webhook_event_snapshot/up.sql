@@ -0,0 +1,31 @@
+-- Your SQL goes here
+ALTER TABLE webhook_event
+  ADD COLUMN job_status job_status,
+  ADD COLUMN completed_fragments INT,
+  ADD COLUMN total_fragments INT,
+  ADD COLUMN fragment_status fragment_job_status;
+
+-- The events recorded before this migration get the current state of their job.
+UPDATE webhook_event SET
+  job_status = (
+    SELECT status FROM transcoding_job
+    WHERE transcoding_job.transcoding_job_id = webhook_event.transcoding_job_id
+  ),
+  completed_fragments = (
+    SELECT count(*) FROM transcoding_fragment_job
+    WHERE transcoding_fragment_job.transcoding_job_id = webhook_event.transcoding_job_id
+      AND transcoding_fragment_job.status = 'completed'
+  ),
+  total_fragments = (
+    SELECT count(*) FROM transcoding_fragment_job
+    WHERE transcoding_fragment_job.transcoding_job_id = webhook_event.transcoding_job_id
+  ),
+  fragment_status = (
+    SELECT status FROM transcoding_fragment_job
+    WHERE transcoding_fragment_job.transcoding_fragment_job_id = webhook_event.transcoding_fragment_job_id
+  );
+
+ALTER TABLE webhook_event
+  ALTER COLUMN job_status SET NOT NULL,
+  ALTER COLUMN completed_fragments SET NOT NULL,
+  ALTER COLUMN total_fragments SET NOT NULL;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS transcoding_fragment_job_log;
+DROP TABLE IF EXISTS transcoding_fragment_job;
+DROP TABLE IF EXISTS worker;
+DROP TABLE IF EXISTS transcoding_job;
+DROP TABLE IF EXISTS fragment;
+DROP TABLE IF EXISTS media;
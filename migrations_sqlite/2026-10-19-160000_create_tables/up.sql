@@ -0,0 +1,91 @@
+-- Your SQL goes here
+-- SQLite schema, matching the Postgres migrations up to 2026-10-19-150000_worker_drain.
+-- UUIDs are stored as hyphenated text, arrays and JSON values as JSON text.
+
+CREATE TABLE media (
+  media_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  basename TEXT,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  deleted_at TIMESTAMP
+);
+
+CREATE TABLE fragment (
+  fragment_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  media_id TEXT REFERENCES media(media_id) ON DELETE CASCADE NOT NULL,
+  filename TEXT NOT NULL,
+  fragment_number INTEGER,
+  encryption_key TEXT,
+  retrieval_url TEXT,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  deleted_at TIMESTAMP,
+  duration DOUBLE
+);
+
+CREATE TABLE transcoding_job (
+  transcoding_job_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  media_id TEXT REFERENCES media(media_id) ON DELETE CASCADE NOT NULL,
+  ffmpeg_command TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'queued', 'in_progress', 'completed', 'failed', 'cancelled', 'deleted')),
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  deleted_at TIMESTAMP,
+  required_encoders TEXT NOT NULL DEFAULT '[]',
+  required_variables TEXT NOT NULL DEFAULT '[]',
+  required_tags TEXT NOT NULL DEFAULT '[]',
+  min_memory BIGINT,
+  priority INTEGER NOT NULL DEFAULT 0,
+  submitter TEXT,
+  deadline TIMESTAMP
+);
+
+CREATE TABLE worker (
+  worker_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  hostname TEXT NOT NULL,
+  ffmpeg_version TEXT,
+  encoders TEXT NOT NULL DEFAULT '[]',
+  filters TEXT NOT NULL DEFAULT '[]',
+  cpu_count INTEGER NOT NULL,
+  template_variables TEXT NOT NULL DEFAULT '{}',
+  free_disk_space BIGINT,
+  current_fragment_job_id TEXT REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE SET NULL,
+  started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  last_seen_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  tags TEXT NOT NULL DEFAULT '[]',
+  memory BIGINT,
+  draining BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE transcoding_fragment_job (
+  transcoding_fragment_job_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  transcoding_job_id TEXT REFERENCES transcoding_job(transcoding_job_id) ON DELETE CASCADE NOT NULL,
+  fragment_id TEXT REFERENCES fragment(fragment_id) ON DELETE CASCADE NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'queued', 'reserved', 'in_progress', 'completed', 'failed', 'cancelled', 'deleted')),
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  deleted_at TIMESTAMP,
+  progress DOUBLE,
+  fps DOUBLE,
+  speed DOUBLE,
+  bitrate DOUBLE,
+  started_at TIMESTAMP,
+  completed_at TIMESTAMP,
+  encode_time DOUBLE,
+  output_size BIGINT,
+  average_speed DOUBLE,
+  worker_id TEXT REFERENCES worker(worker_id) ON DELETE SET NULL
+);
+
+CREATE TABLE transcoding_fragment_job_log (
+  transcoding_fragment_job_log_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  transcoding_fragment_job_id TEXT REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE CASCADE NOT NULL,
+  exit_status INTEGER,
+  message TEXT NOT NULL,
+  log TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE worker_token (
+  token_hash TEXT PRIMARY KEY NOT NULL,
+  worker_id TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX worker_token_worker_id ON worker_token (worker_id);
@@ -0,0 +1,31 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN webhook_url TEXT;
+
+CREATE TABLE webhook_event (
+  webhook_event_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  event TEXT NOT NULL,
+  transcoding_job_id TEXT REFERENCES transcoding_job(transcoding_job_id) ON DELETE CASCADE NOT NULL,
+  transcoding_fragment_job_id TEXT REFERENCES transcoding_fragment_job(transcoding_fragment_job_id) ON DELETE CASCADE,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  dispatched_at TIMESTAMP
+);
+
+CREATE INDEX webhook_event_undispatched ON webhook_event (created_at) WHERE dispatched_at IS NULL;
+
+CREATE TABLE webhook_delivery (
+  webhook_delivery_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  webhook_event_id TEXT REFERENCES webhook_event(webhook_event_id) ON DELETE CASCADE NOT NULL,
+  url TEXT NOT NULL,
+  payload TEXT NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending'
+    CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  response_status INTEGER,
+  error TEXT,
+  next_attempt_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  delivered_at TIMESTAMP
+);
+
+CREATE INDEX webhook_delivery_pending ON webhook_delivery (next_attempt_at) WHERE status = 'pending';
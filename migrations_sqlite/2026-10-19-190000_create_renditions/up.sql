@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE transcoding_rendition (
+  transcoding_rendition_id TEXT PRIMARY KEY NOT NULL DEFAULT (lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + abs(random()) % 4, 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)))),
+  transcoding_job_id TEXT REFERENCES transcoding_job(transcoding_job_id) ON DELETE CASCADE NOT NULL,
+  name TEXT NOT NULL,
+  ffmpeg_command TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  UNIQUE (transcoding_job_id, name)
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN overlap DOUBLE NOT NULL DEFAULT 0;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN trim_start DOUBLE;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN trim_end DOUBLE;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_rendition
+  DROP COLUMN ffmpeg_passes;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN ffmpeg_passes;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN ffmpeg_passes TEXT NOT NULL DEFAULT '[]';
+
+ALTER TABLE transcoding_rendition
+  ADD COLUMN ffmpeg_passes TEXT NOT NULL DEFAULT '[]';
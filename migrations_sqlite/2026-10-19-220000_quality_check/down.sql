@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN quality_flagged;
+
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN quality_score;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN fail_below_threshold;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN quality_threshold;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN quality_metric;
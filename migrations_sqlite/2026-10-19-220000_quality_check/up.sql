@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN quality_metric TEXT;
+
+ALTER TABLE transcoding_job
+  ADD COLUMN quality_threshold DOUBLE;
+
+ALTER TABLE transcoding_job
+  ADD COLUMN fail_below_threshold BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN quality_score DOUBLE;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN quality_flagged BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transcoding_fragment_job
+  DROP COLUMN crf;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN crf_max;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN crf_min;
+
+ALTER TABLE transcoding_job
+  DROP COLUMN target_vmaf;
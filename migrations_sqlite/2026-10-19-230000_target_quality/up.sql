@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE transcoding_job
+  ADD COLUMN target_vmaf DOUBLE;
+
+ALTER TABLE transcoding_job
+  ADD COLUMN crf_min INTEGER NOT NULL DEFAULT 10;
+
+ALTER TABLE transcoding_job
+  ADD COLUMN crf_max INTEGER NOT NULL DEFAULT 51;
+
+ALTER TABLE transcoding_fragment_job
+  ADD COLUMN crf INTEGER;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE preset (
+  name TEXT PRIMARY KEY NOT NULL,
+  settings TEXT NOT NULL,
+  created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
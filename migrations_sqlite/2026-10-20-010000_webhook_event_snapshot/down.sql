@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE webhook_event
+  DROP COLUMN fragment_status;
+
+ALTER TABLE webhook_event
+  DROP COLUMN total_fragments;
+
+ALTER TABLE webhook_event
+  DROP COLUMN completed_fragments;
+
+ALTER TABLE webhook_event
+  DROP COLUMN job_status;
@@ -1,21 +1,23 @@
 use age::secrecy::ExposeSecret;
 use age::Recipient;
-use anyhow::{anyhow, bail, Result};
-use clap::{Parser, Subcommand};
-use diesel::pg::PgConnection;
+use anyhow::{bail, Result};
 use diesel::prelude::*;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use tempdir::TempDir;
 use tokio::process::Command;
-use tokio_util::compat::{
-    FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt, TokioAsyncReadCompatExt,
-    TokioAsyncWriteCompatExt,
-};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::{model, schema, AddMediaCommand};
+use crate::db::DbConnection;
+use crate::{model, probe, schema, segment, AddMediaCommand};
 
-pub async fn add_media(db: &mut PgConnection, cmd: AddMediaCommand, ffmpeg_bin: &str) -> Result<()> {
+pub async fn add_media(
+    db: &mut DbConnection,
+    cmd: AddMediaCommand,
+    ffmpeg_bin: &str,
+    ffprobe_bin: &str,
+) -> Result<()> {
     let media = model::NewMedia {
         basename: cmd
             .input
@@ -29,10 +31,7 @@ pub async fn add_media(db: &mut PgConnection, cmd: AddMediaCommand, ffmpeg_bin:
 
     println!("Media added: {}", media_id);
 
-    let tmp_dir = TempDir::new(&format!(
-        "transcodeck-{}",
-        media_id.as_hyphenated().to_string()
-    ))?;
+    let tmp_dir = TempDir::new(&format!("transcodeck-{}", media_id.as_hyphenated()))?;
     let mut fragments = Vec::new();
 
     if cmd.fragment > 0 {
@@ -48,15 +47,35 @@ pub async fn add_media(db: &mut PgConnection, cmd: AddMediaCommand, ffmpeg_bin:
         tokio::fs::create_dir_all(&output_dir).await?;
 
         println!("Fragmenting media into {} second pieces", cmd.fragment);
-        let _fragments =
-            fragment_media(ffmpeg_bin, cmd.input.clone(), output_dir, cmd.fragment as usize).await?;
+        let plan = segment::plan(
+            ffmpeg_bin,
+            ffprobe_bin,
+            &cmd.input,
+            cmd.fragment as f64,
+            cmd.segmentation,
+            cmd.scene_threshold,
+        )
+        .await?;
+        if let Some(split_points) = &plan.split_points {
+            println!("Cutting at {} split points", split_points.len());
+        }
+        let _fragments = fragment_media(
+            ffmpeg_bin,
+            cmd.input.clone(),
+            &output_dir,
+            cmd.fragment as usize,
+            &plan,
+        )
+        .await?;
         for fragment in _fragments {
+            let duration = probe_duration(ffprobe_bin, output_dir.join(&fragment.filename)).await;
             fragments.push(model::NewFragment {
                 media_id,
                 filename: fragment.filename,
                 fragment_number: fragment.fragment_number,
                 encryption_key: None,
                 retrieval_url: None,
+                duration,
             });
         }
     } else {
@@ -66,6 +85,7 @@ pub async fn add_media(db: &mut PgConnection, cmd: AddMediaCommand, ffmpeg_bin:
             fragment_number: None,
             encryption_key: None,
             retrieval_url: None,
+            duration: probe_duration(ffprobe_bin, &cmd.input).await,
         };
         fragments.push(fragment);
     }
@@ -99,13 +119,19 @@ pub async fn add_media(db: &mut PgConnection, cmd: AddMediaCommand, ffmpeg_bin:
     }
 
     if let Err(e) = tmp_dir.close() {
-        eprintln!("Failed to clean up temporary directory: {}", e);
+        warn!("Failed to clean up temporary directory: {}", e);
     }
 
     println!("Adding {} fragments to the database.", fragments.len());
-    diesel::insert_into(schema::fragment::table)
-        .values(&fragments)
-        .execute(db)?;
+    // One insert per row, multi-row inserts are not supported by every backend.
+    db.transaction(|db| {
+        for fragment in fragments {
+            diesel::insert_into(schema::fragment::table)
+                .values(fragment)
+                .execute(db)?;
+        }
+        diesel::QueryResult::Ok(())
+    })?;
 
     Ok(())
 }
@@ -115,22 +141,59 @@ pub async fn fragment_media(
     input: impl AsRef<Path>,
     output_dir: impl AsRef<Path>,
     duration: usize,
+    plan: &segment::Plan,
 ) -> Result<Vec<model::NewFragment>> {
-    let status = Command::new(ffmpeg_bin)
+    let mut command = Command::new(ffmpeg_bin);
+    command
         .arg("-hide_banner")
         .arg("-loglevel")
         .arg("error")
         .arg("-stats")
         .arg("-y")
         .arg("-i")
-        .arg(input.as_ref())
-        .arg("-c:v")
-        .arg("copy")
-        .arg("-an")
-        .arg("-f")
-        .arg("segment")
-        .arg("-segment_time")
-        .arg(duration.to_string())
+        .arg(input.as_ref());
+    if plan.reencode {
+        // Lossless, so that the transcoding does not suffer a generation loss, and in the pixel
+        // format of the source instead of the one negotiated with the encoder, so that the
+        // fragments keep its chroma subsampling and bit depth.
+        // Every boundary gets a keyframe, and the GOPs are closed so that every fragment
+        // decodes on its own.
+        command
+            .arg("-c:v")
+            .arg("libx264")
+            .arg("-preset")
+            .arg("veryfast")
+            .arg("-qp")
+            .arg("0")
+            .arg("-flags")
+            .arg("+cgop")
+            .arg("-force_key_frames")
+            .arg(format!("expr:gte(t,n_forced*{})", duration));
+        if let Some(pixel_format) = &plan.pixel_format {
+            command.arg("-pix_fmt").arg(pixel_format);
+        }
+    } else {
+        command.arg("-c:v").arg("copy");
+    }
+    command.arg("-an").arg("-f").arg("segment");
+    match &plan.split_points {
+        Some(split_points) if !split_points.is_empty() => {
+            let times = split_points
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            command.arg("-segment_times").arg(times);
+        }
+        // A single fragment
+        Some(_) => {
+            command.arg("-segment_time").arg(u32::MAX.to_string());
+        }
+        None => {
+            command.arg("-segment_time").arg(duration.to_string());
+        }
+    }
+    let status = command
         .arg("-reset_timestamps")
         .arg("1")
         .arg(output_dir.as_ref().join("fragment-%03d.mkv"))
@@ -146,10 +209,14 @@ pub async fn fragment_media(
 
     // Waiting for completion of the command
 
-    // Listing the files in the output directory
+    // Listing the files in the output directory, in the order of the fragments
+    let mut paths = vec![];
     let mut dir = tokio::fs::read_dir(output_dir.as_ref()).await?;
     while let Some(entry) = dir.next_entry().await? {
-        let path = entry.path();
+        paths.push(entry.path());
+    }
+    paths.sort();
+    for path in paths {
         if path.is_file() {
             let fragment = model::NewFragment {
                 media_id: Uuid::nil(),
@@ -157,6 +224,7 @@ pub async fn fragment_media(
                 fragment_number: Some(fragment_number),
                 encryption_key: None,
                 retrieval_url: None,
+                duration: None,
             };
             fragments.push(fragment);
             fragment_number += 1;
@@ -166,6 +234,21 @@ pub async fn fragment_media(
     Ok(fragments)
 }
 
+/// Probe the duration of a fragment, used to estimate the remaining time of jobs.
+async fn probe_duration(ffprobe_bin: &str, path: impl AsRef<Path>) -> Option<f64> {
+    match probe::duration(ffprobe_bin, path.as_ref()).await {
+        Ok(duration) => Some(duration),
+        Err(err) => {
+            warn!(
+                path = %path.as_ref().display(),
+                "Failed to probe duration: {}",
+                err
+            );
+            None
+        }
+    }
+}
+
 async fn encrypt_file(
     input: impl AsRef<Path>,
     output: impl AsRef<Path>,
@@ -176,8 +259,8 @@ async fn encrypt_file(
     let encryptor =
         age::Encryptor::with_recipients(vec![pubkey]).expect("Failed to create encryptor");
 
-    let mut input_file = tokio::fs::File::open(input).await?;
-    let mut output_file = tokio::fs::File::create(output).await?;
+    let input_file = tokio::fs::File::open(input).await?;
+    let output_file = tokio::fs::File::create(output).await?;
 
     let mut enc_writer = encryptor.wrap_async_output(output_file.compat()).await?;
     futures::io::copy(&mut input_file.compat(), &mut enc_writer).await?;
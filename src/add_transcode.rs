@@ -1,71 +1,543 @@
-use anyhow::{anyhow, bail, Result};
-use clap::{Parser, Subcommand};
-use diesel::pg::PgConnection;
+use anyhow::{bail, Context, Result};
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::{model, schema, TranscodeCommand};
-use model::{FragmentJobStatus, JobStatus};
+use crate::db::DbConnection;
+use crate::policy::Policy;
+use crate::{model, preset, schema, template, webhook, worker, TranscodeCommand};
+use model::{FragmentJobStatus, JobStatus, QualityMetric};
 
-pub async fn new_transcode(db: &mut PgConnection, cmd: TranscodeCommand) -> Result<()> {
-    let media_id = Uuid::parse_str(&cmd.media_id)?;
-    let media = schema::media::table
-        .filter(schema::media::media_id.eq(media_id))
-        .first::<model::Media>(db)?;
+/// A new transcoding job, from the `transcode` command or the management API.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct JobRequest {
+    /// The media ID to transcode
+    #[serde(skip_serializing_if = "Uuid::is_nil")]
+    pub media_id: Uuid,
+    /// Name of a preset providing the settings left empty by the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    /// The ffmpeg command to use for transcoding, empty if the job has renditions.
+    pub ffmpeg_command: String,
+    /// ffmpeg commands run before `ffmpeg_command`, in order and in the same directory, e.g.
+    /// the first pass of a two-pass encode (`-pass 1 -passlogfile {passlogfile} -f null -`).
+    pub ffmpeg_passes: Vec<String>,
+    /// Named renditions sharing the fragments of the media, each with its own ffmpeg command,
+    /// e.g. the steps of an adaptive bitrate ladder.
+    pub renditions: Vec<RenditionRequest>,
+    /// Whether the job is queued to be processed immediately.
+    pub start: bool,
+    /// Worker variables used by the ffmpeg command, as `name` or `name=sample`.
+    pub variables: Vec<String>,
+    /// Whether the ffmpeg command is first tried against a small generated clip.
+    pub dry_run: bool,
+    /// Encoders the workers must support, in addition to the ones used by the ffmpeg command.
+    pub required_encoders: Vec<String>,
+    /// Tags the workers must have to process this job.
+    pub required_tags: Vec<String>,
+    /// Minimum memory of the workers, in bytes.
+    pub min_memory: Option<i64>,
+    /// Jobs with a higher priority are processed first.
+    pub priority: i32,
+    pub submitter: Option<String>,
+    /// Deadline of the job (UTC).
+    pub deadline: Option<NaiveDateTime>,
+    /// Webhook URL notified of the state changes of this job, in addition to the global one.
+    pub webhook_url: Option<String>,
+    /// Seconds of the neighbouring fragments encoded before and after every fragment, and
+    /// trimmed when packaging, to avoid quality and bitrate changes at the fragment boundaries.
+    pub overlap: f64,
+    /// Metric of the quality check of the transcoded fragments, against their source.
+    pub quality_metric: Option<QualityMetric>,
+    /// Minimum quality score of the fragments, the ones below it are flagged for review.
+    pub quality_threshold: Option<f64>,
+    /// Whether the fragments below the threshold are failed, instead of flagged.
+    pub fail_below_threshold: bool,
+    /// VMAF score to reach with the lowest quality: the workers search the `{crf}` placeholder
+    /// of every fragment with short probe encodes, between `crf_min` and `crf_max`.
+    pub target_vmaf: Option<f64>,
+    pub crf_min: Option<i32>,
+    pub crf_max: Option<i32>,
+}
 
-    if cmd.ffmpeg_command.is_empty() {
-        bail!("ffmpeg_command cannot be empty");
+impl JobRequest {
+    /// Fill the settings left empty (or false, or zero) by the request with the ones of a
+    /// preset. The ffmpeg commands and renditions of the preset are only used together, when
+    /// the request has none.
+    pub fn with_preset(self, preset: JobRequest) -> JobRequest {
+        let (ffmpeg_command, ffmpeg_passes, renditions) =
+            if self.ffmpeg_command.is_empty() && self.renditions.is_empty() {
+                (
+                    preset.ffmpeg_command,
+                    preset.ffmpeg_passes,
+                    preset.renditions,
+                )
+            } else {
+                (self.ffmpeg_command, self.ffmpeg_passes, self.renditions)
+            };
+        let or_preset = |values: Vec<String>, preset: Vec<String>| {
+            if values.is_empty() {
+                preset
+            } else {
+                values
+            }
+        };
+        JobRequest {
+            media_id: self.media_id,
+            preset: None,
+            ffmpeg_command,
+            ffmpeg_passes,
+            renditions,
+            start: self.start || preset.start,
+            variables: or_preset(self.variables, preset.variables),
+            dry_run: self.dry_run || preset.dry_run,
+            required_encoders: or_preset(self.required_encoders, preset.required_encoders),
+            required_tags: or_preset(self.required_tags, preset.required_tags),
+            min_memory: self.min_memory.or(preset.min_memory),
+            priority: if self.priority != 0 {
+                self.priority
+            } else {
+                preset.priority
+            },
+            submitter: self.submitter.or(preset.submitter),
+            deadline: self.deadline.or(preset.deadline),
+            webhook_url: self.webhook_url.or(preset.webhook_url),
+            overlap: if self.overlap != 0.0 {
+                self.overlap
+            } else {
+                preset.overlap
+            },
+            quality_metric: self.quality_metric.or(preset.quality_metric),
+            quality_threshold: self.quality_threshold.or(preset.quality_threshold),
+            fail_below_threshold: self.fail_below_threshold || preset.fail_below_threshold,
+            target_vmaf: self.target_vmaf.or(preset.target_vmaf),
+            crf_min: self.crf_min.or(preset.crf_min),
+            crf_max: self.crf_max.or(preset.crf_max),
+        }
     }
+}
 
-    let mut job = model::NewTranscodingJob {
-        media_id: media.media_id,
-        ffmpeg_command: cmd.ffmpeg_command,
-        status: JobStatus::Pending,
+/// Search range of the `{crf}` placeholder, the range of the CRF of libx264 and libx265.
+pub const DEFAULT_CRF_RANGE: (i32, i32) = (10, 51);
+
+/// Parse a CRF range given as `min-max`.
+pub fn parse_crf_range(value: &str) -> Result<(i32, i32)> {
+    let Some((min, max)) = value.split_once('-') else {
+        bail!("Invalid CRF range, expected min-max: {}", value);
     };
+    Ok((min.trim().parse()?, max.trim().parse()?))
+}
 
-    if cmd.start {
-        job.status = JobStatus::Queued;
-    }
+/// A rendition of a new transcoding job.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RenditionRequest {
+    /// Name of the rendition, e.g. `720p`, used for its output directory and playlist.
+    pub name: String,
+    /// The ffmpeg command to use for this rendition
+    pub ffmpeg_command: String,
+    /// ffmpeg commands run before `ffmpeg_command`, in order.
+    #[serde(default)]
+    pub ffmpeg_passes: Vec<String>,
+}
 
-    let job_id = diesel::insert_into(schema::transcoding_job::table)
-        .values(&job)
-        .returning(schema::transcoding_job::transcoding_job_id)
-        .get_result::<Uuid>(db)?;
+/// Parse a rendition given as `name=ffmpeg command`.
+pub fn parse_rendition(value: &str) -> Result<RenditionRequest> {
+    let Some((name, ffmpeg_command)) = value.split_once('=') else {
+        bail!(
+            "Invalid rendition, expected name=<ffmpeg command>: {}",
+            value
+        );
+    };
+    Ok(RenditionRequest {
+        name: name.trim().to_string(),
+        ffmpeg_command: ffmpeg_command.trim().to_string(),
+        ffmpeg_passes: Vec::new(),
+    })
+}
 
-    let fragments = schema::fragment::table
-        .filter(schema::fragment::media_id.eq(media_id))
-        .load::<model::Fragment>(db)?;
+/// A transcoding job added to the database.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NewJob {
+    pub transcoding_job_id: Uuid,
+    pub status: JobStatus,
+    pub fragments: usize,
+    /// Whether the ffmpeg commands succeeded against a test clip, before adding the job.
+    pub dry_run: bool,
+    pub warnings: Vec<String>,
+}
 
-    let mut fragment_jobs = Vec::new();
-    for fragment in fragments.iter() {
-        let mut job = model::NewTranscodingFragmentJob {
-            transcoding_job_id: job_id,
-            fragment_id: fragment.fragment_id,
-            status: FragmentJobStatus::Pending,
+pub async fn new_transcode(
+    db: &mut DbConnection,
+    cmd: TranscodeCommand,
+    ffmpeg_bin: &str,
+) -> Result<()> {
+    // The passes of the renditions are given separately, as `name=<ffmpeg command>`.
+    let mut renditions = cmd.renditions;
+    for pass in cmd.rendition_passes {
+        let Some(rendition) = renditions.iter_mut().find(|r| r.name == pass.name) else {
+            bail!("Pass of an unknown rendition: {}", pass.name);
         };
-        if cmd.start {
-            job.status = FragmentJobStatus::Queued;
-        }
-        fragment_jobs.push(job);
+        rendition.ffmpeg_passes.push(pass.ffmpeg_command);
+    }
+    let request = JobRequest {
+        media_id: Uuid::parse_str(&cmd.media_id)?,
+        preset: cmd.preset,
+        ffmpeg_command: cmd.ffmpeg_command.unwrap_or_default(),
+        ffmpeg_passes: cmd.ffmpeg_passes,
+        renditions,
+        start: cmd.start,
+        variables: cmd.variables,
+        dry_run: cmd.dry_run,
+        required_encoders: cmd.required_encoders,
+        required_tags: cmd.required_tags,
+        min_memory: cmd.min_memory,
+        priority: cmd.priority,
+        submitter: cmd.submitter,
+        deadline: cmd.deadline,
+        webhook_url: cmd.webhook_url,
+        overlap: cmd.overlap,
+        quality_metric: cmd.quality_metric,
+        quality_threshold: cmd.quality_threshold,
+        fail_below_threshold: cmd.fail_below_threshold,
+        target_vmaf: cmd.target_vmaf,
+        crf_min: cmd.crf_range.map(|(min, _)| min),
+        crf_max: cmd.crf_range.map(|(_, max)| max),
+    };
+    if request.dry_run {
+        println!("Trying the ffmpeg command against a test clip...");
     }
-    diesel::insert_into(schema::transcoding_fragment_job::table)
-        .values(&fragment_jobs)
-        .execute(db)?;
+    let job = create_job(db, request, ffmpeg_bin, None).await?;
 
-    if cmd.start {
+    if job.dry_run {
+        println!("Dry-run succeeded.");
+    }
+    for warning in &job.warnings {
+        warn!(transcoding_job_id = %job.transcoding_job_id, "{}", warning);
+    }
+    if job.status == JobStatus::Queued {
         println!(
             "Transcoding job added and started: {} ({} fragments)",
-            job_id,
-            fragments.len()
+            job.transcoding_job_id, job.fragments
         );
     } else {
         println!(
             "Transcoding job added: {} ({} fragments)",
-            job_id,
-            fragments.len()
+            job.transcoding_job_id, job.fragments
         );
     }
 
     Ok(())
 }
+
+/// Validate and add a transcoding job, with a fragment job for every fragment of the media.
+///
+/// The dry-run runs the commands of the request on this host: the requests of untrusted
+/// submitters must give a policy to check them against.
+pub async fn create_job(
+    db: &mut DbConnection,
+    request: JobRequest,
+    ffmpeg_bin: &str,
+    policy: Option<&Policy>,
+) -> Result<NewJob> {
+    let request = match request.preset.clone() {
+        Some(name) => request.with_preset(preset::get_preset(db, &name)?.settings),
+        None => request,
+    };
+    let media_id = request.media_id;
+    let media = schema::media::table
+        .filter(schema::media::media_id.eq(media_id))
+        .first::<model::Media>(db)
+        .with_context(|| format!("Media not found: {}", media_id))?;
+
+    // Parse the declared worker variables, with their optional sample value.
+    let mut variables = Vec::new();
+    let mut samples = HashMap::new();
+    for var in &request.variables {
+        let (name, sample) = match var.split_once('=') {
+            Some((name, sample)) => (name, sample),
+            None => (var.as_str(), ""),
+        };
+        if name.is_empty() {
+            bail!("Invalid worker variable: {}", var);
+        }
+        variables.push(name.to_lowercase());
+        samples.insert(name.to_lowercase(), sample.to_string());
+    }
+
+    if !request.overlap.is_finite() || request.overlap < 0.0 {
+        bail!("Invalid overlap: {}", request.overlap);
+    }
+    if request.overlap > 0.0 {
+        // The overlap is cut from the neighbouring fragments, it cannot be longer than them.
+        let shortest = schema::fragment::table
+            .filter(schema::fragment::media_id.eq(media_id))
+            .select(schema::fragment::duration)
+            .load::<Option<f64>>(db)?
+            .into_iter()
+            .flatten()
+            .min_by(f64::total_cmp);
+        if let Some(shortest) = shortest.filter(|shortest| request.overlap > *shortest) {
+            bail!(
+                "Invalid overlap, longer than the shortest fragment ({:.3} seconds): {}",
+                shortest,
+                request.overlap
+            );
+        }
+    }
+    if request.quality_metric.is_none() && request.quality_threshold.is_some() {
+        bail!("A quality threshold requires a quality metric");
+    }
+    if request.quality_threshold.is_none() && request.fail_below_threshold {
+        bail!("Failing the fragments below the threshold requires a quality threshold");
+    }
+    let crf_min = request.crf_min.unwrap_or(DEFAULT_CRF_RANGE.0);
+    let crf_max = request.crf_max.unwrap_or(DEFAULT_CRF_RANGE.1);
+    if let Some(target_vmaf) = request.target_vmaf {
+        if !(0.0..=100.0).contains(&target_vmaf) {
+            bail!("Invalid target VMAF (between 0 and 100): {}", target_vmaf);
+        }
+        if crf_min < 0 || crf_min > crf_max {
+            bail!("Invalid CRF range: {}-{}", crf_min, crf_max);
+        }
+        // A fragment job stores a single CRF.
+        if !request.renditions.is_empty() {
+            bail!("A target quality is not supported by jobs with renditions");
+        }
+        samples.insert("crf".into(), crf_max.to_string());
+    }
+
+    // Jobs with renditions run one ffmpeg command per rendition, instead of the job command.
+    let commands = if request.renditions.is_empty() {
+        vec![(
+            request.ffmpeg_passes.as_slice(),
+            request.ffmpeg_command.as_str(),
+        )]
+    } else {
+        if !request.ffmpeg_command.trim().is_empty() || !request.ffmpeg_passes.is_empty() {
+            bail!("A transcoding job has either an ffmpeg command or renditions, not both");
+        }
+        for (i, rendition) in request.renditions.iter().enumerate() {
+            if !is_valid_rendition_name(&rendition.name) {
+                bail!(
+                    "Invalid rendition name (letters, digits, '-' and '_' only): {}",
+                    rendition.name
+                );
+            }
+            if request.renditions[..i]
+                .iter()
+                .any(|other| other.name == rendition.name)
+            {
+                bail!("Duplicate rendition: {}", rendition.name);
+            }
+        }
+        request
+            .renditions
+            .iter()
+            .map(|rendition| {
+                (
+                    rendition.ffmpeg_passes.as_slice(),
+                    rendition.ffmpeg_command.as_str(),
+                )
+            })
+            .collect()
+    };
+
+    for (passes, command) in &commands {
+        for pass in *passes {
+            template::validate_pass(pass, &variables)?;
+        }
+        template::validate(command, &variables)?;
+        let mut uses_crf = false;
+        for command in passes.iter().map(String::as_str).chain([*command]) {
+            uses_crf |= template::has_key(&template::parse(command)?, "crf");
+        }
+        match (request.target_vmaf, uses_crf) {
+            (Some(_), false) => {
+                bail!("ffmpeg command template must use the {{crf}} placeholder with a target quality")
+            }
+            (None, true) => bail!("The {{crf}} placeholder requires a target quality"),
+            _ => {}
+        }
+    }
+    if request.dry_run {
+        for (passes, command) in &commands {
+            template::dry_run(ffmpeg_bin, passes, command, &samples, policy).await?;
+        }
+    }
+
+    // Workers must support the encoders of the commands, and provide their variables.
+    let mut required_encoders = Vec::new();
+    let mut required_variables = Vec::new();
+    let all_commands = commands
+        .iter()
+        .flat_map(|(passes, command)| passes.iter().map(String::as_str).chain([*command]));
+    for command in all_commands {
+        for encoder in template::encoders(command) {
+            if !required_encoders.contains(&encoder) {
+                required_encoders.push(encoder);
+            }
+        }
+        for variable in template::worker_variables(&template::parse(command)?) {
+            if !required_variables.contains(&variable) {
+                required_variables.push(variable);
+            }
+        }
+    }
+    for encoder in request.required_encoders {
+        if !required_encoders.contains(&encoder) {
+            required_encoders.push(encoder);
+        }
+    }
+    required_variables.sort();
+
+    let job = model::NewTranscodingJob {
+        media_id: media.media_id,
+        ffmpeg_command: request.ffmpeg_command,
+        status: if request.start {
+            JobStatus::Queued
+        } else {
+            JobStatus::Pending
+        },
+        required_encoders,
+        required_variables,
+        required_tags: request.required_tags,
+        min_memory: request.min_memory,
+        priority: request.priority,
+        submitter: request.submitter,
+        deadline: request.deadline,
+        webhook_url: request.webhook_url,
+        overlap: request.overlap,
+        ffmpeg_passes: request.ffmpeg_passes,
+        quality_metric: request.quality_metric,
+        quality_threshold: request.quality_threshold,
+        fail_below_threshold: request.fail_below_threshold,
+        target_vmaf: request.target_vmaf,
+        crf_min,
+        crf_max,
+    };
+
+    let mut warnings = Vec::new();
+    let online_workers = schema::worker::table
+        .select(model::Worker::as_select())
+        .load(db)?
+        .into_iter()
+        .filter(worker::is_online)
+        .collect::<Vec<_>>();
+    if !online_workers.is_empty()
+        && !online_workers
+            .iter()
+            .any(|w| worker::can_run(w, &job.requirements()))
+    {
+        warnings.push("None of the online workers meets the requirements of this job".into());
+    }
+
+    if let Some(url) = &job.webhook_url {
+        reqwest::Url::parse(url).with_context(|| format!("Invalid webhook URL: {}", url))?;
+    }
+
+    // The job is only visible with all its fragment jobs and renditions.
+    let (job_id, fragments) = db.transaction(|db| {
+        let job_id = diesel::insert_into(schema::transcoding_job::table)
+            .values(&job)
+            .returning(schema::transcoding_job::transcoding_job_id)
+            .get_result::<Uuid>(db)?;
+
+        let fragments = schema::fragment::table
+            .filter(schema::fragment::media_id.eq(media_id))
+            .select(schema::fragment::fragment_id)
+            .load::<Uuid>(db)?;
+        // One insert per row, multi-row inserts are not supported by every backend.
+        for fragment_id in &fragments {
+            diesel::insert_into(schema::transcoding_fragment_job::table)
+                .values(model::NewTranscodingFragmentJob {
+                    transcoding_job_id: job_id,
+                    fragment_id: *fragment_id,
+                    status: if request.start {
+                        FragmentJobStatus::Queued
+                    } else {
+                        FragmentJobStatus::Pending
+                    },
+                })
+                .execute(db)?;
+        }
+        for (position, rendition) in request.renditions.into_iter().enumerate() {
+            diesel::insert_into(schema::transcoding_rendition::table)
+                .values(model::NewTranscodingRendition {
+                    transcoding_job_id: job_id,
+                    name: rendition.name,
+                    ffmpeg_command: rendition.ffmpeg_command,
+                    position: position as i32,
+                    ffmpeg_passes: rendition.ffmpeg_passes,
+                })
+                .execute(db)?;
+        }
+        if request.start {
+            webhook::record(db, webhook::Event::JobQueued, job_id, None)?;
+        }
+        diesel::QueryResult::Ok((job_id, fragments.len()))
+    })?;
+
+    Ok(NewJob {
+        transcoding_job_id: job_id,
+        status: job.status,
+        fragments,
+        dry_run: request.dry_run,
+        warnings,
+    })
+}
+
+/// Rendition names are used as directory names and HLS variant names.
+fn is_valid_rendition_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_the_empty_settings_with_the_preset() {
+        let preset = JobRequest {
+            ffmpeg_command: "-i {input} -c:v libx264 {output}".into(),
+            ffmpeg_passes: vec!["-i {input} -pass 1 -f null -".into()],
+            required_tags: vec!["gpu".into()],
+            priority: 5,
+            overlap: 2.0,
+            quality_metric: Some(QualityMetric::Vmaf),
+            ..Default::default()
+        };
+        let request = JobRequest {
+            media_id: Uuid::new_v4(),
+            preset: Some("h264".into()),
+            priority: 10,
+            ..Default::default()
+        };
+        let merged = request.clone().with_preset(preset.clone());
+        assert_eq!(merged.media_id, request.media_id);
+        assert_eq!(merged.preset, None);
+        assert_eq!(merged.ffmpeg_command, preset.ffmpeg_command);
+        assert_eq!(merged.ffmpeg_passes, preset.ffmpeg_passes);
+        assert_eq!(merged.required_tags, preset.required_tags);
+        assert_eq!(merged.priority, 10);
+        assert_eq!(merged.overlap, 2.0);
+        assert_eq!(merged.quality_metric, Some(QualityMetric::Vmaf));
+
+        // The commands of the preset are not mixed with the ones of the request.
+        let request = JobRequest {
+            renditions: vec![parse_rendition("720p=-i {input} -s 1280x720 {output}").unwrap()],
+            ..Default::default()
+        };
+        let merged = request.clone().with_preset(preset);
+        assert_eq!(merged.ffmpeg_command, "");
+        assert!(merged.ffmpeg_passes.is_empty());
+        assert_eq!(merged.renditions, request.renditions);
+    }
+}
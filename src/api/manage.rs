@@ -0,0 +1,487 @@
+use axum::extract::{FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::routing::{get, post, put};
+use axum::{async_trait, Json, Router};
+use serde::{Deserialize, Serialize};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use super::{block_on, ApiError, ApiResult, AppState};
+use crate::add_transcode::{self, JobRequest, NewJob, RenditionRequest};
+use crate::job::{
+    self, FragmentQuality, FragmentReport, JobReport, JobSummary, LogEntry, QualityReport,
+};
+use crate::media::{self, FragmentSummary, MediaDetails, MediaSummary};
+use crate::preset::{self, PresetEntry};
+use crate::transport::ErrorResponse;
+use crate::webhook::{self, DeliveryEntry};
+use crate::{model, worker};
+use model::{JobStatus, QualityMetric, TranscodingRendition};
+
+/// Routes of the management API, used by the dashboard and the ingestion scripts.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/media", get(list_media))
+        .route("/media/:id", get(get_media))
+        .route("/jobs", get(list_jobs).post(create_job))
+        .route("/jobs/:id", get(get_job))
+        .route("/jobs/:id/logs", get(get_job_logs))
+        .route("/jobs/:id/quality", get(get_job_quality))
+        .route("/jobs/:id/webhooks", get(get_job_webhooks))
+        .route("/jobs/:id/priority", put(set_job_priority))
+        .route("/jobs/:id/queue", post(queue_job))
+        .route("/jobs/:id/cancel", post(cancel_job))
+        .route("/jobs/:id/retry", post(retry_job))
+        .route("/presets", get(list_presets))
+        .route(
+            "/presets/:name",
+            get(get_preset).put(save_preset).delete(delete_preset),
+        )
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "transcodeck management API"),
+    paths(
+        list_media,
+        get_media,
+        list_jobs,
+        create_job,
+        get_job,
+        get_job_logs,
+        get_job_quality,
+        get_job_webhooks,
+        set_job_priority,
+        queue_job,
+        cancel_job,
+        retry_job,
+        list_presets,
+        get_preset,
+        save_preset,
+        delete_preset
+    ),
+    components(schemas(
+        MediaSummary,
+        MediaDetails,
+        FragmentSummary,
+        JobSummary,
+        JobReport,
+        FragmentReport,
+        LogEntry,
+        QualityReport,
+        FragmentQuality,
+        DeliveryEntry,
+        JobRequest,
+        RenditionRequest,
+        NewJob,
+        PresetEntry,
+        PriorityRequest,
+        JobUpdate,
+        ErrorResponse,
+        model::TranscodingJob,
+        TranscodingRendition,
+        JobStatus,
+        QualityMetric,
+        model::FragmentJobStatus,
+        model::WebhookDelivery,
+        model::WebhookDeliveryStatus
+    )),
+    modifiers(&BearerAuth),
+    security(("admin_token" = []))
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+/// A request authenticated by the `Authorization: Bearer <admin token>` header.
+pub struct Admin;
+
+#[async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> ApiResult<Self> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Missing admin token"))?;
+        // Compare the hashes, so that the comparison time does not depend on the token.
+        match &state.admin_token {
+            Some(admin_token)
+                if worker::hash_token(token.trim()) == worker::hash_token(admin_token) =>
+            {
+                Ok(Admin)
+            }
+            _ => Err(ApiError::new(
+                StatusCode::UNAUTHORIZED,
+                "Invalid admin token",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobFilter {
+    /// Only list the jobs with this status.
+    status: Option<JobStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct PriorityRequest {
+    /// Jobs with a higher priority are processed first.
+    priority: i32,
+}
+
+/// Result of a change of the status of a transcoding job.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobUpdate {
+    transcoding_job_id: Uuid,
+    /// Number of fragment jobs changed.
+    fragments: usize,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/media",
+    tag = "media",
+    responses((status = 200, description = "Media, newest first", body = [MediaSummary]))
+)]
+async fn list_media(State(state): State<AppState>, _: Admin) -> ApiResult<Json<Vec<MediaSummary>>> {
+    let media = state.with_db(|db| Ok(media::list_media(db)?)).await?;
+    Ok(Json(media))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/media/{id}",
+    tag = "media",
+    params(("id" = Uuid, Path, description = "The media ID")),
+    responses(
+        (status = 200, description = "Media, with its fragments and transcoding jobs", body = MediaDetails),
+        (status = 404, description = "Media not found", body = ErrorResponse)
+    )
+)]
+async fn get_media(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<MediaDetails>> {
+    let media = state
+        .with_db(move |db| Ok(media::media_details(db, id)?))
+        .await?;
+    Ok(Json(media))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs",
+    tag = "jobs",
+    params(JobFilter),
+    responses((status = 200, description = "Transcoding jobs, newest first", body = [JobSummary]))
+)]
+async fn list_jobs(
+    State(state): State<AppState>,
+    _: Admin,
+    Query(filter): Query<JobFilter>,
+) -> ApiResult<Json<Vec<JobSummary>>> {
+    let jobs = state
+        .with_db(move |db| Ok(job::list_jobs(db, filter.status)?))
+        .await?;
+    Ok(Json(jobs))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/jobs",
+    tag = "jobs",
+    request_body = JobRequest,
+    responses(
+        (status = 201, description = "Transcoding job added", body = NewJob),
+        (status = 400, description = "Invalid ffmpeg command", body = ErrorResponse),
+        (status = 404, description = "Media not found", body = ErrorResponse)
+    )
+)]
+async fn create_job(
+    State(state): State<AppState>,
+    _: Admin,
+    Json(request): Json<JobRequest>,
+) -> ApiResult<(StatusCode, Json<NewJob>)> {
+    let ffmpeg_bin = state.ffmpeg_bin.clone();
+    let policy = state.policy.clone();
+    let job = state
+        .with_db(move |db| {
+            Ok(block_on(add_transcode::create_job(
+                db,
+                request,
+                &ffmpeg_bin,
+                Some(&policy),
+            ))?)
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(job)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses(
+        (status = 200, description = "Transcoding job and the progress of its fragments", body = JobReport),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn get_job(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<JobReport>> {
+    let report = state
+        .with_db(move |db| Ok(job::job_report(db, id)?))
+        .await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}/logs",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID, or a transcoding fragment job ID")),
+    responses((status = 200, description = "Logs of the failed fragments", body = [LogEntry]))
+)]
+async fn get_job_logs(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Vec<LogEntry>>> {
+    let logs = state.with_db(move |db| Ok(job::job_logs(db, id)?)).await?;
+    Ok(Json(logs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}/quality",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses(
+        (status = 200, description = "Quality scores of the fragments", body = QualityReport),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn get_job_quality(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<QualityReport>> {
+    let report = state
+        .with_db(move |db| Ok(job::quality_report(db, id)?))
+        .await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/jobs/{id}/webhooks",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses((status = 200, description = "Webhook deliveries of the job events, newest first", body = [DeliveryEntry]))
+)]
+async fn get_job_webhooks(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<Vec<DeliveryEntry>>> {
+    let deliveries = state
+        .with_db(move |db| Ok(webhook::deliveries(db, Some(id))?))
+        .await?;
+    Ok(Json(deliveries))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/jobs/{id}/priority",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    request_body = PriorityRequest,
+    responses(
+        (status = 204, description = "Priority changed"),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn set_job_priority(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+    Json(request): Json<PriorityRequest>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| Ok(job::set_priority(db, id, request.priority)?))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{id}/queue",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses(
+        (status = 200, description = "Pending transcoding job queued", body = JobUpdate),
+        (status = 400, description = "Transcoding job is not pending", body = ErrorResponse),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn queue_job(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<JobUpdate>> {
+    let fragments = state.with_db(move |db| Ok(job::queue_job(db, id)?)).await?;
+    Ok(Json(JobUpdate {
+        transcoding_job_id: id,
+        fragments,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{id}/cancel",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses(
+        (status = 200, description = "Transcoding job cancelled, fragment jobs in progress are not interrupted", body = JobUpdate),
+        (status = 400, description = "Transcoding job is already completed or cancelled", body = ErrorResponse),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn cancel_job(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<JobUpdate>> {
+    let fragments = state
+        .with_db(move |db| Ok(job::cancel_job(db, id)?))
+        .await?;
+    Ok(Json(JobUpdate {
+        transcoding_job_id: id,
+        fragments,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/jobs/{id}/retry",
+    tag = "jobs",
+    params(("id" = Uuid, Path, description = "The transcoding job ID")),
+    responses(
+        (status = 200, description = "Failed and cancelled fragments queued again", body = JobUpdate),
+        (status = 400, description = "Transcoding job has no failed or cancelled fragment", body = ErrorResponse),
+        (status = 404, description = "Transcoding job not found", body = ErrorResponse)
+    )
+)]
+async fn retry_job(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<JobUpdate>> {
+    let fragments = state.with_db(move |db| Ok(job::retry_job(db, id)?)).await?;
+    Ok(Json(JobUpdate {
+        transcoding_job_id: id,
+        fragments,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/presets",
+    tag = "presets",
+    responses((status = 200, description = "Presets, by name", body = [PresetEntry]))
+)]
+async fn list_presets(
+    State(state): State<AppState>,
+    _: Admin,
+) -> ApiResult<Json<Vec<PresetEntry>>> {
+    let presets = state.with_db(|db| Ok(preset::list_presets(db)?)).await?;
+    Ok(Json(presets))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/presets/{name}",
+    tag = "presets",
+    params(("name" = String, Path, description = "The preset name")),
+    responses(
+        (status = 200, description = "Preset and its settings", body = PresetEntry),
+        (status = 404, description = "Preset not found", body = ErrorResponse)
+    )
+)]
+async fn get_preset(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(name): Path<String>,
+) -> ApiResult<Json<PresetEntry>> {
+    let preset = state
+        .with_db(move |db| Ok(preset::get_preset(db, &name)?))
+        .await?;
+    Ok(Json(preset))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/presets/{name}",
+    tag = "presets",
+    params(("name" = String, Path, description = "The preset name")),
+    request_body(content = JobRequest, description = "Settings of the jobs using the preset, without media ID"),
+    responses(
+        (status = 200, description = "Preset added or replaced", body = PresetEntry),
+        (status = 400, description = "Invalid preset", body = ErrorResponse)
+    )
+)]
+async fn save_preset(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(name): Path<String>,
+    Json(settings): Json<JobRequest>,
+) -> ApiResult<Json<PresetEntry>> {
+    let preset = state
+        .with_db(move |db| Ok(preset::save_preset(db, &name, settings)?))
+        .await?;
+    Ok(Json(preset))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/presets/{name}",
+    tag = "presets",
+    params(("name" = String, Path, description = "The preset name")),
+    responses(
+        (status = 204, description = "Preset deleted"),
+        (status = 404, description = "Preset not found", body = ErrorResponse)
+    )
+)]
+async fn delete_preset(
+    State(state): State<AppState>,
+    _: Admin,
+    Path(name): Path<String>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| Ok(preset::delete_preset(db, &name)?))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
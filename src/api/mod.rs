@@ -0,0 +1,147 @@
+use anyhow::Result;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::db::{self, DbConnection, DbPool};
+use crate::policy::Policy;
+use crate::transport::ErrorResponse;
+use crate::{migrate, webhook, ServeCommand};
+
+pub mod manage;
+pub mod worker;
+
+/// State shared by the handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: DbPool,
+    pub admin_token: Option<Arc<str>>,
+    /// FFmpeg bin used for the dry-run of new transcoding jobs.
+    pub ffmpeg_bin: Arc<str>,
+    /// Allow-list of the dry-runs, the commands of the API clients run on this host.
+    pub policy: Arc<Policy>,
+}
+
+impl AppState {
+    /// Run database queries with a connection of the pool, on a thread where blocking is
+    /// allowed, so that the requests do not wait for each other nor block the runtime.
+    pub async fn with_db<T, F>(&self, f: F) -> ApiResult<T>
+    where
+        F: FnOnce(&mut DbConnection) -> ApiResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut db = pool.get().map_err(|err| {
+                error!("No database connection: {}", err);
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Database unavailable")
+            })?;
+            f(&mut db)
+        })
+        .await
+        .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+    }
+}
+
+/// Run a future to completion in [`AppState::with_db`], e.g. the methods of the database
+/// transport or a job creation.
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Handle::current().block_on(future)
+}
+
+pub async fn serve(db_uri: &str, cmd: ServeCommand, ffmpeg_bin: &str) -> Result<()> {
+    let mut db = db::establish(db_uri)?;
+    migrate::check_schema(&mut db)?;
+    let state = AppState {
+        pool: db::pool(db_uri, cmd.db_pool_size)?,
+        admin_token: cmd.admin_token.map(Arc::from),
+        ffmpeg_bin: Arc::from(ffmpeg_bin),
+        policy: Arc::new(Policy::new(
+            &cmd.allow_flags,
+            &cmd.allow_codecs,
+            &cmd.allow_filters,
+        )),
+    };
+    // The deliveries are retried by any running dispatcher, they are not lost on shutdown.
+    tokio::spawn(webhook::Dispatcher::new(&cmd.webhooks)?.run(Arc::new(Mutex::new(db))));
+
+    let mut app = Router::new()
+        .route("/api/openapi.json", get(openapi))
+        .nest("/api/worker", worker::router());
+    if state.admin_token.is_some() {
+        app = app.nest("/api", manage::router());
+    } else {
+        warn!("No admin token, the management API is disabled");
+    }
+    let app = app.with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&cmd.listen).await?;
+    info!(address = %listener.local_addr()?, "Listening");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    info!("Server stopped");
+    Ok(())
+}
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(<manage::ApiDoc as utoipa::OpenApi>::openapi())
+}
+
+/// An error returned by the API, as a JSON `{"error": "..."}` body.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// Database errors are server errors, except for missing rows, other errors are caused
+/// by the request (e.g. an invalid ffmpeg command).
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let status = match err.downcast_ref::<diesel::result::Error>() {
+            Some(diesel::result::Error::NotFound) => StatusCode::NOT_FOUND,
+            Some(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            None => StatusCode::BAD_REQUEST,
+        };
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("Request failed: {:#}", err);
+        }
+        ApiError::new(status, err.to_string())
+    }
+}
+
+impl From<diesel::result::Error> for ApiError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => ApiError::new(StatusCode::NOT_FOUND, "Not found"),
+            err => anyhow::Error::from(err).into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse {
+            error: self.message,
+        };
+        (self.status, Json(body)).into_response()
+    }
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
@@ -0,0 +1,227 @@
+use axum::extract::{FromRequestParts, Path, State};
+use axum::http::request::Parts;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{async_trait, Json, Router};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::{block_on, ApiError, ApiResult, AppState};
+use crate::db::DbConnection;
+use crate::transport::{
+    ClaimRequest, DbTransport, DurationRequest, Failure, Heartbeat, HeartbeatResponse, QueueDepth,
+    RegisterResponse, Transport,
+};
+use crate::{model, schema, worker};
+use model::FragmentJobStatus;
+
+/// Routes of the worker API, used by the daemons started with `--api-url`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/register", post(register))
+        .route("/heartbeat", post(heartbeat))
+        .route("/claim", post(claim))
+        .route("/queue", post(queue))
+        .route("/fragment-jobs/:id/progress", post(progress))
+        .route("/fragment-jobs/:id/duration", post(duration))
+        .route("/fragment-jobs/:id/complete", post(complete))
+        .route("/fragment-jobs/:id/fail", post(fail))
+        .route("/fragment-jobs/:id/requeue", post(requeue))
+}
+
+/// The worker authenticated by the `Authorization: Bearer <token>` header of a request.
+pub struct AuthenticatedWorker {
+    pub worker_id: Uuid,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthenticatedWorker {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> ApiResult<Self> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Missing worker token"))?;
+        let token_hash = worker::hash_token(token.trim());
+        let token = state
+            .with_db(move |db| {
+                Ok(schema::worker_token::table
+                    .filter(schema::worker_token::token_hash.eq(token_hash))
+                    .select(model::WorkerToken::as_select())
+                    .first(db)
+                    .optional()?)
+            })
+            .await?
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Invalid worker token"))?;
+        Ok(AuthenticatedWorker {
+            worker_id: token.worker_id,
+        })
+    }
+}
+
+/// Check that a fragment job is in progress on the authenticated worker.
+fn check_assigned(
+    db: &mut DbConnection,
+    worker: &AuthenticatedWorker,
+    transcoding_fragment_job_id: Uuid,
+) -> ApiResult<()> {
+    let (status, worker_id) = schema::transcoding_fragment_job::table
+        .filter(
+            schema::transcoding_fragment_job::transcoding_fragment_job_id
+                .eq(transcoding_fragment_job_id),
+        )
+        .select((
+            schema::transcoding_fragment_job::status,
+            schema::transcoding_fragment_job::worker_id,
+        ))
+        .first::<(FragmentJobStatus, Option<Uuid>)>(db)?;
+    if worker_id != Some(worker.worker_id) || status != FragmentJobStatus::InProgress {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Fragment job is not in progress on this worker",
+        ));
+    }
+    Ok(())
+}
+
+async fn register(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Json(mut registration): Json<model::NewWorker>,
+) -> ApiResult<Json<RegisterResponse>> {
+    // The worker ID is bound to the token, not chosen by the worker.
+    registration.worker_id = worker.worker_id;
+    let worker_id = state
+        .with_db(move |db| {
+            Ok(block_on(
+                DbTransport::unchecked(db).register(&registration),
+            )?)
+        })
+        .await?;
+    Ok(Json(RegisterResponse { worker_id }))
+}
+
+async fn heartbeat(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Json(heartbeat): Json<Heartbeat>,
+) -> ApiResult<Json<HeartbeatResponse>> {
+    let draining = state
+        .with_db(move |db| {
+            Ok(block_on(
+                DbTransport::unchecked(db).heartbeat(worker.worker_id, &heartbeat),
+            )?)
+        })
+        .await?;
+    Ok(Json(HeartbeatResponse { draining }))
+}
+
+async fn claim(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Json(request): Json<ClaimRequest>,
+) -> ApiResult<Response> {
+    let assignment = state
+        .with_db(move |db| {
+            Ok(block_on(
+                DbTransport::unchecked(db).claim(worker.worker_id, request.schedule),
+            )?)
+        })
+        .await?;
+    Ok(match assignment {
+        Some(assignment) => Json(assignment).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
+}
+
+async fn queue(
+    State(state): State<AppState>,
+    _: AuthenticatedWorker,
+) -> ApiResult<Json<Vec<QueueDepth>>> {
+    let depths = state
+        .with_db(|db| Ok(block_on(DbTransport::unchecked(db).queue_depth())?))
+        .await?;
+    Ok(Json(depths))
+}
+
+async fn progress(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Path(id): Path<Uuid>,
+    Json(progress): Json<model::FragmentJobProgress>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| {
+            check_assigned(db, &worker, id)?;
+            Ok(block_on(
+                DbTransport::unchecked(db).progress(id, &progress),
+            )?)
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn duration(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Path(id): Path<Uuid>,
+    Json(request): Json<DurationRequest>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| {
+            check_assigned(db, &worker, id)?;
+            Ok(block_on(
+                DbTransport::unchecked(db).duration(id, request.duration),
+            )?)
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn complete(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Path(id): Path<Uuid>,
+    Json(stats): Json<model::FragmentJobStats>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| {
+            check_assigned(db, &worker, id)?;
+            Ok(block_on(DbTransport::unchecked(db).complete(id, &stats))?)
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn fail(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Path(id): Path<Uuid>,
+    Json(failure): Json<Failure>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| {
+            check_assigned(db, &worker, id)?;
+            Ok(block_on(DbTransport::unchecked(db).fail(id, &failure))?)
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn requeue(
+    State(state): State<AppState>,
+    worker: AuthenticatedWorker,
+    Path(id): Path<Uuid>,
+) -> ApiResult<StatusCode> {
+    state
+        .with_db(move |db| {
+            check_assigned(db, &worker, id)?;
+            Ok(block_on(DbTransport::unchecked(db).requeue(id))?)
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
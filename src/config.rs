@@ -0,0 +1,273 @@
+//! Configuration file of the CLI, the daemon and the API server.
+//!
+//! Every setting is taken from, in order of precedence: its command-line flag, its
+//! environment variable, the configuration file, then its built-in default. The file covers
+//! the database, the ffmpeg binaries, the daemon (output directory, worker API, tags,
+//! scheduling, concurrency, identity files, sandbox and template variables), the API server,
+//! the webhooks, and the storage the packaged outputs are uploaded to.
+
+use anyhow::{Context, Result};
+use clap::Command;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// The configuration file, `None` settings are not set by the file.
+///
+/// ```toml
+/// db_uri = "postgres://transcodeck@localhost/transcodeck"
+/// ffmpeg_bin = "/usr/local/bin/ffmpeg"
+///
+/// [daemon]
+/// output_dir = "/srv/transcodes"
+/// tags = ["gpu"]
+/// concurrency = 2
+/// identity_files = ["/etc/transcodeck/worker.key"]
+///
+/// [daemon.template]
+/// lp = "2"
+///
+/// [storage]
+/// url = "https://cdn.example.com/media"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// `--db-uri`, `DATABASE_URL`
+    pub db_uri: Option<String>,
+    /// `--ffmpeg-bin`, `FFMPEG_BIN`
+    pub ffmpeg_bin: Option<String>,
+    /// `--ffprobe-bin`, `FFPROBE_BIN`
+    pub ffprobe_bin: Option<String>,
+    /// `--log-format`, `TRANSCODECK_LOG_FORMAT`
+    pub log_format: Option<String>,
+    pub daemon: DaemonConfig,
+    pub serve: ServeConfig,
+    pub webhooks: WebhookConfig,
+    pub storage: StorageConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub output_dir: Option<PathBuf>,
+    pub worker_id: Option<Uuid>,
+    pub api_url: Option<String>,
+    pub api_token: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub concurrency: Option<u32>,
+    pub identity_files: Option<Vec<PathBuf>>,
+    pub schedule: Option<String>,
+    pub shutdown: Option<String>,
+    pub metrics_listen: Option<String>,
+    pub sandbox: Option<bool>,
+    /// The allow-lists are also used by `serve`, for the dry-runs of the management API.
+    pub allow_flags: Option<Vec<String>>,
+    pub allow_codecs: Option<Vec<String>>,
+    pub allow_filters: Option<Vec<String>>,
+    /// Template variables of the ffmpeg commands, like the `TRANSCODECK_TEMPLATE_*` variables.
+    pub template: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServeConfig {
+    pub listen: Option<String>,
+    pub admin_token: Option<String>,
+    pub db_pool_size: Option<u32>,
+}
+
+/// Used by `package`, to upload the packaged outputs.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// `--storage`, `TRANSCODECK_STORAGE`
+    pub url: Option<String>,
+    /// `--storage-token`, `TRANSCODECK_STORAGE_TOKEN`
+    pub token: Option<String>,
+}
+
+/// Used by `serve` and `webhook dispatch`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+}
+
+impl Config {
+    /// Load the file given by `--config` or `TRANSCODECK_CONFIG`, or else
+    /// `$XDG_CONFIG_HOME/transcodeck/config.toml` if it exists.
+    ///
+    /// The arguments are not parsed yet: the settings of the file are their defaults.
+    pub fn load() -> Result<Self> {
+        let (path, required) = match config_arg() {
+            Some(path) => (path, true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default())
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to read config file {}", path.display()))
+            }
+        };
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Set the settings of the file as the default values of their arguments, so that clap
+    /// gives precedence to the command-line flags, then to the environment variables.
+    pub fn command(&self, command: Command) -> Command {
+        let daemon = &self.daemon;
+        let webhooks = [
+            ("webhook_url", self.webhooks.url.clone().map(one)),
+            ("webhook_secret", self.webhooks.secret.clone().map(one)),
+        ];
+        let command = with_defaults(
+            command,
+            &[
+                ("db_uri", self.db_uri.clone().map(one)),
+                ("ffmpeg_bin", self.ffmpeg_bin.clone().map(one)),
+                ("ffprobe_bin", self.ffprobe_bin.clone().map(one)),
+                ("log_format", self.log_format.clone().map(one)),
+            ],
+        );
+        command
+            .mut_subcommand("daemon", |command| {
+                with_defaults(
+                    command,
+                    &[
+                        (
+                            "output_dir",
+                            daemon
+                                .output_dir
+                                .as_ref()
+                                .map(|dir| one(dir.to_string_lossy().to_string())),
+                        ),
+                        ("worker_id", daemon.worker_id.map(|id| one(id.to_string()))),
+                        ("api_url", daemon.api_url.clone().map(one)),
+                        ("api_token", daemon.api_token.clone().map(one)),
+                        ("tags", daemon.tags.clone()),
+                        (
+                            "concurrency",
+                            daemon.concurrency.map(|n| one(n.to_string())),
+                        ),
+                        (
+                            "identity_files",
+                            daemon.identity_files.as_ref().map(|paths| {
+                                paths
+                                    .iter()
+                                    .map(|path| path.to_string_lossy().to_string())
+                                    .collect()
+                            }),
+                        ),
+                        ("schedule", daemon.schedule.clone().map(one)),
+                        ("shutdown", daemon.shutdown.clone().map(one)),
+                        ("metrics_listen", daemon.metrics_listen.clone().map(one)),
+                        (
+                            "sandbox",
+                            daemon.sandbox.map(|sandbox| one(sandbox.to_string())),
+                        ),
+                        ("allow_flags", daemon.allow_flags.clone()),
+                        ("allow_codecs", daemon.allow_codecs.clone()),
+                        ("allow_filters", daemon.allow_filters.clone()),
+                    ],
+                )
+            })
+            .mut_subcommand("package", |command| {
+                with_defaults(
+                    command,
+                    &[
+                        (
+                            "output_dir",
+                            daemon
+                                .output_dir
+                                .as_ref()
+                                .map(|dir| one(dir.to_string_lossy().to_string())),
+                        ),
+                        ("storage", self.storage.url.clone().map(one)),
+                        ("storage_token", self.storage.token.clone().map(one)),
+                    ],
+                )
+            })
+            .mut_subcommand("serve", |command| {
+                let command = with_defaults(
+                    command,
+                    &[
+                        ("listen", self.serve.listen.clone().map(one)),
+                        ("admin_token", self.serve.admin_token.clone().map(one)),
+                        (
+                            "db_pool_size",
+                            self.serve.db_pool_size.map(|size| one(size.to_string())),
+                        ),
+                        ("allow_flags", daemon.allow_flags.clone()),
+                        ("allow_codecs", daemon.allow_codecs.clone()),
+                        ("allow_filters", daemon.allow_filters.clone()),
+                    ],
+                );
+                with_defaults(command, &webhooks)
+            })
+            .mut_subcommand("webhook", |command| {
+                command.mut_subcommand("dispatch", |command| with_defaults(command, &webhooks))
+            })
+    }
+}
+
+fn one(value: String) -> Vec<String> {
+    vec![value]
+}
+
+/// Set the default values of the arguments of `command` with these IDs, the values of the
+/// secrets are not shown by `--help`.
+///
+/// Unlike an environment variable, a default value does not satisfy a required argument, so
+/// the arguments with one are no longer required.
+fn with_defaults(command: Command, defaults: &[(&str, Option<Vec<String>>)]) -> Command {
+    command.mut_args(|arg| {
+        let values = defaults
+            .iter()
+            .find(|(id, _)| arg.get_id() == *id)
+            .and_then(|(_, values)| values.clone());
+        match values {
+            Some(values) => {
+                let hide = arg.is_hide_env_values_set();
+                arg.default_values(values)
+                    .hide_default_value(hide)
+                    .required(false)
+            }
+            None => arg,
+        }
+    })
+}
+
+/// The `--config` argument, read before the other arguments are parsed.
+fn config_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("TRANSCODECK_CONFIG").map(PathBuf::from)
+}
+
+fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("transcodeck").join("config.toml"))
+}
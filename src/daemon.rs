@@ -1,30 +1,114 @@
-use age::secrecy::ExposeSecret;
-use age::{decryptor::RecipientsDecryptor, Decryptor, Identity, Recipient};
-use anyhow::{anyhow, bail, Result};
-use clap::{Parser, Subcommand};
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
+use age::{Decryptor, Identity};
+use anyhow::{anyhow, bail, Context, Result};
 use leon::Template;
-use reqwest::Client;
-use std::iter;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tempdir::TempDir;
-use tokio_util::compat::{
-    FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt, TokioAsyncReadCompatExt,
-    TokioAsyncWriteCompatExt,
-};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::{error, info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
 
-use crate::{model, schema, DaemonCommand};
-use model::{FragmentJobStatus, JobStatus};
+use crate::metrics::{self, Metrics};
+use crate::policy::Policy;
+use crate::progress::FfmpegProgress;
+use crate::shutdown::{Shutdown, State};
+use crate::transport::{Assignment, Failure, Transport};
+use crate::worker::Registration;
+use crate::{model, overlap, package, probe, quality, template, validate, DaemonCommand};
 
-pub async fn daemon(db: &mut PgConnection, cmd: DaemonCommand, ffmpeg_bin: &str) -> Result<()> {
-    println!("Starting daemon...");
+/// Minimum interval between two progress updates of a fragment job.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
-    let mut http = reqwest::Client::new();
-    let mut template_values = std::collections::HashMap::new();
+/// Number of ffmpeg log lines stored when a fragment job fails.
+const LOG_TAIL_LINES: usize = 200;
+
+/// First line of the files encrypted with age (binary format).
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1\n";
+
+/// Duration of the clip encoded by the probes of the target-quality search, in seconds.
+const PROBE_CLIP_DURATION: f64 = 4.0;
+
+/// Whether the daemon keeps claiming fragment jobs after the current one.
+enum Flow {
+    Continue,
+    Stop,
+}
+
+/// How ffmpeg ended.
+enum Transcoded {
+    Exited(ExitStatus, String),
+    Stopped,
+}
+
+/// An ffmpeg run of a fragment job, one per pass of every rendition.
+struct Run<'a> {
+    rendition: Option<&'a str>,
+    /// Number of the pass, from 1 to `passes`, the last one writes the output.
+    pass: usize,
+    passes: usize,
+    args: Vec<String>,
+    output_path: PathBuf,
+}
+
+/// Position of an ffmpeg run among the runs of a fragment job, to report the progress of the
+/// whole fragment job.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    index: usize,
+    count: usize,
+    /// Duration of the fragment, in seconds.
+    duration: Option<f64>,
+}
+
+impl Step {
+    /// Completion of the fragment job, in percent.
+    fn progress(&self, progress: &FfmpegProgress) -> Option<f64> {
+        let percent = progress.percent(self.duration?)?;
+        Some((self.index as f64 * 100.0 + percent) / self.count as f64)
+    }
+}
+
+/// State of a running daemon, shared by the fragment jobs it processes.
+#[derive(Clone)]
+struct Daemon<'a> {
+    cmd: &'a DaemonCommand,
+    ffmpeg_bin: &'a str,
+    ffprobe_bin: &'a str,
+    http: reqwest::Client,
+    policy: Option<Policy>,
+    template_values: HashMap<String, String>,
+    /// Identities of the identity files, for the fragments without encryption key.
+    identities: Vec<age::x25519::Identity>,
+    shutdown: Shutdown,
+    metrics: Arc<Metrics>,
+    /// The queue depth costs a query, it is only refreshed when someone can scrape it.
+    refresh_queue_depth: bool,
+}
+
+/// Run the daemon, processing one fragment job at a time with every transport.
+pub async fn daemon(
+    mut transports: Vec<impl Transport>,
+    cmd: DaemonCommand,
+    ffmpeg_bin: &str,
+    ffprobe_bin: &str,
+) -> Result<()> {
+    info!("Starting daemon");
+
+    let policy = cmd.sandbox.then(|| {
+        info!("Sandbox enabled, ffmpeg arguments will be checked before transcoding");
+        Policy::new(&cmd.allow_flags, &cmd.allow_codecs, &cmd.allow_filters)
+    });
+    let mut template_values = cmd
+        .template
+        .iter()
+        .map(|(key, value)| (key.to_lowercase(), value.clone()))
+        .collect::<HashMap<_, _>>();
     for (key, value) in std::env::vars() {
         let mut key = key.to_lowercase();
         if key.starts_with("transcodeck_template_") {
@@ -33,150 +117,884 @@ pub async fn daemon(db: &mut PgConnection, cmd: DaemonCommand, ffmpeg_bin: &str)
         }
     }
 
-    loop {
-        // Searching for a fragment job that is queued
-        let job = schema::transcoding_fragment_job::table
-            .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
-            .select((
-                schema::transcoding_fragment_job::transcoding_fragment_job_id,
-                schema::transcoding_fragment_job::transcoding_job_id,
-                schema::transcoding_fragment_job::fragment_id,
-            ))
-            .order(schema::transcoding_fragment_job::created_at.asc())
-            .first::<model::JobResume>(db)
-            .optional()?;
-
-        if let Some(model::JobResume {
+    let identities = load_identities(&cmd.identity_files)?;
+
+    let worker_id = cmd.worker_id.unwrap_or_else(Uuid::new_v4);
+    let registration = Registration::register(
+        &mut transports[0],
+        worker_id,
+        ffmpeg_bin,
+        &cmd.output_dir,
+        &template_values,
+        &cmd.tags,
+    )
+    .await?;
+    let span = info_span!("worker", worker_id = %registration.worker_id);
+    span.in_scope(|| info!("Registered as worker"));
+    let shutdown = span.in_scope(|| Shutdown::listen(cmd.shutdown))?;
+
+    let metrics = Arc::new(Metrics::new()?);
+    if let Some(listen) = &cmd.metrics_listen {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        info!(address = %listener.local_addr()?, "Serving metrics on /metrics");
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(metrics, listener).await {
+                error!("Metrics server failed: {:#}", err);
+            }
+        });
+    }
+
+    let daemon = Daemon {
+        cmd: &cmd,
+        ffmpeg_bin,
+        ffprobe_bin,
+        http: reqwest::Client::new(),
+        policy,
+        template_values,
+        identities,
+        shutdown,
+        metrics,
+        refresh_queue_depth: cmd.metrics_listen.is_some(),
+    };
+
+    // Every slot claims and processes its own fragment jobs, with its own transport. The
+    // error of a slot stops the other ones, which return their fragment jobs to the queue.
+    let slots = transports.iter_mut().enumerate().map(|(slot, transport)| {
+        let mut daemon = daemon.clone();
+        let mut registration = registration.clone();
+        async move {
+            let result = daemon.run(transport, &mut registration).await;
+            if result.is_err() {
+                daemon.shutdown.stop();
+            }
+            result
+        }
+        .instrument(info_span!("slot", slot))
+    });
+    async {
+        let results = futures::future::join_all(slots).await;
+        info!("Daemon stopped");
+        results.into_iter().collect()
+    }
+    .instrument(span)
+    .await
+}
+
+/// Load the age identities of the identity files.
+fn load_identities(paths: &[PathBuf]) -> Result<Vec<age::x25519::Identity>> {
+    let mut identities = Vec::new();
+    for path in paths {
+        let file = age::IdentityFile::from_file(path.to_string_lossy().to_string())
+            .with_context(|| format!("Failed to read identity file {}", path.display()))?;
+        for entry in file.into_identities() {
+            let age::IdentityFileEntry::Native(identity) = entry;
+            identities.push(identity);
+        }
+    }
+    Ok(identities)
+}
+
+impl Daemon<'_> {
+    async fn run(
+        &mut self,
+        transport: &mut impl Transport,
+        registration: &mut Registration,
+    ) -> Result<()> {
+        loop {
+            registration.heartbeat(transport, None).await?;
+            if self.refresh_queue_depth {
+                self.metrics
+                    .set_queue_depth(&transport.queue_depth().await?);
+            }
+            if registration.drain_requested && self.shutdown.state() == State::Running {
+                info!("Drain requested, the daemon will stop after the current fragment job");
+                self.shutdown.drain();
+            }
+            if self.shutdown.state() != State::Running {
+                break;
+            }
+
+            // Claiming a fragment job that is queued, and that this worker can process
+            let job = transport
+                .claim(registration.worker_id, self.cmd.schedule)
+                .await?;
+
+            if let Some(assignment) = job {
+                let span = info_span!(
+                    "fragment_job",
+                    transcoding_job_id = %assignment.transcoding_job_id,
+                    transcoding_fragment_job_id = %assignment.transcoding_fragment_job_id,
+                );
+                let flow = self
+                    .process(transport, registration, assignment)
+                    .instrument(span)
+                    .await?;
+                if let Flow::Stop = flow {
+                    break;
+                }
+            } else {
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                    _ = self.shutdown.requested() => {}
+                }
+            }
+        }
+
+        registration.heartbeat(transport, None).await?;
+        Ok(())
+    }
+
+    /// Process a claimed fragment job, any error fails it, instead of stopping the daemon and
+    /// leaving the fragment job in progress. The error and its context are the failure message.
+    ///
+    /// Only the errors of the transport itself, failing the fragment job, stop the daemon.
+    async fn process(
+        &mut self,
+        transport: &mut impl Transport,
+        registration: &mut Registration,
+        assignment: Assignment,
+    ) -> Result<Flow> {
+        let transcoding_fragment_job_id = assignment.transcoding_fragment_job_id;
+        match self
+            .process_assignment(transport, registration, assignment)
+            .await
+        {
+            Ok(flow) => Ok(flow),
+            // The temporary directory of the fragment job is removed when dropped.
+            Err(err) => {
+                let message = format!("{:#}", err);
+                warn!("Transcoding failed: {}", message);
+                fail_fragment_job(
+                    transport,
+                    &self.metrics,
+                    transcoding_fragment_job_id,
+                    &message,
+                    None,
+                    "",
+                )
+                .await?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    /// Download, decrypt and transcode a claimed fragment job.
+    async fn process_assignment(
+        &mut self,
+        transport: &mut impl Transport,
+        registration: &mut Registration,
+        assignment: Assignment,
+    ) -> Result<Flow> {
+        let Assignment {
             transcoding_fragment_job_id,
             transcoding_job_id,
-            fragment_id,
-        }) = job
-        {
-            println!(
-                "Found a candidate fragment job: {}",
-                transcoding_fragment_job_id
+            fragment,
+            ffmpeg_command,
+            ffmpeg_passes,
+            renditions,
+            overlap,
+            previous,
+            next,
+            quality_metric,
+            quality_threshold,
+            fail_below_threshold,
+            target_vmaf,
+            crf_min,
+            crf_max,
+        } = assignment;
+        self.metrics.claimed(registration.worker_id);
+        registration
+            .heartbeat(transport, Some(transcoding_fragment_job_id))
+            .await?;
+
+        info!("Starting fragment job");
+        // Jobs with renditions transcode the fragment once per rendition, and every rendition
+        // can run several passes before its command.
+        let commands = if renditions.is_empty() {
+            vec![(None, &ffmpeg_passes, ffmpeg_command.as_str())]
+        } else {
+            renditions
+                .iter()
+                .map(|r| {
+                    (
+                        Some(r.name.as_str()),
+                        &r.ffmpeg_passes,
+                        r.ffmpeg_command.as_str(),
+                    )
+                })
+                .collect()
+        };
+        let mut templates = Vec::new();
+        for (rendition, passes, command) in &commands {
+            let ctemplates = passes
+                .iter()
+                .map(String::as_str)
+                .chain([*command])
+                .map(template::parse)
+                .collect::<Result<Vec<_>>>()
+                .context("Invalid ffmpeg command")?;
+            templates.push((*rendition, ctemplates));
+        }
+
+        let tempdir = TempDir::new(&format!(
+            "transcodeck-job-{}",
+            transcoding_fragment_job_id.as_hyphenated()
+        ))
+        .context("Failed to create the temporary directory")?;
+
+        let media_path = self
+            .fetch(&fragment, tempdir.path())
+            .await
+            .context("Failed to fetch the fragment")?;
+
+        // Encode a part of the neighbouring fragments around the fragment
+        let context = if overlap > 0.0 {
+            let mut neighbours = Vec::new();
+            for (name, neighbour) in [("previous", &previous), ("next", &next)] {
+                let path = match neighbour {
+                    Some(neighbour) => {
+                        let dir = tempdir.path().join(name);
+                        tokio::fs::create_dir(&dir).await?;
+                        let path = self
+                            .fetch(neighbour, &dir)
+                            .await
+                            .with_context(|| format!("Failed to fetch the {} fragment", name))?;
+                        Some(path)
+                    }
+                    None => None,
+                };
+                neighbours.push(path);
+            }
+            let context = overlap::context(
+                self.ffmpeg_bin,
+                self.ffprobe_bin,
+                &media_path,
+                neighbours[0].as_deref(),
+                neighbours[1].as_deref(),
+                overlap,
+                tempdir.path(),
+            )
+            .instrument(info_span!("overlap"))
+            .await
+            .context("Failed to prepare the overlap")?;
+            Some(context)
+        } else {
+            None
+        };
+        let input_path = context
+            .as_ref()
+            .map_or(&media_path, |context| &context.path);
+
+        // Search the highest CRF reaching the target quality, on a clip of the fragment
+        let mut crf = None;
+        let mut target_missed = false;
+        if let Some(target_vmaf) = target_vmaf {
+            let searched = self
+                .search_crf(
+                    &templates,
+                    input_path,
+                    target_vmaf,
+                    crf_min,
+                    crf_max,
+                    tempdir.path(),
+                )
+                .instrument(info_span!("target_quality", target_vmaf))
+                .await
+                .context("Target quality search failed")?;
+            match searched {
+                Some((value, reached)) => {
+                    crf = Some(value);
+                    target_missed = !reached;
+                }
+                None => {
+                    info!("Stopped the target quality search, returning fragment job to the queue");
+                    transport.requeue(transcoding_fragment_job_id).await?;
+                    self.metrics.fragments_retried.inc();
+                    let _ = tempdir.close();
+                    return Ok(Flow::Stop);
+                }
+            }
+        }
+        match crf {
+            Some(crf) => self.template_values.insert("crf".into(), crf.to_string()),
+            None => self.template_values.remove("crf"),
+        };
+
+        // Transcode the media fragment, into every rendition
+        let job_dir = package::job_dir(&self.cmd.output_dir, transcoding_job_id);
+        let mut runs = Vec::new();
+        for (rendition, ctemplates) in &templates {
+            let output_dir = match rendition {
+                Some(rendition) => job_dir.join(rendition),
+                None => job_dir.clone(),
+            };
+            let output_path = package::output_path(&output_dir, &fragment.filename);
+            tokio::fs::create_dir_all(&output_dir)
+                .await
+                .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+            let passlogfile = match rendition {
+                Some(rendition) => tempdir.path().join(format!("passlog-{}", rendition)),
+                None => tempdir.path().join("passlog"),
+            };
+
+            self.template_values
+                .insert("input".into(), input_path.to_string_lossy().to_string());
+            self.template_values
+                .insert("output".into(), output_path.to_string_lossy().to_string());
+            self.template_values.insert(
+                "passlogfile".into(),
+                passlogfile.to_string_lossy().to_string(),
             );
-            // Updating the fragment job to in progress, if it is still queued
-            let changed = diesel::update(schema::transcoding_fragment_job::table)
-                .filter(
-                    schema::transcoding_fragment_job::transcoding_fragment_job_id
-                        .eq(transcoding_fragment_job_id),
+
+            for (pass, ctemplate) in ctemplates.iter().enumerate() {
+                let args = template::render_args(ctemplate, &self.template_values)
+                    .context("Failed to render the ffmpeg command")?;
+                if let Some(policy) = &self.policy {
+                    policy
+                        .check(&args, tempdir.path(), &output_dir)
+                        .context("Rejected by the sandbox policy")?;
+                }
+                let args = match &context {
+                    Some(context) => overlap::force_key_frames(&args, &output_path, context),
+                    None => args,
+                };
+                runs.push(Run {
+                    rendition: *rendition,
+                    pass: pass + 1,
+                    passes: ctemplates.len(),
+                    args,
+                    output_path: output_path.clone(),
+                });
+            }
+        }
+        let duration = match probe::duration(self.ffprobe_bin, &media_path).await {
+            Ok(duration) => Some(duration),
+            Err(err) => {
+                warn!("Failed to probe fragment duration: {}", err);
+                None
+            }
+        };
+        if let (None, Some(duration)) = (fragment.duration, duration) {
+            // Fragments added before their duration was recorded, used by the ETA estimates.
+            transport
+                .duration(transcoding_fragment_job_id, duration)
+                .await?;
+        }
+        // The overlap is encoded too.
+        let duration = match &context {
+            Some(context) => Some(context.duration),
+            None => duration,
+        };
+        if self.shutdown.state() == State::Stopping {
+            transport.requeue(transcoding_fragment_job_id).await?;
+            self.metrics.fragments_retried.inc();
+            let _ = tempdir.close();
+            return Ok(Flow::Stop);
+        }
+
+        let started = Instant::now();
+        let count = runs.len();
+        let mut output_size = Some(0);
+        for (index, run) in runs.iter().enumerate() {
+            let span = info_span!(
+                "transcode",
+                rendition = run.rendition.unwrap_or_default(),
+                pass = run.pass,
+                output = %run.output_path.display()
+            );
+            let transcoded = self
+                .transcode(
+                    transport,
+                    registration,
+                    transcoding_fragment_job_id,
+                    run.args.clone(),
+                    tempdir.path(),
+                    Step {
+                        index,
+                        count,
+                        duration,
+                    },
+                )
+                .instrument(span)
+                .await?;
+            let (status, log) = match transcoded {
+                Transcoded::Exited(status, log) => (status, log),
+                Transcoded::Stopped => {
+                    info!("Killed ffmpeg, returning fragment job to the queue");
+                    transport.requeue(transcoding_fragment_job_id).await?;
+                    self.metrics.fragments_retried.inc();
+                    for run in &runs[..=index] {
+                        let _ = tokio::fs::remove_file(&run.output_path).await;
+                    }
+                    let _ = tempdir.close();
+                    return Ok(Flow::Stop);
+                }
+            };
+
+            if !status.success() {
+                warn!(
+                    exit_status = status.code(),
+                    rendition = run.rendition,
+                    "Transcoding failed: {}",
+                    status
+                );
+                let mut step = Vec::new();
+                if let Some(rendition) = run.rendition {
+                    step.push(format!("rendition {}", rendition));
+                }
+                if run.passes > 1 {
+                    step.push(format!("pass {}", run.pass));
+                }
+                let message = if step.is_empty() {
+                    "ffmpeg failed".to_string()
+                } else {
+                    format!("ffmpeg failed ({})", step.join(", "))
+                };
+                fail_fragment_job(
+                    transport,
+                    &self.metrics,
+                    transcoding_fragment_job_id,
+                    &message,
+                    status.code(),
+                    &log,
                 )
-                .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
-                .set(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::InProgress))
-                .execute(db)?;
-            if changed <= 0 {
+                .await?;
+                let _ = tempdir.close();
+                return Ok(Flow::Continue);
+            }
+            if run.pass < run.passes {
                 continue;
             }
+            output_size = match (output_size, tokio::fs::metadata(&run.output_path).await) {
+                (Some(size), Ok(metadata)) => Some(size + metadata.len() as i64),
+                _ => None,
+            };
+        }
 
-            println!("Starting fragment job: {}", transcoding_fragment_job_id);
-            let fragment = schema::fragment::table
-                .filter(schema::fragment::fragment_id.eq(fragment_id))
-                .first::<model::Fragment>(db)?;
-            let ffmpeg_command = schema::transcoding_job::table
-                .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
-                .select(schema::transcoding_job::ffmpeg_command)
-                .first::<String>(db)?;
-
-            // Update the parent transcoding job to in progress, if it is still queued.
-            if (diesel::update(schema::transcoding_job::table)
-                .set(schema::transcoding_job::status.eq(JobStatus::InProgress))
-                .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
-                .filter(schema::transcoding_job::status.eq(JobStatus::Queued))
-                .execute(db)?
-                > 0)
-            {
-                println!("Started transcoding job: {}", transcoding_job_id);
+        let encode_time = started.elapsed().as_secs_f64();
+
+        // ffmpeg can exit successfully with an empty or truncated output
+        self.validate_outputs(&runs, duration)
+            .instrument(info_span!("validate"))
+            .await
+            .context("Output validation failed")?;
+
+        // Compare the outputs with what was encoded, the fragment gets the lowest score
+        let mut quality_score = None;
+        let mut quality_flagged = target_missed;
+        if let Some(metric) = quality_metric {
+            let (score, rendition) = self
+                .score_outputs(metric, &runs, input_path)
+                .instrument(info_span!("quality", ?metric))
+                .await
+                .context("Quality check failed")?;
+            quality_score = Some(score);
+            if let Some(threshold) = quality_threshold.filter(|threshold| score < *threshold) {
+                quality_flagged = true;
+                warn!(
+                    ?metric,
+                    score, threshold, rendition, "Quality below the threshold"
+                );
+                if fail_below_threshold {
+                    let mut message = format!(
+                        "Quality below the threshold: {:?} {} < {}",
+                        metric, score, threshold
+                    );
+                    if let Some(rendition) = rendition {
+                        message.push_str(&format!(" (rendition {})", rendition));
+                    }
+                    let failure = Failure {
+                        message,
+                        exit_status: None,
+                        log: String::new(),
+                        quality_score: Some(score),
+                    };
+                    transport
+                        .fail(transcoding_fragment_job_id, &failure)
+                        .await?;
+                    self.metrics.fragments_failed.inc();
+                    let _ = tempdir.close();
+                    return Ok(Flow::Continue);
+                }
+            }
+        }
+
+        let stats = model::FragmentJobStats {
+            encode_time: Some(encode_time),
+            output_size,
+            average_speed: duration.map(|duration| duration / encode_time),
+            trim_start: context.as_ref().map(|context| context.trim_start),
+            trim_end: context.as_ref().map(|context| context.trim_end),
+            quality_score,
+            quality_flagged,
+            crf,
+        };
+        info!(
+            path = %job_dir.display(),
+            duration = encode_time,
+            speed = stats.average_speed,
+            crf,
+            "Transcoding completed"
+        );
+        transport
+            .complete(transcoding_fragment_job_id, &stats)
+            .await?;
+        self.metrics.fragments_processed.inc();
+        self.metrics.encode_duration.observe(encode_time);
+        if let Some(speed) = stats.average_speed {
+            self.metrics.encode_speed.observe(speed);
+        }
+
+        // Clean up the temporary directory
+        let _ = tempdir.close();
+        Ok(Flow::Continue)
+    }
+
+    /// Validate the output of every rendition, `duration` is the duration of what was
+    /// encoded, including the overlap.
+    async fn validate_outputs(&self, runs: &[Run<'_>], duration: Option<f64>) -> Result<()> {
+        for run in runs.iter().filter(|run| run.pass == run.passes) {
+            let validated = validate::output(
+                self.ffmpeg_bin,
+                self.ffprobe_bin,
+                &run.output_path,
+                duration,
+            )
+            .await;
+            match (validated, run.rendition) {
+                (Err(err), Some(rendition)) => bail!("{} (rendition {})", err, rendition),
+                (validated, _) => validated?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Score the output of every rendition against `source`, and return the lowest score and
+    /// its rendition.
+    async fn score_outputs<'a>(
+        &self,
+        metric: model::QualityMetric,
+        runs: &[Run<'a>],
+        source: &Path,
+    ) -> Result<(f64, Option<&'a str>)> {
+        let mut lowest: Option<(f64, Option<&str>)> = None;
+        for run in runs.iter().filter(|run| run.pass == run.passes) {
+            let score = quality::score(self.ffmpeg_bin, metric, &run.output_path, source).await?;
+            info!(rendition = run.rendition, score, "Quality checked");
+            if lowest.is_none_or(|(lowest, _)| score < lowest) {
+                lowest = Some((score, run.rendition));
             }
+        }
+        lowest.ok_or_else(|| anyhow!("No output to check"))
+    }
 
-            let tempdir = TempDir::new(&format!(
-                "transcodeck-job-{}",
-                transcoding_fragment_job_id.as_hyphenated().to_string()
-            ))?;
+    /// Binary search the highest CRF between `crf_min` and `crf_max` whose VMAF score reaches
+    /// `target_vmaf`, by encoding a clip from the middle of `input` with every candidate.
+    ///
+    /// Returns the CRF and whether it reaches the target: falls back to `crf_min` if no
+    /// candidate does. Returns `None` if the daemon has to stop.
+    async fn search_crf(
+        &self,
+        templates: &[(Option<&str>, Vec<Template<'_>>)],
+        input: &Path,
+        target_vmaf: f64,
+        crf_min: i32,
+        crf_max: i32,
+        tempdir: &Path,
+    ) -> Result<Option<(i32, bool)>> {
+        // Renditions would need a CRF each, `create_job` rejects them.
+        let [(None, ctemplates)] = templates else {
+            bail!("A target quality is not supported by jobs with renditions");
+        };
+        let duration = probe::duration(self.ffprobe_bin, input).await?;
+        let clip = if duration > PROBE_CLIP_DURATION {
+            let clip = tempdir.join("probe-clip.mkv");
+            let start = (duration - PROBE_CLIP_DURATION) / 2.0;
+            overlap::cut(
+                self.ffmpeg_bin,
+                input,
+                &clip,
+                Some(start),
+                Some(PROBE_CLIP_DURATION),
+            )
+            .await?;
+            clip
+        } else {
+            input.to_path_buf()
+        };
 
-            // Download the media fragment
-            if fragment.retrieval_url.is_none() {
-                bail!("Fragment retrieval URL is missing");
+        let mut values = self.template_values.clone();
+        values.insert("input".into(), clip.to_string_lossy().to_string());
+        values.insert(
+            "passlogfile".into(),
+            tempdir.join("probe-passlog").to_string_lossy().to_string(),
+        );
+        let (mut low, mut high) = (crf_min, crf_max);
+        let mut best = None;
+        while low <= high {
+            if self.shutdown.state() == State::Stopping {
+                return Ok(None);
+            }
+            let crf = low + (high - low) / 2;
+            let output = tempdir.join(format!("probe-{}.mkv", crf));
+            values.insert("crf".into(), crf.to_string());
+            values.insert("output".into(), output.to_string_lossy().to_string());
+            for ctemplate in ctemplates {
+                let args = template::render_args(ctemplate, &values)?;
+                if let Some(policy) = &self.policy {
+                    policy
+                        .check(&args, tempdir, tempdir)
+                        .context("Rejected by the sandbox policy")?;
+                }
+                let result = tokio::process::Command::new(self.ffmpeg_bin)
+                    .arg("-hide_banner")
+                    .arg("-loglevel")
+                    .arg("error")
+                    .arg("-y")
+                    .args(args)
+                    .current_dir(tempdir)
+                    .stdin(Stdio::null())
+                    .output()
+                    .await?;
+                if !result.status.success() {
+                    bail!(
+                        "ffmpeg failed with CRF {}: status={:?}\n{}",
+                        crf,
+                        result.status.code(),
+                        String::from_utf8_lossy(&result.stderr)
+                    );
+                }
             }
-            let fragment_url = fragment.retrieval_url.unwrap();
-            let fragment_path = tempdir.path().join(&fragment.filename);
+            let score =
+                quality::score(self.ffmpeg_bin, model::QualityMetric::Vmaf, &output, &clip).await?;
+            let _ = tokio::fs::remove_file(&output).await;
+            info!(crf, score, "Probe encoded");
+            if score >= target_vmaf {
+                best = Some(crf);
+                low = crf + 1;
+            } else {
+                high = crf - 1;
+            }
+        }
+        let Some(crf) = best else {
+            warn!(crf = crf_min, "No CRF reaches the target quality");
+            return Ok(Some((crf_min, false)));
+        };
+        info!(crf, "Target quality search completed");
+        Ok(Some((crf, true)))
+    }
+
+    /// Download a fragment into `dir`, and decrypt it if needed.
+    async fn fetch(&self, fragment: &model::Fragment, dir: &Path) -> Result<PathBuf> {
+        let Some(fragment_url) = &fragment.retrieval_url else {
+            bail!("Fragment retrieval URL is missing");
+        };
+        let fragment_path = dir.join(&fragment.filename);
+        async {
             let mut fragment_file = tokio::fs::File::create(&fragment_path).await?;
-            println!("Downloading fragment: {}", fragment_url);
-            let mut response = http.get(&fragment_url).send().await?;
+            info!("Downloading fragment");
+            let started = Instant::now();
+            let mut bytes = 0;
+            let mut response = self.http.get(fragment_url).send().await?;
             while let Some(chunk) = response.chunk().await? {
                 tokio::io::copy(&mut chunk.as_ref(), &mut fragment_file).await?;
                 fragment_file.flush().await?;
+                bytes += chunk.len() as u64;
             }
-            println!("Fragment downloaded: {}", fragment_path.display());
-
-            // Decrypt the media fragment if needed
-            let media_path = if fragment.encryption_key.is_some() {
-                let key =
-                    age::x25519::Identity::from_str(fragment.encryption_key.as_ref().unwrap())
-                        .expect("Failed to parse encryption key");
-                let mut output_path = tempdir.path().join(&fragment.filename);
-                output_path.set_extension("mkv");
-                decrypt_file(fragment_path, output_path.clone(), key).await?;
-                println!("Fragment decrypted: {}", output_path.display());
-                output_path
-            } else {
-                fragment_path
-            };
+            let elapsed = started.elapsed().as_secs_f64();
+            self.metrics.download_bytes.inc_by(bytes);
+            self.metrics.download_duration.observe(elapsed);
+            info!(
+                path = %fragment_path.display(),
+                bytes,
+                duration = elapsed,
+                "Fragment downloaded"
+            );
+            anyhow::Ok(())
+        }
+        .instrument(info_span!("download", url = %fragment_url))
+        .await?;
 
-            // Transcode the media fragment
-            let mut output_path = cmd
-                .output_dir
-                .join(&format!(
-                    "transcode-{}",
-                    transcoding_job_id.as_hyphenated().to_string()
-                ))
-                .join(&fragment.filename);
-            output_path.set_extension("mkv");
-            tokio::fs::create_dir_all(&output_path.parent().unwrap()).await?;
-
-            template_values.insert("input".into(), media_path.to_string_lossy().to_string());
-            template_values.insert("output".into(), output_path.to_string_lossy().to_string());
-
-            let ctemplate =
-                Template::parse(&ffmpeg_command).expect("Failed to parse ffmpeg command");
-            let command = ctemplate.render(&template_values)?;
-            let mut transcoder = tokio::process::Command::new(ffmpeg_bin)
-                .args(command.split_whitespace())
-                .spawn()?;
-
-            let status = transcoder.wait().await?;
-            if !status.success() {
-                println!("Transcoding failed: {}", status);
-            } else {
-                println!("Transcoding completed: {}", output_path.display());
-                diesel::update(schema::transcoding_fragment_job::table)
-                    .set(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Completed))
-                    .filter(
-                        schema::transcoding_fragment_job::transcoding_fragment_job_id
-                            .eq(transcoding_fragment_job_id),
-                    )
-                    .execute(db)?;
+        // Decrypt the media fragment if needed, with its own key or else with the identities
+        // of the daemon.
+        let identities = match &fragment.encryption_key {
+            Some(encryption_key) => vec![age::x25519::Identity::from_str(encryption_key)
+                .map_err(|err| anyhow!("Failed to parse encryption key: {}", err))?],
+            None if is_encrypted(&fragment_path).await? => {
+                if self.identities.is_empty() {
+                    bail!("The fragment is encrypted, without key nor identity file to decrypt it");
+                }
+                self.identities.clone()
             }
+            None => return Ok(fragment_path),
+        };
+        let mut output_path = dir.join(&fragment.filename);
+        output_path.set_extension("mkv");
+        async {
+            let started = Instant::now();
+            decrypt_file(fragment_path, output_path.clone(), &identities).await?;
+            let elapsed = started.elapsed().as_secs_f64();
+            self.metrics.decrypt_duration.observe(elapsed);
+            info!(
+                path = %output_path.display(),
+                duration = elapsed,
+                "Fragment decrypted"
+            );
+            anyhow::Ok(())
+        }
+        .instrument(info_span!("decrypt"))
+        .await?;
+        Ok(output_path)
+    }
 
-            // Clean up the temporary directory
-            let _ = tempdir.close();
-        } else {
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    /// Run ffmpeg, following its progress, until it exits or the daemon has to stop.
+    async fn transcode(
+        &self,
+        transport: &mut impl Transport,
+        registration: &mut Registration,
+        transcoding_fragment_job_id: Uuid,
+        args: Vec<String>,
+        tempdir: &Path,
+        step: Step,
+    ) -> Result<Transcoded> {
+        // ffmpeg runs in its own process group, so that a SIGINT from the terminal
+        // does not interrupt it while draining.
+        let mut transcoder = std::process::Command::new(self.ffmpeg_bin);
+        transcoder
+            .arg("-progress")
+            .arg("pipe:1")
+            .arg("-nostats")
+            // The passes of a rendition write the same output, and so do the retries.
+            .arg("-y")
+            .args(args)
+            .current_dir(tempdir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0);
+        let mut transcoder = tokio::process::Command::from(transcoder)
+            .kill_on_drop(true)
+            .spawn()?;
+
+        // Keep the tail of the ffmpeg logs, in case the transcoding fails
+        let mut stderr = BufReader::new(transcoder.stderr.take().unwrap()).lines();
+        let log_tail = tokio::spawn(
+            async move {
+                let mut tail = VecDeque::with_capacity(LOG_TAIL_LINES);
+                while let Ok(Some(line)) = stderr.next_line().await {
+                    info!(target: "ffmpeg", "{}", line);
+                    if tail.len() == LOG_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line);
+                }
+                Vec::from(tail).join("\n")
+            }
+            .instrument(Span::current()),
+        );
+
+        // Follow the progress reported by ffmpeg
+        let mut lines = BufReader::new(transcoder.stdout.take().unwrap()).lines();
+        let mut progress = FfmpegProgress::default();
+        let mut last_update = Instant::now();
+        loop {
+            let line: Option<String> = tokio::select! {
+                line = lines.next_line() => line?,
+                _ = self.shutdown.stopping() => {
+                    transcoder.kill().await?;
+                    log_tail.abort();
+                    return Ok(Transcoded::Stopped);
+                }
+            };
+            let Some(line) = line else {
+                break;
+            };
+            if progress.update(&line)
+                && (progress.ended || last_update.elapsed() >= PROGRESS_INTERVAL)
+            {
+                update_progress(transport, transcoding_fragment_job_id, &progress, step).await?;
+                registration
+                    .heartbeat(transport, Some(transcoding_fragment_job_id))
+                    .await?;
+                if self.refresh_queue_depth {
+                    self.metrics
+                        .set_queue_depth(&transport.queue_depth().await?);
+                }
+                last_update = Instant::now();
+            }
         }
+
+        let status = transcoder.wait().await?;
+        let log = log_tail.await.unwrap_or_default();
+        Ok(Transcoded::Exited(status, log))
     }
+}
+
+async fn update_progress(
+    transport: &mut impl Transport,
+    transcoding_fragment_job_id: Uuid,
+    progress: &FfmpegProgress,
+    step: Step,
+) -> Result<()> {
+    let changes = model::FragmentJobProgress {
+        progress: step.progress(progress),
+        fps: progress.fps,
+        speed: progress.speed,
+        bitrate: progress.bitrate,
+    };
+    transport
+        .progress(transcoding_fragment_job_id, &changes)
+        .await
+}
 
+/// Mark a fragment job as failed, and store the reason and the ffmpeg logs (if any).
+async fn fail_fragment_job(
+    transport: &mut impl Transport,
+    metrics: &Metrics,
+    transcoding_fragment_job_id: Uuid,
+    message: &str,
+    exit_status: Option<i32>,
+    log: &str,
+) -> Result<()> {
+    let failure = Failure {
+        message: message.to_string(),
+        exit_status,
+        log: log.to_string(),
+        quality_score: None,
+    };
+    transport
+        .fail(transcoding_fragment_job_id, &failure)
+        .await?;
+    metrics.fragments_failed.inc();
     Ok(())
 }
 
-async fn decrypt_file(input: PathBuf, output: PathBuf, key: impl Identity + Send) -> Result<()> {
-    let mut input_file = tokio::fs::File::open(input).await?;
-    let mut output_file = tokio::fs::File::create(output).await?;
+/// Whether a file starts with the header of the age format.
+async fn is_encrypted(path: &Path) -> Result<bool> {
+    let mut header = [0; AGE_HEADER.len()];
+    let mut file = tokio::fs::File::open(path).await?;
+    match file.read_exact(&mut header).await {
+        Ok(_) => Ok(header == *AGE_HEADER),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn decrypt_file(
+    input: PathBuf,
+    output: PathBuf,
+    identities: &[age::x25519::Identity],
+) -> Result<()> {
+    let input_file = tokio::fs::File::open(input).await?;
+    let output_file = tokio::fs::File::create(output).await?;
 
     let mut input_compat = input_file.compat();
     let decryptor = Decryptor::new_async(&mut input_compat).await;
     match decryptor {
         Ok(age::Decryptor::Recipients(d)) => {
-            let mut decrypted = d.decrypt_async(iter::once(&key as &dyn age::Identity))?;
+            let mut decrypted =
+                d.decrypt_async(identities.iter().map(|identity| identity as &dyn Identity))?;
             futures::io::copy(&mut decrypted, &mut output_file.compat()).await?;
         }
         Ok(_) => bail!("Unsupported decryptor"),
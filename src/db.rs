@@ -0,0 +1,326 @@
+use anyhow::Result;
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::{Pg, PgConnection, PgValue};
+use diesel::prelude::*;
+use diesel::r2d2::{self, R2D2Connection};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{self as diesel_types, HasSqlType};
+
+use crate::{model, schema};
+
+/// A connection to the Postgres database or, with the `sqlite` feature, to a SQLite database.
+#[cfg(not(feature = "sqlite"))]
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Pg(PgConnection),
+}
+
+/// A connection to the Postgres database or, with the `sqlite` feature, to a SQLite database.
+#[cfg(feature = "sqlite")]
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Pg(PgConnection),
+    Sqlite(diesel::sqlite::SqliteConnection),
+}
+
+/// Connect to the database, the backend is chosen from the scheme of the URL:
+/// `sqlite://<path>` for SQLite, anything else for Postgres.
+pub fn establish(url: &str) -> Result<DbConnection> {
+    if let Some(path) = url
+        .strip_prefix("sqlite://")
+        .or_else(|| url.strip_prefix("sqlite:"))
+    {
+        return establish_sqlite(path);
+    }
+    Ok(DbConnection::Pg(PgConnection::establish(url)?))
+}
+
+#[cfg(feature = "sqlite")]
+fn establish_sqlite(path: &str) -> Result<DbConnection> {
+    use diesel::connection::SimpleConnection;
+
+    let mut db = diesel::sqlite::SqliteConnection::establish(path)?;
+    // Several daemons might share the database file, wait for the locks instead of failing.
+    db.batch_execute(
+        "PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 10000; PRAGMA foreign_keys = ON;",
+    )?;
+    Ok(DbConnection::Sqlite(db))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn establish_sqlite(_path: &str) -> Result<DbConnection> {
+    anyhow::bail!("SQLite support is not enabled, build transcodeck with the `sqlite` feature")
+}
+
+/// A pool of database connections, used by the API server to run queries concurrently.
+pub type DbPool = r2d2::Pool<DbConnectionManager>;
+
+/// Build a pool of up to `max_size` connections to the database, see [`establish`].
+pub fn pool(url: &str, max_size: u32) -> Result<DbPool> {
+    let manager = DbConnectionManager {
+        url: url.to_string(),
+    };
+    Ok(r2d2::Pool::builder().max_size(max_size).build(manager)?)
+}
+
+/// Opens the connections of a [`DbPool`] with [`establish`], unlike the connection manager of
+/// diesel which does not know about the `sqlite://` URLs.
+#[derive(Debug)]
+pub struct DbConnectionManager {
+    url: String,
+}
+
+impl r2d2::ManageConnection for DbConnectionManager {
+    type Connection = DbConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<DbConnection, r2d2::Error> {
+        establish(&self.url).map_err(|err| {
+            r2d2::Error::ConnectionError(ConnectionError::BadConnection(err.to_string()))
+        })
+    }
+
+    fn is_valid(&self, db: &mut DbConnection) -> Result<(), r2d2::Error> {
+        db.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, db: &mut DbConnection) -> bool {
+        std::thread::panicking() || db.is_broken()
+    }
+}
+
+/// SQL types that can be stored by every backend, see [`multi_backend_type!`].
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 2950, array_oid = 2951))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct Uuid;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 1009, array_oid = 0))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct TextArray;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 3802, array_oid = 3807))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct Jsonb;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(oid = 1184, array_oid = 1185))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct Timestamptz;
+}
+
+/// Implement a SQL type of the schema for [`MultiBackend`], by binding the value as
+/// `$portable_type`, which must be implemented by every backend.
+macro_rules! multi_backend_type {
+    ($sql_type:ty, $portable_type:path, $rust_type:ty) => {
+        impl HasSqlType<$sql_type> for MultiBackend {
+            fn metadata(lookup: &mut Self::MetadataLookup) -> Self::TypeMetadata {
+                MultiBackend::lookup_sql_type::<$portable_type>(lookup)
+            }
+        }
+
+        impl ToSql<$sql_type, MultiBackend> for $rust_type {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, MultiBackend>) -> serialize::Result {
+                out.set_value(($portable_type, self));
+                Ok(IsNull::No)
+            }
+        }
+
+        impl FromSql<$sql_type, MultiBackend> for $rust_type {
+            fn from_sql(bytes: MultiRawValue<'_>) -> deserialize::Result<Self> {
+                bytes.from_sql::<Self, $portable_type>()
+            }
+        }
+    };
+}
+
+/// Implement an enum of the schema for [`MultiBackend`] and SQLite, diesel-derive-enum
+/// only implements it for Postgres. On SQLite, the variants are stored as text.
+macro_rules! multi_backend_enum {
+    ($sql_type:path, $rust_type:ty { $($variant:ident => $name:literal,)* }) => {
+        multi_backend_type!($sql_type, $sql_type, $rust_type);
+
+        impl Queryable<$sql_type, MultiBackend> for $rust_type {
+            type Row = Self;
+
+            fn build(row: Self::Row) -> deserialize::Result<Self> {
+                Ok(row)
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl ToSql<$sql_type, diesel::sqlite::Sqlite> for $rust_type {
+            fn to_sql<'b>(
+                &'b self,
+                out: &mut Output<'b, '_, diesel::sqlite::Sqlite>,
+            ) -> serialize::Result {
+                out.set_value(match self {
+                    $(<$rust_type>::$variant => $name,)*
+                });
+                Ok(IsNull::No)
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl FromSql<$sql_type, diesel::sqlite::Sqlite> for $rust_type {
+            fn from_sql(
+                value: diesel::sqlite::SqliteValue<'_, '_, '_>,
+            ) -> deserialize::Result<Self> {
+                match sqlite::text(value)?.as_str() {
+                    $($name => Ok(<$rust_type>::$variant),)*
+                    other => Err(format!("Unrecognized enum variant: {}", other).into()),
+                }
+            }
+        }
+    };
+}
+
+/// Implement a portable SQL type on Postgres with the native Postgres type.
+macro_rules! pg_type {
+    ($portable_type:ty, $pg_type:ty, $rust_type:ty) => {
+        impl ToSql<$portable_type, Pg> for $rust_type {
+            fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+                <$rust_type as ToSql<$pg_type, Pg>>::to_sql(self, out)
+            }
+        }
+
+        impl FromSql<$portable_type, Pg> for $rust_type {
+            fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+                <$rust_type as FromSql<$pg_type, Pg>>::from_sql(bytes)
+            }
+        }
+    };
+}
+
+multi_backend_type!(diesel_types::Uuid, sql_types::Uuid, uuid::Uuid);
+multi_backend_type!(
+    diesel_types::Array<diesel_types::Text>,
+    sql_types::TextArray,
+    Vec<String>
+);
+multi_backend_type!(diesel_types::Jsonb, sql_types::Jsonb, serde_json::Value);
+multi_backend_type!(
+    diesel_types::Timestamptz,
+    sql_types::Timestamptz,
+    chrono::NaiveDateTime
+);
+multi_backend_enum!(
+    schema::sql_types::JobStatus,
+    model::JobStatus {
+        Pending => "pending",
+        Queued => "queued",
+        InProgress => "in_progress",
+        Completed => "completed",
+        Failed => "failed",
+        Cancelled => "cancelled",
+        Deleted => "deleted",
+    }
+);
+multi_backend_enum!(
+    schema::sql_types::FragmentJobStatus,
+    model::FragmentJobStatus {
+        Pending => "pending",
+        Queued => "queued",
+        Reserved => "reserved",
+        InProgress => "in_progress",
+        Completed => "completed",
+        Failed => "failed",
+        Cancelled => "cancelled",
+        Deleted => "deleted",
+    }
+);
+multi_backend_enum!(
+    schema::sql_types::QualityMetric,
+    model::QualityMetric {
+        Vmaf => "vmaf",
+        Ssim => "ssim",
+        Psnr => "psnr",
+    }
+);
+multi_backend_enum!(
+    schema::sql_types::WebhookDeliveryStatus,
+    model::WebhookDeliveryStatus {
+        Pending => "pending",
+        Delivered => "delivered",
+        Failed => "failed",
+    }
+);
+
+pg_type!(sql_types::Uuid, diesel_types::Uuid, uuid::Uuid);
+pg_type!(
+    sql_types::TextArray,
+    diesel_types::Array<diesel_types::Text>,
+    Vec<String>
+);
+pg_type!(sql_types::Jsonb, diesel_types::Jsonb, serde_json::Value);
+pg_type!(
+    sql_types::Timestamptz,
+    diesel_types::Timestamptz,
+    chrono::NaiveDateTime
+);
+
+/// SQLite has no UUID, array or JSON types: UUIDs are stored as hyphenated strings,
+/// arrays and JSON values as JSON strings.
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use diesel::sqlite::{Sqlite, SqliteValue};
+
+    pub(super) fn text(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<String> {
+        <String as FromSql<diesel_types::Text, Sqlite>>::from_sql(value)
+    }
+
+    impl ToSql<sql_types::Uuid, Sqlite> for uuid::Uuid {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+            out.set_value(self.hyphenated().to_string());
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::Uuid, Sqlite> for uuid::Uuid {
+        fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            Ok(uuid::Uuid::parse_str(&text(value)?)?)
+        }
+    }
+
+    impl ToSql<sql_types::TextArray, Sqlite> for Vec<String> {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+            out.set_value(serde_json::to_string(self)?);
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::TextArray, Sqlite> for Vec<String> {
+        fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            Ok(serde_json::from_str(&text(value)?)?)
+        }
+    }
+
+    impl ToSql<sql_types::Jsonb, Sqlite> for serde_json::Value {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+            out.set_value(serde_json::to_string(self)?);
+            Ok(IsNull::No)
+        }
+    }
+
+    impl FromSql<sql_types::Jsonb, Sqlite> for serde_json::Value {
+        fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            Ok(serde_json::from_str(&text(value)?)?)
+        }
+    }
+
+    impl ToSql<sql_types::Timestamptz, Sqlite> for chrono::NaiveDateTime {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+            <chrono::NaiveDateTime as ToSql<diesel_types::Timestamp, Sqlite>>::to_sql(self, out)
+        }
+    }
+
+    impl FromSql<sql_types::Timestamptz, Sqlite> for chrono::NaiveDateTime {
+        fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            <chrono::NaiveDateTime as FromSql<diesel_types::Timestamp, Sqlite>>::from_sql(value)
+        }
+    }
+}
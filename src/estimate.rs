@@ -0,0 +1,283 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::{job, model, schema, worker};
+use model::FragmentJobStatus;
+
+/// Number of completed fragment jobs used to estimate the speed of the workers.
+const HISTORY_SIZE: i64 = 1000;
+
+/// Speed assumed for a worker without any history, relative to realtime.
+const DEFAULT_SPEED: f64 = 1.0;
+
+/// A completed fragment job: the worker, the ffmpeg commands (see [`commands_key`]) and the
+/// average encoding speed.
+type SpeedSample = (Option<Uuid>, String, f64);
+
+/// Estimates the remaining time of transcoding jobs, from the duration of their fragments
+/// and the historical speed of the online workers.
+pub struct Estimator {
+    workers: Vec<model::Worker>,
+    history: Vec<SpeedSample>,
+}
+
+impl Estimator {
+    pub fn load(db: &mut DbConnection) -> Result<Self> {
+        let workers = schema::worker::table
+            .select(model::Worker::as_select())
+            .load(db)?
+            .into_iter()
+            .filter(worker::is_online)
+            .collect();
+        let history = schema::transcoding_fragment_job::table
+            .inner_join(schema::transcoding_job::table)
+            .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Completed))
+            .filter(schema::transcoding_fragment_job::average_speed.is_not_null())
+            .order(schema::transcoding_fragment_job::completed_at.desc())
+            .limit(HISTORY_SIZE)
+            .select((
+                schema::transcoding_fragment_job::worker_id,
+                schema::transcoding_job::transcoding_job_id,
+                schema::transcoding_job::ffmpeg_command,
+                schema::transcoding_fragment_job::average_speed.assume_not_null(),
+            ))
+            .load::<(Option<Uuid>, Uuid, String, f64)>(db)?;
+
+        // Jobs with renditions have no ffmpeg command of their own.
+        let mut rendition_jobs = history
+            .iter()
+            .filter(|(_, _, command, _)| command.is_empty())
+            .map(|(_, job_id, _, _)| *job_id)
+            .collect::<Vec<_>>();
+        rendition_jobs.sort();
+        rendition_jobs.dedup();
+        let mut renditions = HashMap::new();
+        for job_id in rendition_jobs {
+            let commands = job::renditions(db, job_id)?
+                .into_iter()
+                .map(|rendition| rendition.ffmpeg_command)
+                .collect::<Vec<_>>();
+            renditions.insert(job_id, commands);
+        }
+
+        let history = history
+            .into_iter()
+            .map(|(worker_id, job_id, command, speed)| {
+                let renditions = renditions.get(&job_id).map_or(&[][..], Vec::as_slice);
+                (worker_id, commands_key(&command, renditions), speed)
+            })
+            .collect();
+        Ok(Estimator { workers, history })
+    }
+
+    /// Estimated speed of a worker for an ffmpeg command, relative to realtime.
+    ///
+    /// Uses the history of the worker with this command, or else the history of any worker
+    /// with this command, or else the history of the worker with any command.
+    pub fn worker_speed(&self, worker_id: Uuid, ffmpeg_command: &str) -> f64 {
+        let average = |samples: Vec<f64>| {
+            (!samples.is_empty()).then(|| samples.iter().sum::<f64>() / samples.len() as f64)
+        };
+        let samples = |same_worker: bool, same_command: bool| {
+            self.history
+                .iter()
+                .filter(|(w, _, _)| !same_worker || *w == Some(worker_id))
+                .filter(|(_, c, _)| !same_command || c == ffmpeg_command)
+                .map(|(_, _, speed)| *speed)
+                .collect::<Vec<_>>()
+        };
+        average(samples(true, true))
+            .or_else(|| average(samples(false, true)))
+            .or_else(|| average(samples(true, false)))
+            .unwrap_or(DEFAULT_SPEED)
+    }
+
+    /// Combined speed of the online workers able to process a job, relative to realtime.
+    ///
+    /// `commands` are the ffmpeg commands of the job, see [`commands_key`].
+    pub fn throughput(&self, job: &model::TranscodingJob, commands: &str) -> f64 {
+        self.workers
+            .iter()
+            .filter(|w| worker::can_run(w, &job.requirements()))
+            .map(|w| self.worker_speed(w.worker_id, commands))
+            .sum()
+    }
+
+    /// Estimated remaining time of a job, in seconds.
+    ///
+    /// Returns None if the duration of some fragments is unknown, or if no online worker
+    /// can process the job.
+    pub fn eta(&self, db: &mut DbConnection, job: &model::TranscodingJob) -> Result<Option<f64>> {
+        let Some(remaining) = remaining_duration(db, job.transcoding_job_id)? else {
+            return Ok(None);
+        };
+        let renditions = job::renditions(db, job.transcoding_job_id)?
+            .into_iter()
+            .map(|rendition| rendition.ffmpeg_command)
+            .collect::<Vec<_>>();
+        let throughput = self.throughput(job, &commands_key(&job.ffmpeg_command, &renditions));
+        if throughput <= 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(remaining / throughput))
+    }
+}
+
+/// Identifies the ffmpeg commands of a job in the speed history: its command, or else the
+/// commands of its renditions, in order.
+fn commands_key(ffmpeg_command: &str, renditions: &[String]) -> String {
+    if renditions.is_empty() {
+        ffmpeg_command.to_string()
+    } else {
+        renditions.join("\n")
+    }
+}
+
+/// Whether a job with this estimated remaining time would miss its deadline.
+///
+/// A job without estimate is not at risk: nothing is known about its remaining time, and it
+/// would otherwise be scheduled before every other job.
+pub fn is_at_risk(eta: Option<f64>, deadline: NaiveDateTime) -> bool {
+    let now = Utc::now().naive_utc();
+    match eta.and_then(|eta| Duration::try_milliseconds((eta * 1000.0) as i64)) {
+        Some(eta) => now + eta > deadline,
+        None => false,
+    }
+}
+
+/// Parse a deadline, as a RFC 3339 date or a delay from now (e.g. `90m`, `12h` or `2d`).
+pub fn parse_deadline(deadline: &str) -> Result<NaiveDateTime> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(deadline) {
+        return Ok(date.naive_utc());
+    }
+    let Some((index, unit)) = deadline.char_indices().next_back() else {
+        bail!("Invalid deadline: {}", deadline);
+    };
+    let number = deadline[..index]
+        .parse::<i64>()
+        .ok()
+        .filter(|number| *number > 0)
+        .ok_or_else(|| anyhow!("Invalid deadline: {}", deadline))?;
+    let delay = match unit {
+        's' => Duration::try_seconds(number),
+        'm' => Duration::try_minutes(number),
+        'h' => Duration::try_hours(number),
+        'd' => Duration::try_days(number),
+        _ => bail!(
+            "Invalid deadline unit: {} (expected s, m, h or d)",
+            deadline
+        ),
+    }
+    .ok_or_else(|| anyhow!("Invalid deadline: {}", deadline))?;
+    Ok(Utc::now().naive_utc() + delay)
+}
+
+/// Media duration (in seconds) left to transcode in a job.
+pub fn remaining_duration(db: &mut DbConnection, transcoding_job_id: Uuid) -> Result<Option<f64>> {
+    let fragments = schema::transcoding_fragment_job::table
+        .inner_join(schema::fragment::table)
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(transcoding_job_id))
+        .filter(schema::transcoding_fragment_job::status.eq_any([
+            FragmentJobStatus::Pending,
+            FragmentJobStatus::Queued,
+            FragmentJobStatus::Reserved,
+            FragmentJobStatus::InProgress,
+        ]))
+        .select((
+            schema::fragment::duration,
+            schema::transcoding_fragment_job::status,
+            schema::transcoding_fragment_job::progress,
+        ))
+        .load::<(Option<f64>, FragmentJobStatus, Option<f64>)>(db)?;
+
+    let mut remaining = 0.0;
+    for (duration, status, progress) in fragments {
+        let Some(duration) = duration else {
+            return Ok(None);
+        };
+        let progress = match status {
+            FragmentJobStatus::InProgress => progress.unwrap_or(0.0),
+            _ => 0.0,
+        };
+        remaining += duration * (1.0 - progress / 100.0);
+    }
+    Ok(Some(remaining))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_without_estimate_are_not_at_risk() {
+        let now = Utc::now().naive_utc();
+        let hour = Duration::try_hours(1).unwrap();
+        assert!(!is_at_risk(None, now + hour));
+        assert!(!is_at_risk(None, now - hour));
+        assert!(is_at_risk(Some(7200.0), now + hour));
+        assert!(!is_at_risk(Some(60.0), now + hour));
+    }
+
+    #[test]
+    fn keys_rendition_jobs_on_their_commands() {
+        assert_eq!(
+            commands_key("-i {input} {output}", &[]),
+            "-i {input} {output}"
+        );
+        let renditions = ["-s 1280x720".to_string(), "-s 640x360".to_string()];
+        assert_eq!(commands_key("", &renditions), "-s 1280x720\n-s 640x360");
+        assert_ne!(
+            commands_key("", &renditions),
+            commands_key("", &renditions[..1])
+        );
+    }
+
+    #[test]
+    fn parses_rfc3339_deadlines() {
+        let deadline = parse_deadline("2026-10-20T12:00:00+02:00").unwrap();
+        assert_eq!(deadline.to_string(), "2026-10-20 10:00:00");
+        let deadline = parse_deadline("2026-10-20T12:00:00Z").unwrap();
+        assert_eq!(deadline.to_string(), "2026-10-20 12:00:00");
+    }
+
+    #[test]
+    fn parses_delays() {
+        for (delay, seconds) in [
+            ("30s", 30),
+            ("90m", 90 * 60),
+            ("12h", 12 * 3600),
+            ("2d", 2 * 86400),
+        ] {
+            let expected = Duration::try_seconds(seconds).unwrap();
+            let before = Utc::now().naive_utc();
+            let deadline = parse_deadline(delay).unwrap();
+            let after = Utc::now().naive_utc();
+            assert!(deadline >= before + expected && deadline <= after + expected);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_deadlines() {
+        for deadline in ["", "h", "12", "12w", "-h", "tomorrow", "2026-10-20"] {
+            assert!(parse_deadline(deadline).is_err(), "{}", deadline);
+        }
+    }
+
+    #[test]
+    fn rejects_non_ascii_units() {
+        for deadline in ["1é", "é", "12日"] {
+            assert!(parse_deadline(deadline).is_err(), "{}", deadline);
+        }
+    }
+
+    #[test]
+    fn rejects_delays_in_the_past() {
+        for deadline in ["-5m", "0h", "-1d"] {
+            assert!(parse_deadline(deadline).is_err(), "{}", deadline);
+        }
+    }
+}
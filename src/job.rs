@@ -0,0 +1,729 @@
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::estimate::{self, Estimator};
+use crate::{model, schema, webhook, worker, JobCommand, JobSubcommand};
+use model::{FragmentJobStatus, JobStatus, QualityMetric, TranscodingJob, TranscodingRendition};
+
+/// Progress of a transcoding job and of its fragments.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobReport {
+    pub job: TranscodingJob,
+    /// Renditions of the job, empty if it has a single ffmpeg command.
+    pub renditions: Vec<TranscodingRendition>,
+    /// Completion of the job, in percent.
+    pub progress: f64,
+    pub completed_fragments: usize,
+    pub total_fragments: usize,
+    /// Estimated remaining time, in seconds.
+    pub eta: Option<f64>,
+    /// Whether the job is expected to miss its deadline.
+    pub at_risk: bool,
+    pub fragments: Vec<FragmentReport>,
+}
+
+/// Progress of a fragment job.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FragmentReport {
+    pub transcoding_fragment_job_id: Uuid,
+    pub fragment_id: Uuid,
+    pub filename: String,
+    pub status: FragmentJobStatus,
+    /// Completion of the fragment job, in percent.
+    pub progress: f64,
+    pub fps: Option<f64>,
+    pub speed: Option<f64>,
+    /// Estimated remaining time, in seconds.
+    pub eta: Option<f64>,
+    pub worker_id: Option<Uuid>,
+    pub started_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+/// Quality scores of the fragments of a transcoding job.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QualityReport {
+    pub transcoding_job_id: Uuid,
+    pub metric: Option<QualityMetric>,
+    pub threshold: Option<f64>,
+    /// VMAF score targeted by the search of the CRF of every fragment.
+    pub target_vmaf: Option<f64>,
+    pub scored_fragments: usize,
+    /// Number of fragments below the threshold.
+    pub flagged_fragments: usize,
+    pub min_score: Option<f64>,
+    pub mean_score: Option<f64>,
+    pub max_score: Option<f64>,
+    pub fragments: Vec<FragmentQuality>,
+}
+
+/// Quality score of a fragment job, the lowest one of its renditions.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FragmentQuality {
+    pub transcoding_fragment_job_id: Uuid,
+    pub filename: String,
+    pub status: FragmentJobStatus,
+    pub score: Option<f64>,
+    pub flagged: bool,
+    /// CRF chosen by the target-quality search.
+    pub crf: Option<i32>,
+}
+
+/// Summary of a transcoding job, as listed by `job list`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobSummary {
+    pub transcoding_job_id: Uuid,
+    pub media_id: Uuid,
+    pub status: JobStatus,
+    pub priority: i32,
+    pub submitter: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub deadline: Option<NaiveDateTime>,
+    pub completed_fragments: usize,
+    pub total_fragments: usize,
+}
+
+/// Failure log of a fragment job.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LogEntry {
+    pub transcoding_fragment_job_id: Uuid,
+    pub filename: String,
+    pub exit_status: Option<i32>,
+    pub message: String,
+    pub log: String,
+    pub created_at: NaiveDateTime,
+}
+
+pub async fn job(db: &mut DbConnection, cmd: JobCommand) -> Result<()> {
+    match cmd.cmd {
+        JobSubcommand::List { status } => print_jobs(db, status),
+        JobSubcommand::Show { job_id } => show_job(db, &job_id),
+        JobSubcommand::Logs { job_id } => show_logs(db, &job_id),
+        JobSubcommand::Quality { job_id } => show_quality(db, &job_id),
+        JobSubcommand::Priority { job_id, priority } => {
+            let job_id = Uuid::parse_str(&job_id)?;
+            set_priority(db, job_id, priority)?;
+            println!("Transcoding job {} priority set to {}", job_id, priority);
+            Ok(())
+        }
+        JobSubcommand::Queue { job_id } => {
+            let job_id = Uuid::parse_str(&job_id)?;
+            let queued = queue_job(db, job_id)?;
+            println!("Transcoding job queued: {} ({} fragments)", job_id, queued);
+            Ok(())
+        }
+        JobSubcommand::Cancel { job_id } => {
+            let job_id = Uuid::parse_str(&job_id)?;
+            let cancelled = cancel_job(db, job_id)?;
+            println!(
+                "Transcoding job cancelled: {} ({} fragments)",
+                job_id, cancelled
+            );
+            Ok(())
+        }
+        JobSubcommand::Retry { job_id } => {
+            let job_id = Uuid::parse_str(&job_id)?;
+            let retried = retry_job(db, job_id)?;
+            println!(
+                "Transcoding job retried: {} ({} fragments)",
+                job_id, retried
+            );
+            Ok(())
+        }
+    }
+}
+
+/// List the transcoding jobs, newest first, optionally only the ones with the given status.
+pub fn list_jobs(db: &mut DbConnection, status: Option<JobStatus>) -> Result<Vec<JobSummary>> {
+    let mut query = schema::transcoding_job::table
+        .filter(schema::transcoding_job::status.ne(JobStatus::Deleted))
+        .order(schema::transcoding_job::created_at.desc())
+        .select(TranscodingJob::as_select())
+        .into_boxed();
+    if let Some(status) = &status {
+        query = query.filter(schema::transcoding_job::status.eq(status.clone()));
+    }
+    let jobs = query.load(db)?;
+
+    // Fragment jobs counted by the database, only for the listed jobs.
+    let mut counts = schema::transcoding_fragment_job::table
+        .inner_join(schema::transcoding_job::table)
+        .filter(schema::transcoding_job::status.ne(JobStatus::Deleted))
+        .group_by((
+            schema::transcoding_fragment_job::transcoding_job_id,
+            schema::transcoding_fragment_job::status,
+        ))
+        .select((
+            schema::transcoding_fragment_job::transcoding_job_id,
+            schema::transcoding_fragment_job::status,
+            diesel::dsl::count_star(),
+        ))
+        .into_boxed();
+    if let Some(status) = status {
+        counts = counts.filter(schema::transcoding_job::status.eq(status));
+    }
+    let mut fragments = HashMap::new();
+    for (transcoding_job_id, status, count) in counts.load::<(Uuid, FragmentJobStatus, i64)>(db)? {
+        let (completed, total) = fragments.entry(transcoding_job_id).or_insert((0, 0));
+        if status == FragmentJobStatus::Completed {
+            *completed += count as usize;
+        }
+        *total += count as usize;
+    }
+
+    Ok(jobs
+        .into_iter()
+        .map(|job| {
+            let (completed_fragments, total_fragments) = fragments
+                .get(&job.transcoding_job_id)
+                .copied()
+                .unwrap_or((0, 0));
+            JobSummary {
+                transcoding_job_id: job.transcoding_job_id,
+                media_id: job.media_id,
+                status: job.status,
+                priority: job.priority,
+                submitter: job.submitter,
+                created_at: job.created_at,
+                deadline: job.deadline,
+                completed_fragments,
+                total_fragments,
+            }
+        })
+        .collect())
+}
+
+fn print_jobs(db: &mut DbConnection, status: Option<JobStatus>) -> Result<()> {
+    println!(
+        "{:<36} {:<36} {:<12} {:>8} {:>9} {:<19}",
+        "JOB", "MEDIA", "STATUS", "PRIORITY", "FRAGMENTS", "CREATED"
+    );
+    for job in list_jobs(db, status)? {
+        println!(
+            "{:<36} {:<36} {:<12} {:>8} {:>9} {:<19}",
+            job.transcoding_job_id,
+            job.media_id,
+            format!("{:?}", job.status),
+            job.priority,
+            format!("{}/{}", job.completed_fragments, job.total_fragments),
+            job.created_at.format("%Y-%m-%d %H:%M:%S"),
+        );
+    }
+    Ok(())
+}
+
+/// Load a transcoding job, with the progress and estimates of its fragments.
+pub fn job_report(db: &mut DbConnection, job_id: Uuid) -> Result<JobReport> {
+    let job = schema::transcoding_job::table
+        .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+        .select(TranscodingJob::as_select())
+        .first(db)
+        .with_context(|| format!("Transcoding job not found: {}", job_id))?;
+    let renditions = renditions(db, job_id)?;
+    let fragments = schema::transcoding_fragment_job::table
+        .inner_join(schema::fragment::table)
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job_id))
+        .order((
+            schema::fragment::fragment_number.asc(),
+            schema::fragment::filename.asc(),
+        ))
+        .select((
+            model::TranscodingFragmentJob::as_select(),
+            model::Fragment::as_select(),
+        ))
+        .load::<(model::TranscodingFragmentJob, model::Fragment)>(db)?;
+
+    let now = Utc::now().naive_utc();
+    let completed = fragments
+        .iter()
+        .filter(|(fj, _)| fj.status == FragmentJobStatus::Completed)
+        .count();
+    let progress = fragments
+        .iter()
+        .map(|(fj, _)| fragment_progress(fj))
+        .sum::<f64>()
+        / fragments.len().max(1) as f64;
+
+    // Estimate from the fragment durations and the speed of the online workers, or else
+    // from the progress made since the first fragment started.
+    let started_at = fragments.iter().filter_map(|(fj, _)| fj.started_at).min();
+    let job_eta = match Estimator::load(db)?.eta(db, &job)? {
+        Some(eta) => Some(eta),
+        None => started_at.and_then(|started_at| eta(started_at, progress, now)),
+    };
+    let at_risk = match job.deadline {
+        Some(deadline) => completed < fragments.len() && estimate::is_at_risk(job_eta, deadline),
+        None => false,
+    };
+
+    let fragments = fragments
+        .into_iter()
+        .map(|(fragment_job, fragment)| FragmentReport {
+            eta: match (&fragment_job.status, fragment_job.started_at) {
+                (FragmentJobStatus::InProgress, Some(started_at)) => {
+                    eta(started_at, fragment_progress(&fragment_job), now)
+                }
+                _ => None,
+            },
+            progress: fragment_progress(&fragment_job),
+            transcoding_fragment_job_id: fragment_job.transcoding_fragment_job_id,
+            fragment_id: fragment.fragment_id,
+            filename: fragment.filename,
+            status: fragment_job.status,
+            fps: fragment_job.fps,
+            speed: fragment_job.speed,
+            worker_id: fragment_job.worker_id,
+            started_at: fragment_job.started_at,
+            completed_at: fragment_job.completed_at,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(JobReport {
+        job,
+        renditions,
+        progress,
+        completed_fragments: completed,
+        total_fragments: fragments.len(),
+        eta: (completed < fragments.len()).then_some(job_eta).flatten(),
+        at_risk,
+        fragments,
+    })
+}
+
+/// Load the renditions of a transcoding job, in order.
+pub fn renditions(db: &mut DbConnection, job_id: Uuid) -> QueryResult<Vec<TranscodingRendition>> {
+    schema::transcoding_rendition::table
+        .filter(schema::transcoding_rendition::transcoding_job_id.eq(job_id))
+        .order(schema::transcoding_rendition::position.asc())
+        .select(TranscodingRendition::as_select())
+        .load(db)
+}
+
+fn show_job(db: &mut DbConnection, job_id: &str) -> Result<()> {
+    let report = job_report(db, Uuid::parse_str(job_id)?)?;
+    let job = &report.job;
+    let now = Utc::now().naive_utc();
+
+    println!("Transcoding job: {}", job.transcoding_job_id);
+    println!("  Media: {}", job.media_id);
+    println!("  Status: {:?}", job.status);
+    println!("  Priority: {}", job.priority);
+    if let Some(submitter) = &job.submitter {
+        println!("  Submitter: {}", submitter);
+    }
+    if report.renditions.is_empty() {
+        for (i, pass) in job.ffmpeg_passes.iter().enumerate() {
+            println!("  Pass {}: {}", i + 1, pass);
+        }
+        println!("  Command: {}", job.ffmpeg_command);
+    }
+    for rendition in &report.renditions {
+        for (i, pass) in rendition.ffmpeg_passes.iter().enumerate() {
+            println!("  Rendition {} pass {}: {}", rendition.name, i + 1, pass);
+        }
+        println!(
+            "  Rendition {}: {}",
+            rendition.name, rendition.ffmpeg_command
+        );
+    }
+    if !job.required_encoders.is_empty() {
+        println!("  Required encoders: {}", job.required_encoders.join(", "));
+    }
+    if !job.required_variables.is_empty() {
+        println!(
+            "  Required variables: {}",
+            job.required_variables.join(", ")
+        );
+    }
+    if !job.required_tags.is_empty() {
+        println!("  Required tags: {}", job.required_tags.join(", "));
+    }
+    if let Some(min_memory) = job.min_memory {
+        println!("  Minimum memory: {}", worker::format_size(min_memory));
+    }
+    if job.overlap > 0.0 {
+        println!("  Overlap: {}s", job.overlap);
+    }
+    if let Some(metric) = job.quality_metric {
+        match job.quality_threshold {
+            Some(threshold) => println!(
+                "  Quality check: {:?} >= {} ({} below)",
+                metric,
+                threshold,
+                if job.fail_below_threshold {
+                    "fail"
+                } else {
+                    "flag"
+                }
+            ),
+            None => println!("  Quality check: {:?}", metric),
+        }
+    }
+    if let Some(target_vmaf) = job.target_vmaf {
+        println!(
+            "  Target quality: VMAF {} (CRF {}-{})",
+            target_vmaf, job.crf_min, job.crf_max
+        );
+    }
+    println!("  Created: {}", job.created_at);
+    println!("  Updated: {}", job.updated_at);
+    println!(
+        "  Progress: {:.1}% ({}/{} fragments completed)",
+        report.progress, report.completed_fragments, report.total_fragments
+    );
+    let completion = report
+        .eta
+        .and_then(|eta| chrono::Duration::try_seconds(eta as i64))
+        .map(|eta| now + eta);
+    if let (Some(eta), Some(completion)) = (report.eta, completion) {
+        println!(
+            "  ETA: {} (around {})",
+            format_duration(eta),
+            completion.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+    if let Some(deadline) = job.deadline {
+        println!(
+            "  Deadline: {}{}",
+            deadline.format("%Y-%m-%d %H:%M:%S"),
+            if report.at_risk { " (at risk)" } else { "" }
+        );
+    }
+    println!();
+
+    println!(
+        "{:<24} {:<12} {:>8} {:>8} {:>7} {:>10}",
+        "FRAGMENT", "STATUS", "PROGRESS", "FPS", "SPEED", "ETA"
+    );
+    for fragment in &report.fragments {
+        println!(
+            "{:<24} {:<12} {:>8} {:>8} {:>7} {:>10}",
+            fragment.filename,
+            format!("{:?}", fragment.status),
+            format!("{:.1}%", fragment.progress),
+            fragment
+                .fps
+                .map(|fps| format!("{:.1}", fps))
+                .unwrap_or_default(),
+            fragment
+                .speed
+                .map(|speed| format!("{:.2}x", speed))
+                .unwrap_or_default(),
+            fragment.eta.map(format_duration).unwrap_or_default(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Load the failure logs of a transcoding job, or of a single fragment job.
+pub fn job_logs(db: &mut DbConnection, job_id: Uuid) -> Result<Vec<LogEntry>> {
+    let logs = schema::transcoding_fragment_job_log::table
+        .inner_join(schema::transcoding_fragment_job::table.inner_join(schema::fragment::table))
+        .filter(
+            schema::transcoding_fragment_job::transcoding_job_id
+                .eq(job_id)
+                .or(schema::transcoding_fragment_job::transcoding_fragment_job_id.eq(job_id)),
+        )
+        .order(schema::transcoding_fragment_job_log::created_at.asc())
+        .select((
+            model::TranscodingFragmentJobLog::as_select(),
+            schema::fragment::filename,
+        ))
+        .load::<(model::TranscodingFragmentJobLog, String)>(db)?;
+
+    Ok(logs
+        .into_iter()
+        .map(|(log, filename)| LogEntry {
+            transcoding_fragment_job_id: log.transcoding_fragment_job_id,
+            filename,
+            exit_status: log.exit_status,
+            message: log.message,
+            log: log.log,
+            created_at: log.created_at,
+        })
+        .collect())
+}
+
+fn show_logs(db: &mut DbConnection, job_id: &str) -> Result<()> {
+    let job_id = Uuid::parse_str(job_id)?;
+    let logs = job_logs(db, job_id)?;
+
+    if logs.is_empty() {
+        println!("No logs found for {}", job_id);
+    }
+    for log in logs {
+        println!(
+            "== {} (fragment job {}) at {}",
+            log.filename, log.transcoding_fragment_job_id, log.created_at
+        );
+        match log.exit_status {
+            Some(code) => println!("{} (exit status: {})", log.message, code),
+            None => println!("{}", log.message),
+        }
+        if !log.log.is_empty() {
+            println!("{}", log.log);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Load the quality scores of the fragments of a transcoding job.
+pub fn quality_report(db: &mut DbConnection, job_id: Uuid) -> Result<QualityReport> {
+    let job = schema::transcoding_job::table
+        .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+        .select(TranscodingJob::as_select())
+        .first(db)
+        .with_context(|| format!("Transcoding job not found: {}", job_id))?;
+    let fragments = schema::transcoding_fragment_job::table
+        .inner_join(schema::fragment::table)
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job_id))
+        .order((
+            schema::fragment::fragment_number.asc(),
+            schema::fragment::filename.asc(),
+        ))
+        .select((
+            model::TranscodingFragmentJob::as_select(),
+            schema::fragment::filename,
+        ))
+        .load::<(model::TranscodingFragmentJob, String)>(db)?
+        .into_iter()
+        .map(|(fragment_job, filename)| FragmentQuality {
+            transcoding_fragment_job_id: fragment_job.transcoding_fragment_job_id,
+            filename,
+            status: fragment_job.status,
+            score: fragment_job.quality_score,
+            flagged: fragment_job.quality_flagged,
+            crf: fragment_job.crf,
+        })
+        .collect::<Vec<_>>();
+
+    let scores = fragments
+        .iter()
+        .filter_map(|fragment| fragment.score)
+        .collect::<Vec<_>>();
+    Ok(QualityReport {
+        transcoding_job_id: job.transcoding_job_id,
+        metric: job.quality_metric,
+        threshold: job.quality_threshold,
+        target_vmaf: job.target_vmaf,
+        scored_fragments: scores.len(),
+        flagged_fragments: fragments.iter().filter(|fragment| fragment.flagged).count(),
+        min_score: scores.iter().copied().min_by(f64::total_cmp),
+        mean_score: (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64),
+        max_score: scores.iter().copied().max_by(f64::total_cmp),
+        fragments,
+    })
+}
+
+fn show_quality(db: &mut DbConnection, job_id: &str) -> Result<()> {
+    let report = quality_report(db, Uuid::parse_str(job_id)?)?;
+    if report.metric.is_none() && report.target_vmaf.is_none() {
+        println!(
+            "Transcoding job {} has no quality check (see transcode --quality)",
+            report.transcoding_job_id
+        );
+        return Ok(());
+    }
+    let format_score = |score: Option<f64>| score.map(|s| format!("{:.3}", s)).unwrap_or_default();
+
+    println!("Transcoding job: {}", report.transcoding_job_id);
+    if let Some(metric) = report.metric {
+        println!("  Metric: {:?}", metric);
+    }
+    if let Some(target_vmaf) = report.target_vmaf {
+        println!("  Target: VMAF {}", target_vmaf);
+    }
+    if let Some(threshold) = report.threshold {
+        println!("  Threshold: {}", threshold);
+    }
+    println!(
+        "  Scored: {}/{} fragments",
+        report.scored_fragments,
+        report.fragments.len()
+    );
+    if report.scored_fragments > 0 {
+        println!(
+            "  Score: min {}, mean {}, max {}",
+            format_score(report.min_score),
+            format_score(report.mean_score),
+            format_score(report.max_score)
+        );
+    }
+    if report.threshold.is_some() {
+        println!("  Flagged: {} fragments", report.flagged_fragments);
+    }
+    println!();
+
+    println!(
+        "{:<24} {:<12} {:>10} {:>4} {:<7}",
+        "FRAGMENT", "STATUS", "SCORE", "CRF", "FLAGGED"
+    );
+    for fragment in &report.fragments {
+        println!(
+            "{:<24} {:<12} {:>10} {:>4} {:<7}",
+            fragment.filename,
+            format!("{:?}", fragment.status),
+            format_score(fragment.score),
+            fragment.crf.map(|crf| crf.to_string()).unwrap_or_default(),
+            if fragment.flagged { "yes" } else { "" }
+        );
+    }
+
+    Ok(())
+}
+
+pub fn set_priority(db: &mut DbConnection, job_id: Uuid, priority: i32) -> Result<()> {
+    job_status(db, job_id)?;
+    diesel::update(schema::transcoding_job::table)
+        .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+        .set(schema::transcoding_job::priority.eq(priority))
+        .execute(db)?;
+    Ok(())
+}
+
+fn job_status(db: &mut DbConnection, job_id: Uuid) -> Result<JobStatus> {
+    schema::transcoding_job::table
+        .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+        .select(schema::transcoding_job::status)
+        .first(db)
+        .with_context(|| format!("Transcoding job not found: {}", job_id))
+}
+
+/// Queue a pending transcoding job (added without `--start`), returns the number of
+/// queued fragments.
+pub fn queue_job(db: &mut DbConnection, job_id: Uuid) -> Result<usize> {
+    let status = job_status(db, job_id)?;
+    if status != JobStatus::Pending {
+        bail!("Transcoding job {} is not pending: {:?}", job_id, status);
+    }
+    db.transaction(|db| {
+        diesel::update(schema::transcoding_job::table)
+            .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+            .set(schema::transcoding_job::status.eq(JobStatus::Queued))
+            .execute(db)?;
+        webhook::record(db, webhook::Event::JobQueued, job_id, None)?;
+        diesel::update(schema::transcoding_fragment_job::table)
+            .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job_id))
+            .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Pending))
+            .set(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
+            .execute(db)
+    })
+    .map_err(Into::into)
+}
+
+/// Cancel a transcoding job, returns the number of cancelled fragments.
+///
+/// Fragment jobs already in progress are not interrupted.
+pub fn cancel_job(db: &mut DbConnection, job_id: Uuid) -> Result<usize> {
+    let status = job_status(db, job_id)?;
+    if matches!(
+        status,
+        JobStatus::Completed | JobStatus::Cancelled | JobStatus::Deleted
+    ) {
+        bail!(
+            "Transcoding job {} cannot be cancelled: {:?}",
+            job_id,
+            status
+        );
+    }
+    db.transaction(|db| {
+        diesel::update(schema::transcoding_job::table)
+            .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+            .set(schema::transcoding_job::status.eq(JobStatus::Cancelled))
+            .execute(db)?;
+        webhook::record(db, webhook::Event::JobCancelled, job_id, None)?;
+        diesel::update(schema::transcoding_fragment_job::table)
+            .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job_id))
+            .filter(
+                schema::transcoding_fragment_job::status
+                    .eq(FragmentJobStatus::Pending)
+                    .or(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued)),
+            )
+            .set(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Cancelled))
+            .execute(db)
+    })
+    .map_err(Into::into)
+}
+
+/// Queue again the failed and cancelled fragments of a transcoding job, returns the number
+/// of retried fragments.
+pub fn retry_job(db: &mut DbConnection, job_id: Uuid) -> Result<usize> {
+    let status = job_status(db, job_id)?;
+    if status == JobStatus::Deleted {
+        bail!("Transcoding job {} is deleted", job_id);
+    }
+    let retried = db.transaction(|db| {
+        let retried = diesel::update(schema::transcoding_fragment_job::table)
+            .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job_id))
+            .filter(
+                schema::transcoding_fragment_job::status
+                    .eq(FragmentJobStatus::Failed)
+                    .or(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Cancelled)),
+            )
+            .set((
+                schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued),
+                schema::transcoding_fragment_job::started_at.eq(None::<NaiveDateTime>),
+                schema::transcoding_fragment_job::worker_id.eq(None::<Uuid>),
+                &model::FragmentJobProgress::default(),
+            ))
+            .execute(db)?;
+        if retried > 0 && status != JobStatus::InProgress {
+            diesel::update(schema::transcoding_job::table)
+                .filter(schema::transcoding_job::transcoding_job_id.eq(job_id))
+                .set(schema::transcoding_job::status.eq(JobStatus::Queued))
+                .execute(db)?;
+            webhook::record(db, webhook::Event::JobQueued, job_id, None)?;
+        }
+        diesel::QueryResult::Ok(retried)
+    })?;
+    if retried == 0 {
+        bail!(
+            "Transcoding job {} has no failed or cancelled fragment",
+            job_id
+        );
+    }
+    Ok(retried)
+}
+
+/// Completion of a fragment job, in percent.
+fn fragment_progress(fragment_job: &model::TranscodingFragmentJob) -> f64 {
+    match fragment_job.status {
+        FragmentJobStatus::Completed => 100.0,
+        FragmentJobStatus::InProgress => fragment_job.progress.unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+/// Estimate the remaining time (in seconds) from the elapsed time and the progress so far.
+fn eta(started_at: NaiveDateTime, progress: f64, now: NaiveDateTime) -> Option<f64> {
+    if progress <= 0.0 || progress >= 100.0 {
+        return None;
+    }
+    let elapsed = (now - started_at).num_milliseconds() as f64 / 1000.0;
+    Some(elapsed * (100.0 - progress) / progress)
+}
+
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}h{:02}m{:02}s",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
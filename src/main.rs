@@ -1,26 +1,73 @@
-use anyhow::{anyhow, bail, Result};
-use clap::{Parser, Subcommand};
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use uuid::Uuid;
 
 pub mod add_media;
 pub mod add_transcode;
+pub mod api;
+pub mod config;
 pub mod daemon;
+pub mod db;
+pub mod estimate;
+pub mod job;
+pub mod logging;
+pub mod media;
+pub mod metrics;
+pub mod migrate;
 pub mod model;
+pub mod overlap;
+pub mod package;
+pub mod policy;
+pub mod preset;
+pub mod probe;
+pub mod progress;
+pub mod quality;
+pub mod scheduler;
 pub mod schema;
+pub mod segment;
+pub mod shutdown;
+pub mod storage;
+pub mod template;
+pub mod transport;
+pub mod validate;
+pub mod webhook;
+pub mod worker;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// The URI of the database to connect to
+    /// Configuration file, `$XDG_CONFIG_HOME/transcodeck/config.toml` by default. Its settings
+    /// are overridden by the environment variables, and by the command-line flags.
+    #[clap(long, env = "TRANSCODECK_CONFIG", global = true)]
+    #[allow(dead_code)] // Read by `Config::load`, before the arguments are parsed.
+    config: Option<PathBuf>,
+
+    /// The URI of the database to connect to, either a Postgres URI or `sqlite://<path>`
+    /// (requires the `sqlite` feature). Not needed by a daemon using the worker API.
     #[clap(long, env = "DATABASE_URL")]
-    db_uri: String,
+    db_uri: Option<String>,
 
     /// FFmpeg bin to use for transcoding
     #[clap(long, env = "FFMPEG_BIN", default_value = "ffmpeg")]
     ffmpeg_bin: String,
 
+    /// FFprobe bin to use for media analysis
+    #[clap(long, env = "FFPROBE_BIN", default_value = "ffprobe")]
+    ffprobe_bin: String,
+
+    /// Format of the logs, the level is set by `RUST_LOG`.
+    #[clap(
+        long,
+        env = "TRANSCODECK_LOG_FORMAT",
+        value_enum,
+        default_value_t,
+        global = true
+    )]
+    log_format: logging::LogFormat,
+
     #[clap(subcommand)]
     cmd: Command,
 }
@@ -36,10 +83,40 @@ enum Command {
     #[command(about = "Start the transcoding daemon")]
     Daemon(DaemonCommand),
 
+    #[command(about = "Inspect transcoding jobs")]
+    Job(JobCommand),
+
+    #[command(about = "Manage the presets of the transcoding jobs")]
+    Preset(PresetCommand),
+
+    #[command(about = "List the transcoding workers")]
+    Workers(WorkersCommand),
+
+    #[command(about = "Manage a transcoding worker")]
+    Worker(WorkerCommand),
+
+    #[command(about = "Apply the pending database migrations")]
+    Migrate(MigrateCommand),
+
+    #[command(about = "Start the worker API server")]
+    Serve(ServeCommand),
+
+    #[command(about = "Deliver and inspect the webhook notifications")]
+    Webhook(WebhookCommand),
+
+    #[command(about = "Package a completed transcoding job for streaming (HLS, DASH)")]
+    Package(PackageCommand),
+
     //    #[command(about = "Add a transcoding fragment job")]
     //    TranscodeFragment(TranscodeFragmentCommand),
     #[command(about = "List all media in the database")]
     ListMedia,
+
+    #[command(about = "Show a media, its fragments and transcoding jobs")]
+    ShowMedia {
+        /// The media ID
+        media_id: String,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -60,6 +137,14 @@ pub struct AddMediaCommand {
     #[clap(short, long, default_value = "0")]
     fragment: u32,
 
+    /// How the cuts between the fragments are chosen.
+    #[clap(long, value_enum, default_value_t)]
+    segmentation: segment::Segmentation,
+
+    /// Minimum scene change score (between 0 and 1) of the `scene` segmentation.
+    #[clap(long, default_value = "0.4")]
+    scene_threshold: f64,
+
     /// The output path where every fragment will be stored.
     /// If not set, the fragments will be stored in a sub-directory where the input file is stored.
     /// If the media is not fragmented nor encrypted, this flag is ignored.
@@ -73,11 +158,367 @@ pub struct TranscodeCommand {
     media_id: String,
 
     /// The ffmpeg command to use for transcoding
-    ffmpeg_command: String,
+    #[clap(required_unless_present_any = ["renditions", "preset"])]
+    ffmpeg_command: Option<String>,
+
+    /// Preset providing the settings not given on the command line, e.g. its ffmpeg command.
+    #[clap(long)]
+    preset: Option<String>,
+
+    /// Rendition of the job, as `name=<ffmpeg command>`, instead of a single ffmpeg command.
+    /// Every rendition is transcoded from the same fragments, e.g. the steps of an adaptive
+    /// bitrate ladder.
+    #[clap(
+        long = "rendition",
+        value_parser = add_transcode::parse_rendition,
+        conflicts_with = "ffmpeg_command"
+    )]
+    renditions: Vec<add_transcode::RenditionRequest>,
+
+    /// ffmpeg command run before the ffmpeg command, in the same directory, e.g. the first
+    /// pass of a two-pass encode. Repeat it for every pass, in order. The passes can use the
+    /// `{passlogfile}` placeholder for their statistics files, and discard their output with
+    /// `-f null -`.
+    #[clap(
+        long = "pass",
+        conflicts_with = "renditions",
+        allow_hyphen_values = true
+    )]
+    ffmpeg_passes: Vec<String>,
+
+    /// ffmpeg command run before the ffmpeg command of a rendition, as `name=<ffmpeg command>`.
+    #[clap(
+        long = "rendition-pass",
+        value_parser = add_transcode::parse_rendition,
+        requires = "renditions"
+    )]
+    rendition_passes: Vec<add_transcode::RenditionRequest>,
 
     /// Start flag, if set, the transcoding job will be queued to be processed immediately.
     #[clap(short, long, default_value = "false")]
     start: bool,
+
+    /// Worker variable used by the ffmpeg command, as `name` or `name=sample`.
+    /// Workers provide them through the `transcodeck_template_<name>` environment variables,
+    /// the sample value is only used for the dry-run.
+    #[clap(long = "var")]
+    variables: Vec<String>,
+
+    /// Dry-run flag, if set, the ffmpeg command is first tried against a small generated clip.
+    #[clap(long, default_value = "false")]
+    dry_run: bool,
+
+    /// Encoder the workers must support, in addition to the ones used by the ffmpeg command.
+    #[clap(long = "require-encoder")]
+    required_encoders: Vec<String>,
+
+    /// Tag the workers must have to process this job.
+    #[clap(long = "require-tag")]
+    required_tags: Vec<String>,
+
+    /// Minimum memory of the workers to process this job, e.g. `8G`.
+    #[clap(long, value_parser = worker::parse_size)]
+    min_memory: Option<i64>,
+
+    /// Priority of the job, jobs with a higher priority are processed first.
+    #[clap(short, long, default_value = "0", allow_negative_numbers = true)]
+    priority: i32,
+
+    /// The submitter of the job, used to share the workers fairly between submitters.
+    #[clap(long, env = "USER")]
+    submitter: Option<String>,
+
+    /// Deadline of the job, as a RFC 3339 date or a delay from now (e.g. `12h` or `2d`).
+    /// Jobs at risk of missing their deadline are processed first.
+    #[clap(long, value_parser = estimate::parse_deadline)]
+    deadline: Option<NaiveDateTime>,
+
+    /// Webhook URL notified of the state changes of this job, in addition to the global one.
+    #[clap(long = "webhook")]
+    webhook_url: Option<String>,
+
+    /// Seconds of the neighbouring fragments to encode before and after every fragment, as
+    /// context for the encoder. The overlap is trimmed when packaging the job.
+    #[clap(long, default_value = "0")]
+    overlap: f64,
+
+    /// Compare every transcoded fragment with its source using this metric, and store the score.
+    #[clap(long = "quality", value_enum)]
+    quality_metric: Option<model::QualityMetric>,
+
+    /// Minimum quality score, the fragments below it are flagged for review.
+    #[clap(long, requires = "quality_metric")]
+    quality_threshold: Option<f64>,
+
+    /// Fail the fragments below the quality threshold, instead of flagging them.
+    #[clap(long, default_value = "false", requires = "quality_threshold")]
+    fail_below_threshold: bool,
+
+    /// VMAF score to reach on every fragment, with the highest `{crf}` placeholder possible.
+    /// Workers search it for every fragment with short probe encodes, before the final encode.
+    #[clap(long)]
+    target_vmaf: Option<f64>,
+
+    /// Range of the search of the `{crf}` placeholder, as `min-max` (10-51 by default).
+    #[clap(long, value_parser = add_transcode::parse_crf_range, requires = "target_vmaf")]
+    crf_range: Option<(i32, i32)>,
+}
+
+#[derive(Parser, Debug)]
+pub struct PresetCommand {
+    #[clap(subcommand)]
+    cmd: PresetSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PresetSubcommand {
+    #[command(about = "List the presets")]
+    List,
+
+    #[command(about = "Show the settings of a preset, as JSON")]
+    Show {
+        /// The preset name
+        name: String,
+    },
+
+    #[command(about = "Add a preset, or replace its settings")]
+    Save {
+        /// The preset name
+        name: String,
+
+        /// JSON file of the settings, with the fields of a job request of the management API
+        /// (e.g. `ffmpeg_command`), `-` for stdin.
+        settings: PathBuf,
+    },
+
+    #[command(about = "Delete a preset")]
+    Delete {
+        /// The preset name
+        name: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct JobCommand {
+    #[clap(subcommand)]
+    cmd: JobSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum JobSubcommand {
+    #[command(about = "List the transcoding jobs")]
+    List {
+        /// Only list the jobs with this status.
+        #[clap(long, value_enum)]
+        status: Option<model::JobStatus>,
+    },
+
+    #[command(about = "Show a transcoding job and the progress of its fragments")]
+    Show {
+        /// The transcoding job ID
+        job_id: String,
+    },
+
+    #[command(about = "Show the logs of the failed fragments of a transcoding job")]
+    Logs {
+        /// The transcoding job ID, or a transcoding fragment job ID
+        job_id: String,
+    },
+
+    #[command(about = "Change the priority of a transcoding job")]
+    Priority {
+        /// The transcoding job ID
+        job_id: String,
+
+        /// The new priority, jobs with a higher priority are processed first.
+        #[clap(allow_negative_numbers = true)]
+        priority: i32,
+    },
+
+    #[command(about = "Queue a pending transcoding job")]
+    Queue {
+        /// The transcoding job ID
+        job_id: String,
+    },
+
+    #[command(about = "Cancel a transcoding job, fragment jobs in progress are not interrupted")]
+    Cancel {
+        /// The transcoding job ID
+        job_id: String,
+    },
+
+    #[command(about = "Show the quality scores of the fragments of a transcoding job")]
+    Quality {
+        /// The transcoding job ID
+        job_id: String,
+    },
+
+    #[command(about = "Queue again the failed and cancelled fragments of a transcoding job")]
+    Retry {
+        /// The transcoding job ID
+        job_id: String,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct WorkersCommand {
+    /// Also list the offline workers.
+    #[clap(short, long, default_value = "false")]
+    all: bool,
+
+    /// Show the encoders, filters and template variables of every worker.
+    #[clap(short, long, default_value = "false")]
+    verbose: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct WorkerCommand {
+    #[clap(subcommand)]
+    cmd: WorkerSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WorkerSubcommand {
+    #[command(about = "Stop a worker after its current fragment job")]
+    Drain {
+        /// The worker ID
+        worker_id: Uuid,
+    },
+
+    #[command(about = "Create a token for a worker to use the worker API")]
+    Token {
+        /// The worker ID, a new one is generated if not set.
+        worker_id: Option<Uuid>,
+    },
+
+    #[command(about = "Revoke all the tokens of a worker")]
+    Revoke {
+        /// The worker ID
+        worker_id: Uuid,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct MigrateCommand {
+    /// Only check that the database schema matches this binary, without migrating.
+    #[clap(long, default_value = "false")]
+    check: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct ServeCommand {
+    /// The address to listen on.
+    #[clap(long, env = "TRANSCODECK_LISTEN", default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Token of the management API (media, jobs...), which is disabled if not set.
+    #[clap(long, env = "TRANSCODECK_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+
+    /// Maximum number of database connections, shared by the API requests.
+    #[clap(long, env = "TRANSCODECK_DB_POOL_SIZE", default_value = "10")]
+    db_pool_size: u32,
+
+    /// Additional ffmpeg options (taking a value) allowed in the dry-runs of the management
+    /// API, which are always checked against the sandbox allow-list.
+    #[clap(
+        long = "allow-flag",
+        env = "TRANSCODECK_ALLOW_FLAGS",
+        value_delimiter = ','
+    )]
+    allow_flags: Vec<String>,
+
+    /// Additional codecs allowed in the dry-runs of the management API.
+    #[clap(
+        long = "allow-codec",
+        env = "TRANSCODECK_ALLOW_CODECS",
+        value_delimiter = ','
+    )]
+    allow_codecs: Vec<String>,
+
+    /// Additional filters allowed in the dry-runs of the management API.
+    #[clap(
+        long = "allow-filter",
+        env = "TRANSCODECK_ALLOW_FILTERS",
+        value_delimiter = ','
+    )]
+    allow_filters: Vec<String>,
+
+    #[clap(flatten)]
+    webhooks: WebhookOptions,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct WebhookOptions {
+    /// Webhook URL notified of the state changes of every transcoding job. The other commands
+    /// changing the jobs (e.g. `transcode`, or a daemon connected to the database) read it
+    /// from `TRANSCODECK_WEBHOOK_URL` or the configuration file: without it, they only record
+    /// the events of the jobs with their own webhook URL.
+    #[clap(long, env = "TRANSCODECK_WEBHOOK_URL")]
+    webhook_url: Option<String>,
+
+    /// Secret used to sign the webhook payloads, the HMAC-SHA256 of `<timestamp>.<payload>`
+    /// is sent in the `X-Transcodeck-Signature` header, and the timestamp (in seconds since
+    /// the UNIX epoch) in the `X-Transcodeck-Timestamp` header.
+    #[clap(long, env = "TRANSCODECK_WEBHOOK_SECRET", hide_env_values = true)]
+    webhook_secret: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct WebhookCommand {
+    #[clap(subcommand)]
+    cmd: WebhookSubcommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum WebhookSubcommand {
+    #[command(about = "Deliver the webhook notifications, when no `serve` instance does")]
+    Dispatch(WebhookOptions),
+
+    #[command(about = "Show the webhook deliveries, newest first")]
+    Log {
+        /// Only show the deliveries of this transcoding job.
+        #[clap(long = "job")]
+        job_id: Option<Uuid>,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct PackageCommand {
+    /// The transcoding job ID
+    job_id: Uuid,
+
+    /// Output directory of the daemons, where the transcoded fragments were gathered.
+    #[clap(env = "TRANSCODECK_OUTPUT_DIR")]
+    output_dir: PathBuf,
+
+    /// Where to write the playlists and segments, `<output_dir>/transcode-<job ID>/package`
+    /// by default.
+    #[clap(short, long)]
+    dest: Option<PathBuf>,
+
+    /// The streaming format to produce.
+    #[clap(short, long, value_enum, default_value_t)]
+    format: package::PackageFormat,
+
+    /// Container of the HLS segments.
+    #[clap(long, value_enum, default_value_t)]
+    segment_type: package::SegmentType,
+
+    /// Target duration of the segments, in seconds. Defaults to the duration the media was
+    /// fragmented at, so that the segments are aligned with the fragments.
+    #[clap(short, long)]
+    segment_duration: Option<f64>,
+
+    /// Storage the packaged output is uploaded to, under `transcode-<job ID>/`: a local
+    /// directory (`file:///srv/media`) or an HTTP server accepting PUT requests
+    /// (`https://cdn.example.com/media`).
+    #[clap(long, env = "TRANSCODECK_STORAGE")]
+    storage: Option<String>,
+
+    /// Bearer token of the HTTP storage.
+    #[clap(long, env = "TRANSCODECK_STORAGE_TOKEN", hide_env_values = true)]
+    storage_token: Option<String>,
 }
 
 // #[derive(Parser, Debug)]
@@ -92,34 +533,225 @@ pub struct TranscodeCommand {
 #[derive(Parser, Debug)]
 pub struct DaemonCommand {
     /// Output directory for transcoded media
+    #[clap(env = "TRANSCODECK_OUTPUT_DIR")]
     output_dir: PathBuf,
 
+    /// The worker ID to register as, a new one is generated if not set.
+    #[clap(long, env = "TRANSCODECK_WORKER_ID")]
+    worker_id: Option<Uuid>,
+
+    /// URL of a `transcodeck serve` instance, if set, the daemon uses its worker API
+    /// instead of connecting to the database. The worker ID is then bound to the token.
+    #[clap(long, env = "TRANSCODECK_API_URL", requires = "api_token")]
+    api_url: Option<String>,
+
+    /// Token of this worker for the worker API, see `transcodeck worker token`.
+    #[clap(long, env = "TRANSCODECK_API_TOKEN", hide_env_values = true)]
+    api_token: Option<String>,
+
+    /// Tag of this worker, only jobs requiring a subset of these tags will be processed.
+    #[clap(long = "tag", env = "TRANSCODECK_TAGS", value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Scheduling policy between jobs of the same priority.
+    #[clap(long, env = "TRANSCODECK_SCHEDULE", value_enum, default_value_t)]
+    schedule: scheduler::SchedulingPolicy,
+
+    /// Address to serve the Prometheus metrics on (at `/metrics`), e.g. `0.0.0.0:9090`.
+    #[clap(long, env = "TRANSCODECK_METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+
+    /// What to do on SIGINT or SIGTERM, a second signal always stops the daemon.
+    #[clap(long, env = "TRANSCODECK_SHUTDOWN", value_enum, default_value_t)]
+    shutdown: shutdown::ShutdownMode,
+
+    /// Number of fragment jobs processed at the same time, each by its own ffmpeg process.
+    /// The worker reports the one of its latest heartbeat as its current fragment job.
+    #[clap(
+        long,
+        env = "TRANSCODECK_CONCURRENCY",
+        default_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    concurrency: u32,
+
+    /// age identity file, used to decrypt the fragments encrypted without a key of their own,
+    /// e.g. to a recipient of the workers.
+    #[clap(
+        long = "identity-file",
+        env = "TRANSCODECK_IDENTITY_FILES",
+        value_delimiter = ','
+    )]
+    identity_files: Vec<PathBuf>,
+
     /// Reserve flag, should the daemon try to reserve more jobs than it can process?
     #[clap(short, long, default_value = "false")]
     reserve: bool,
+
+    /// Sandbox flag, if set, the ffmpeg arguments of every job are checked against an
+    /// allow-list, and can only read the job temporary directory and write to the output directory.
+    #[clap(long, env = "TRANSCODECK_SANDBOX", default_value = "false")]
+    sandbox: bool,
+
+    /// Additional ffmpeg options (taking a value) allowed in sandbox mode, e.g. `-aq-mode`.
+    #[clap(
+        long = "allow-flag",
+        env = "TRANSCODECK_ALLOW_FLAGS",
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    allow_flags: Vec<String>,
+
+    /// Additional codecs allowed in sandbox mode.
+    #[clap(
+        long = "allow-codec",
+        env = "TRANSCODECK_ALLOW_CODECS",
+        value_delimiter = ','
+    )]
+    allow_codecs: Vec<String>,
+
+    /// Additional filters allowed in sandbox mode.
+    #[clap(
+        long = "allow-filter",
+        env = "TRANSCODECK_ALLOW_FILTERS",
+        value_delimiter = ','
+    )]
+    allow_filters: Vec<String>,
+
+    /// Template variables of the configuration file, overridden by the
+    /// `transcodeck_template_<name>` environment variables.
+    #[clap(skip)]
+    template: HashMap<String, String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    if let Err(err) = dotenvy::dotenv() {
-        eprintln!("Error loading .env file: {}", err);
+    // Loaded before parsing the arguments, as they can be set by the environment.
+    let dotenv = dotenvy::dotenv();
+    let config = config::Config::load()?;
+    let matches = config.command(Args::command()).get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Command::Daemon(cmd) = &mut args.cmd {
+        cmd.template = config.daemon.template;
+    }
+    // Without a global webhook, only the events of the jobs with a webhook URL are recorded.
+    webhook::set_global_webhook(match &args.cmd {
+        Command::Serve(cmd) => cmd.webhooks.webhook_url.is_some(),
+        _ => config.webhooks.url.is_some() || std::env::var_os("TRANSCODECK_WEBHOOK_URL").is_some(),
+    });
+    logging::init(args.log_format);
+    if let Err(err) = dotenv {
+        tracing::warn!("Error loading .env file: {}", err);
     }
-    pretty_env_logger::init();
-    let args = Args::parse();
 
-    let mut db = PgConnection::establish(&args.db_uri)?;
     let ffmpeg_bin = args.ffmpeg_bin.clone();
+    let ffprobe_bin = args.ffprobe_bin.clone();
+    let connect = || match &args.db_uri {
+        Some(db_uri) => db::establish(db_uri),
+        None => anyhow::bail!("No database URI, set --db-uri or DATABASE_URL"),
+    };
 
     match args.cmd {
         Command::AddMedia(cmd) => {
-            add_media::add_media(&mut db, cmd, &ffmpeg_bin).await?;
+            add_media::add_media(&mut connect()?, cmd, &ffmpeg_bin, &ffprobe_bin).await?;
         }
-        Command::Daemon(cmd) => daemon::daemon(&mut db, cmd, &ffmpeg_bin).await?,
-        Command::ListMedia => {
-            println!("ListMedia");
+        Command::Daemon(cmd) => match (&cmd.api_url, &cmd.api_token) {
+            (Some(api_url), Some(api_token)) => {
+                let transports = (0..cmd.concurrency)
+                    .map(|_| transport::ApiTransport::new(api_url, api_token))
+                    .collect();
+                daemon::daemon(transports, cmd, &ffmpeg_bin, &ffprobe_bin).await?
+            }
+            _ => {
+                // A connection for every fragment job processed at the same time.
+                let mut connections = (0..cmd.concurrency)
+                    .map(|_| connect())
+                    .collect::<Result<Vec<_>>>()?;
+                migrate::check_schema(&mut connections[0])?;
+                let transports = connections
+                    .iter_mut()
+                    .map(transport::DbTransport::unchecked)
+                    .collect();
+                daemon::daemon(transports, cmd, &ffmpeg_bin, &ffprobe_bin).await?
+            }
+        },
+        Command::Job(cmd) => job::job(&mut connect()?, cmd).await?,
+        Command::Preset(cmd) => preset::preset(&mut connect()?, cmd).await?,
+        Command::Workers(cmd) => worker::workers(&mut connect()?, cmd).await?,
+        Command::Worker(cmd) => worker::worker(&mut connect()?, cmd).await?,
+        Command::Migrate(cmd) => migrate::migrate(&mut connect()?, cmd).await?,
+        Command::Serve(cmd) => match &args.db_uri {
+            Some(db_uri) => api::serve(db_uri, cmd, &ffmpeg_bin).await?,
+            None => anyhow::bail!("No database URI, set --db-uri or DATABASE_URL"),
+        },
+        Command::Webhook(cmd) => webhook::webhook(connect()?, cmd).await?,
+        Command::Package(cmd) => package::package(&mut connect()?, cmd, &ffmpeg_bin).await?,
+        Command::ListMedia => media::print_media(&mut connect()?).await?,
+        Command::ShowMedia { media_id } => media::show_media(&mut connect()?, &media_id).await?,
+        Command::Transcode(cmd) => {
+            add_transcode::new_transcode(&mut connect()?, cmd, &ffmpeg_bin).await?
         }
-        Command::Transcode(cmd) => add_transcode::new_transcode(&mut db, cmd).await?,
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(config: &str, args: &[&str]) -> Args {
+        let config: config::Config = toml::from_str(config).unwrap();
+        let matches = config
+            .command(Args::command())
+            .try_get_matches_from(args)
+            .unwrap();
+        Args::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn config_file_sets_defaults() {
+        let config = "[daemon]\noutput_dir = \"/srv/out\"\ntags = [\"gpu\", \"fast\"]\n\
+            concurrency = 3\nidentity_files = [\"/etc/transcodeck/a.key\", \"b.key\"]";
+        let Command::Daemon(cmd) = parse(config, &["transcodeck", "daemon"]).cmd else {
+            panic!("not a daemon command");
+        };
+        assert_eq!(cmd.output_dir, PathBuf::from("/srv/out"));
+        assert_eq!(cmd.tags, ["gpu", "fast"]);
+        assert_eq!(cmd.concurrency, 3);
+        assert_eq!(
+            cmd.identity_files,
+            [
+                PathBuf::from("/etc/transcodeck/a.key"),
+                PathBuf::from("b.key")
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_override_config_file() {
+        let config = "[daemon]\noutput_dir = \"/srv/out\"\ntags = [\"gpu\"]";
+        let args = ["transcodeck", "daemon", "/tmp/out", "--tag", "cpu"];
+        let Command::Daemon(cmd) = parse(config, &args).cmd else {
+            panic!("not a daemon command");
+        };
+        assert_eq!(cmd.output_dir, PathBuf::from("/tmp/out"));
+        assert_eq!(cmd.tags, ["cpu"]);
+    }
+
+    #[test]
+    fn config_file_applies_to_nested_subcommands() {
+        let config = "[webhooks]\nurl = \"http://localhost/hook\"";
+        let args = ["transcodeck", "webhook", "dispatch"];
+        let Command::Webhook(WebhookCommand {
+            cmd: WebhookSubcommand::Dispatch(options),
+        }) = parse(config, &args).cmd
+        else {
+            panic!("not a webhook dispatch command");
+        };
+        assert_eq!(
+            options.webhook_url.as_deref(),
+            Some("http://localhost/hook")
+        );
+    }
+}
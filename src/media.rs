@@ -0,0 +1,188 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::job::{self, JobSummary};
+use crate::{model, schema};
+
+/// Summary of a media, as listed by `list-media`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MediaSummary {
+    pub media_id: Uuid,
+    pub basename: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub fragments: usize,
+    /// Total duration of the fragments, in seconds, if known.
+    pub duration: Option<f64>,
+}
+
+/// A media, with its fragments and transcoding jobs.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MediaDetails {
+    pub media_id: Uuid,
+    pub basename: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub fragments: Vec<FragmentSummary>,
+    pub transcoding_jobs: Vec<JobSummary>,
+}
+
+/// A fragment of a media, without its encryption key.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FragmentSummary {
+    pub fragment_id: Uuid,
+    pub filename: String,
+    pub fragment_number: Option<i32>,
+    pub retrieval_url: Option<String>,
+    pub encrypted: bool,
+    /// Duration of the fragment, in seconds.
+    pub duration: Option<f64>,
+}
+
+/// List the media, newest first.
+pub fn list_media(db: &mut DbConnection) -> Result<Vec<MediaSummary>> {
+    let media = schema::media::table
+        .filter(schema::media::deleted_at.is_null())
+        .order(schema::media::created_at.desc())
+        .select(model::Media::as_select())
+        .load(db)?;
+
+    let mut fragments = HashMap::new();
+    for (media_id, duration) in schema::fragment::table
+        .filter(schema::fragment::deleted_at.is_null())
+        .select((schema::fragment::media_id, schema::fragment::duration))
+        .load::<(Uuid, Option<f64>)>(db)?
+    {
+        let (count, total) = fragments.entry(media_id).or_insert((0, Some(0.0)));
+        *count += 1;
+        *total = total
+            .zip(duration)
+            .map(|(total, duration)| total + duration);
+    }
+
+    Ok(media
+        .into_iter()
+        .map(|media| {
+            let (fragments, duration) =
+                fragments.get(&media.media_id).copied().unwrap_or((0, None));
+            MediaSummary {
+                media_id: media.media_id,
+                basename: media.basename,
+                created_at: media.created_at,
+                fragments,
+                duration,
+            }
+        })
+        .collect())
+}
+
+/// Load a media, with its fragments and transcoding jobs.
+pub fn media_details(db: &mut DbConnection, media_id: Uuid) -> Result<MediaDetails> {
+    let media = schema::media::table
+        .filter(schema::media::media_id.eq(media_id))
+        .select(model::Media::as_select())
+        .first(db)
+        .with_context(|| format!("Media not found: {}", media_id))?;
+    let fragments = schema::fragment::table
+        .filter(schema::fragment::media_id.eq(media_id))
+        .filter(schema::fragment::deleted_at.is_null())
+        .order((
+            schema::fragment::fragment_number.asc(),
+            schema::fragment::filename.asc(),
+        ))
+        .select(model::Fragment::as_select())
+        .load(db)?;
+    let transcoding_jobs = job::list_jobs(db, None)?
+        .into_iter()
+        .filter(|job| job.media_id == media_id)
+        .collect();
+
+    Ok(MediaDetails {
+        media_id: media.media_id,
+        basename: media.basename,
+        created_at: media.created_at,
+        updated_at: media.updated_at,
+        fragments: fragments
+            .into_iter()
+            .map(|fragment| FragmentSummary {
+                fragment_id: fragment.fragment_id,
+                filename: fragment.filename,
+                fragment_number: fragment.fragment_number,
+                retrieval_url: fragment.retrieval_url,
+                encrypted: fragment.encryption_key.is_some(),
+                duration: fragment.duration,
+            })
+            .collect(),
+        transcoding_jobs,
+    })
+}
+
+pub async fn print_media(db: &mut DbConnection) -> Result<()> {
+    println!(
+        "{:<36} {:<32} {:>9} {:>10} {:<19}",
+        "MEDIA", "NAME", "FRAGMENTS", "DURATION", "CREATED"
+    );
+    for media in list_media(db)? {
+        println!(
+            "{:<36} {:<32} {:>9} {:>10} {:<19}",
+            media.media_id,
+            media.basename.as_deref().unwrap_or("-"),
+            media.fragments,
+            media
+                .duration
+                .map(job::format_duration)
+                .unwrap_or_else(|| "-".into()),
+            media.created_at.format("%Y-%m-%d %H:%M:%S"),
+        );
+    }
+    Ok(())
+}
+
+pub async fn show_media(db: &mut DbConnection, media_id: &str) -> Result<()> {
+    let media = media_details(db, Uuid::parse_str(media_id)?)?;
+
+    println!("Media: {}", media.media_id);
+    if let Some(basename) = &media.basename {
+        println!("  Name: {}", basename);
+    }
+    println!("  Created: {}", media.created_at);
+    println!("  Updated: {}", media.updated_at);
+    println!();
+
+    println!(
+        "{:<36} {:<24} {:>10} {:<9} RETRIEVAL URL",
+        "FRAGMENT", "FILENAME", "DURATION", "ENCRYPTED"
+    );
+    for fragment in &media.fragments {
+        println!(
+            "{:<36} {:<24} {:>10} {:<9} {}",
+            fragment.fragment_id,
+            fragment.filename,
+            fragment
+                .duration
+                .map(job::format_duration)
+                .unwrap_or_else(|| "-".into()),
+            if fragment.encrypted { "yes" } else { "no" },
+            fragment.retrieval_url.as_deref().unwrap_or("-"),
+        );
+    }
+
+    if !media.transcoding_jobs.is_empty() {
+        println!();
+        println!("{:<36} {:<12} {:>9}", "JOB", "STATUS", "FRAGMENTS");
+        for job in &media.transcoding_jobs {
+            println!(
+                "{:<36} {:<12} {:>9}",
+                job.transcoding_job_id,
+                format!("{:?}", job.status),
+                format!("{}/{}", job.completed_fragments, job.total_fragments),
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use prometheus::core::Collector;
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::transport::QueueDepth;
+
+/// Metrics of a daemon, exposed in the Prometheus text format on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub fragments_processed: IntCounter,
+    pub fragments_failed: IntCounter,
+    pub fragments_retried: IntCounter,
+    pub download_bytes: IntCounter,
+    pub download_duration: Histogram,
+    pub decrypt_duration: Histogram,
+    pub encode_duration: Histogram,
+    pub encode_speed: Histogram,
+    queue_depth: IntGaugeVec,
+    last_claim: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("transcodeck".into()), None)?;
+        let metrics = Metrics {
+            fragments_processed: IntCounter::new(
+                "fragments_processed_total",
+                "Fragment jobs transcoded by this worker",
+            )?,
+            fragments_failed: IntCounter::new(
+                "fragments_failed_total",
+                "Fragment jobs failed on this worker",
+            )?,
+            fragments_retried: IntCounter::new(
+                "fragments_retried_total",
+                "Fragment jobs interrupted and returned to the queue to be retried",
+            )?,
+            download_bytes: IntCounter::new(
+                "download_bytes_total",
+                "Bytes of media fragments downloaded",
+            )?,
+            download_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "download_duration_seconds",
+                    "Time to download a media fragment",
+                )
+                .buckets(exponential_buckets(0.1, 2.0, 12)?),
+            )?,
+            decrypt_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "decrypt_duration_seconds",
+                    "Time to decrypt a media fragment",
+                )
+                .buckets(exponential_buckets(0.05, 2.0, 12)?),
+            )?,
+            encode_duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "encode_duration_seconds",
+                    "Time spent by ffmpeg to transcode a media fragment",
+                )
+                .buckets(exponential_buckets(1.0, 2.0, 14)?),
+            )?,
+            encode_speed: Histogram::with_opts(
+                HistogramOpts::new(
+                    "encode_speed_ratio",
+                    "Average speed of the transcoding, relative to the duration of the fragment",
+                )
+                .buckets(exponential_buckets(0.125, 2.0, 10)?),
+            )?,
+            queue_depth: IntGaugeVec::new(
+                Opts::new(
+                    "queue_depth",
+                    "Fragment jobs by status, as last seen by this worker",
+                ),
+                &["status"],
+            )?,
+            last_claim: GaugeVec::new(
+                Opts::new(
+                    "worker_last_claim_timestamp_seconds",
+                    "Time of the last fragment job claimed by the worker",
+                ),
+                &["worker_id"],
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 10] = [
+            Box::new(metrics.fragments_processed.clone()),
+            Box::new(metrics.fragments_failed.clone()),
+            Box::new(metrics.fragments_retried.clone()),
+            Box::new(metrics.download_bytes.clone()),
+            Box::new(metrics.download_duration.clone()),
+            Box::new(metrics.decrypt_duration.clone()),
+            Box::new(metrics.encode_duration.clone()),
+            Box::new(metrics.encode_speed.clone()),
+            Box::new(metrics.queue_depth.clone()),
+            Box::new(metrics.last_claim.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Replace the queue depths, the statuses without fragment jobs are dropped.
+    pub fn set_queue_depth(&self, depths: &[QueueDepth]) {
+        self.queue_depth.reset();
+        for depth in depths {
+            let status = serde_json::to_value(&depth.status).unwrap_or_default();
+            self.queue_depth
+                .with_label_values(&[status.as_str().unwrap_or_default()])
+                .set(depth.count);
+        }
+    }
+
+    /// Record that a worker claimed a fragment job now.
+    pub fn claimed(&self, worker_id: Uuid) {
+        self.last_claim
+            .with_label_values(&[&worker_id.to_string()])
+            .set(chrono::Utc::now().timestamp_millis() as f64 / 1000.0);
+    }
+
+    fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+/// Serve the metrics on `/metrics` until the process stops.
+pub async fn serve(metrics: Arc<Metrics>, listener: tokio::net::TcpListener) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+async fn metrics_handler(State(metrics): State<Arc<Metrics>>) -> Response {
+    match metrics.encode() {
+        Ok(body) => (
+            [(header::CONTENT_TYPE, TextEncoder::new().format_type())],
+            body,
+        )
+            .into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
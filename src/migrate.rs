@@ -0,0 +1,99 @@
+use anyhow::{anyhow, bail, Result};
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+use crate::db::DbConnection;
+use crate::MigrateCommand;
+
+/// Migrations of the `migrations` directory, embedded at build time.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+/// Migrations of the `migrations_sqlite` directory, embedded at build time.
+#[cfg(feature = "sqlite")]
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+pub async fn migrate(db: &mut DbConnection, cmd: MigrateCommand) -> Result<()> {
+    if cmd.check {
+        check_schema(db)?;
+        println!("Database schema is up to date.");
+        return Ok(());
+    }
+
+    match db {
+        DbConnection::Pg(db) => run_migrations(db, MIGRATIONS),
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(db) => run_migrations(db, SQLITE_MIGRATIONS),
+    }
+}
+
+/// Check that the database schema matches the migrations of this binary.
+///
+/// Fails if migrations are pending, or if the database has migrations unknown to this
+/// binary (i.e. it was migrated by a newer version of transcodeck).
+pub fn check_schema(db: &mut DbConnection) -> Result<()> {
+    match db {
+        DbConnection::Pg(db) => check_migrations(db, MIGRATIONS),
+        #[cfg(feature = "sqlite")]
+        DbConnection::Sqlite(db) => check_migrations(db, SQLITE_MIGRATIONS),
+    }
+}
+
+fn run_migrations<DB: Backend>(
+    db: &mut impl MigrationHarness<DB>,
+    migrations: EmbeddedMigrations,
+) -> Result<()>
+where
+    EmbeddedMigrations: MigrationSource<DB>,
+{
+    let applied = db
+        .run_pending_migrations(migrations)
+        .map_err(|err| anyhow!("Failed to run the migrations: {}", err))?;
+    if applied.is_empty() {
+        println!("Database schema is up to date.");
+    }
+    for version in applied {
+        println!("Applied migration: {}", version);
+    }
+    Ok(())
+}
+
+fn check_migrations<DB: Backend>(
+    db: &mut impl MigrationHarness<DB>,
+    migrations: EmbeddedMigrations,
+) -> Result<()>
+where
+    EmbeddedMigrations: MigrationSource<DB>,
+{
+    let known = MigrationSource::<DB>::migrations(&migrations)
+        .map_err(|err| anyhow!("Failed to load the embedded migrations: {}", err))?;
+    let applied = db
+        .applied_migrations()
+        .map_err(|err| anyhow!("Failed to list the applied migrations: {}", err))?;
+
+    let pending = known
+        .iter()
+        .filter(|m| !applied.iter().any(|version| *version == m.name().version()))
+        .map(|m| m.name().to_string())
+        .collect::<Vec<_>>();
+    if !pending.is_empty() {
+        bail!(
+            "Database schema is outdated, {} migration(s) pending: {}. Run `transcodeck migrate`.",
+            pending.len(),
+            pending.join(", ")
+        );
+    }
+
+    let unknown = applied
+        .iter()
+        .filter(|version| !known.iter().any(|m| m.name().version() == **version))
+        .map(|version| version.to_string())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        bail!(
+            "Database schema is newer than this binary, unknown migration(s): {}. Upgrade transcodeck.",
+            unknown.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -1,11 +1,12 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Queryable, Selectable, AsChangeset)]
 #[diesel(table_name = crate::schema::fragment)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Fragment {
     pub fragment_id: Uuid,
     pub media_id: Uuid,
@@ -16,15 +17,18 @@ pub struct Fragment {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub duration: Option<f64>,
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::fragment)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
 pub struct NewFragment {
     pub media_id: Uuid,
     pub filename: String,
     pub fragment_number: Option<i32>,
     pub encryption_key: Option<String>,
     pub retrieval_url: Option<String>,
+    pub duration: Option<f64>,
 }
@@ -17,6 +17,7 @@ pub struct Media {
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::media)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
 pub struct NewMedia {
     pub basename: Option<String>,
 }
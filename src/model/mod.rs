@@ -1,9 +1,21 @@
 pub mod fragment;
 pub mod media;
+pub mod preset;
 pub mod transcoding_fragment;
+pub mod transcoding_fragment_log;
 pub mod transcoding_job;
+pub mod transcoding_rendition;
+pub mod webhook;
+pub mod worker;
+pub mod worker_token;
 
 pub use fragment::*;
 pub use media::*;
+pub use preset::*;
 pub use transcoding_fragment::*;
+pub use transcoding_fragment_log::*;
 pub use transcoding_job::*;
+pub use transcoding_rendition::*;
+pub use webhook::*;
+pub use worker::*;
+pub use worker_token::*;
@@ -1,11 +1,13 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 #[derive(Queryable, Selectable, AsChangeset)]
 #[diesel(table_name = crate::schema::transcoding_fragment_job)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TranscodingFragmentJob {
     pub transcoding_fragment_job_id: Uuid,
     pub transcoding_job_id: Uuid,
@@ -14,6 +16,27 @@ pub struct TranscodingFragmentJob {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub progress: Option<f64>,
+    pub fps: Option<f64>,
+    pub speed: Option<f64>,
+    pub bitrate: Option<f64>,
+    pub started_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub encode_time: Option<f64>,
+    pub output_size: Option<i64>,
+    pub average_speed: Option<f64>,
+    pub worker_id: Option<Uuid>,
+    /// Start of the fragment in the output, after the lead-in of the previous fragment.
+    pub trim_start: Option<f64>,
+    /// End of the fragment in the output, before the lead-out of the next fragment.
+    pub trim_end: Option<f64>,
+    /// Quality score of the output with the job quality metric, the lowest one of the
+    /// renditions.
+    pub quality_score: Option<f64>,
+    /// Whether the quality score is below the job threshold.
+    pub quality_flagged: bool,
+    /// CRF chosen for the fragment, if the job has a target quality.
+    pub crf: Option<i32>,
 }
 
 #[derive(Queryable)]
@@ -29,15 +52,45 @@ pub struct JobResume {
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::transcoding_fragment_job)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
 pub struct NewTranscodingFragmentJob {
     pub transcoding_job_id: Uuid,
     pub fragment_id: Uuid,
     pub status: FragmentJobStatus,
 }
 
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::transcoding_fragment_job)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FragmentJobProgress {
+    pub progress: Option<f64>,
+    pub fps: Option<f64>,
+    pub speed: Option<f64>,
+    pub bitrate: Option<f64>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = crate::schema::transcoding_fragment_job)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FragmentJobStats {
+    pub encode_time: Option<f64>,
+    pub output_size: Option<i64>,
+    pub average_speed: Option<f64>,
+    pub trim_start: Option<f64>,
+    pub trim_end: Option<f64>,
+    pub quality_score: Option<f64>,
+    #[serde(default)]
+    pub quality_flagged: bool,
+    pub crf: Option<i32>,
+}
+
 #[derive(diesel_derive_enum::DbEnum)]
 #[ExistingTypePath = "crate::schema::sql_types::FragmentJobStatus"]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum FragmentJobStatus {
     Pending,
     Queued,
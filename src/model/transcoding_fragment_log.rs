@@ -0,0 +1,27 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::transcoding_fragment_job_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscodingFragmentJobLog {
+    pub transcoding_fragment_job_log_id: Uuid,
+    pub transcoding_fragment_job_id: Uuid,
+    pub exit_status: Option<i32>,
+    pub message: String,
+    pub log: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::transcoding_fragment_job_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewTranscodingFragmentJobLog {
+    pub transcoding_fragment_job_id: Uuid,
+    pub exit_status: Option<i32>,
+    pub message: String,
+    pub log: String,
+}
@@ -1,11 +1,13 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 #[derive(Queryable, Selectable, AsChangeset)]
 #[diesel(table_name = crate::schema::transcoding_job)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
 pub struct TranscodingJob {
     pub transcoding_job_id: Uuid,
     pub media_id: Uuid,
@@ -14,20 +16,94 @@ pub struct TranscodingJob {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted_at: Option<NaiveDateTime>,
+    pub required_encoders: Vec<String>,
+    pub required_variables: Vec<String>,
+    pub required_tags: Vec<String>,
+    pub min_memory: Option<i64>,
+    pub priority: i32,
+    pub submitter: Option<String>,
+    pub deadline: Option<NaiveDateTime>,
+    pub webhook_url: Option<String>,
+    /// Seconds of the neighbouring fragments encoded before and after every fragment.
+    pub overlap: f64,
+    /// ffmpeg commands run before `ffmpeg_command`, in order, e.g. the first pass of a
+    /// two-pass encode.
+    pub ffmpeg_passes: Vec<String>,
+    /// Metric of the quality check of the transcoded fragments, against their source.
+    pub quality_metric: Option<QualityMetric>,
+    /// Minimum quality score of the fragments, the ones below it are flagged for review.
+    pub quality_threshold: Option<f64>,
+    /// Whether the fragments below the threshold are failed, instead of flagged.
+    pub fail_below_threshold: bool,
+    /// VMAF score targeted by the search of the `{crf}` of every fragment, if any.
+    pub target_vmaf: Option<f64>,
+    /// Range of the search of the `{crf}`.
+    pub crf_min: i32,
+    pub crf_max: i32,
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::transcoding_job)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
 pub struct NewTranscodingJob {
     pub media_id: Uuid,
     pub ffmpeg_command: String,
     pub status: JobStatus,
+    pub required_encoders: Vec<String>,
+    pub required_variables: Vec<String>,
+    pub required_tags: Vec<String>,
+    pub min_memory: Option<i64>,
+    pub priority: i32,
+    pub submitter: Option<String>,
+    pub deadline: Option<NaiveDateTime>,
+    pub webhook_url: Option<String>,
+    pub overlap: f64,
+    pub ffmpeg_passes: Vec<String>,
+    pub quality_metric: Option<QualityMetric>,
+    pub quality_threshold: Option<f64>,
+    pub fail_below_threshold: bool,
+    pub target_vmaf: Option<f64>,
+    pub crf_min: i32,
+    pub crf_max: i32,
+}
+
+/// Requirements of a transcoding job on the workers processing it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JobRequirements<'a> {
+    pub required_encoders: &'a [String],
+    pub required_variables: &'a [String],
+    pub required_tags: &'a [String],
+    pub min_memory: Option<i64>,
+}
+
+impl TranscodingJob {
+    pub fn requirements(&self) -> JobRequirements<'_> {
+        JobRequirements {
+            required_encoders: &self.required_encoders,
+            required_variables: &self.required_variables,
+            required_tags: &self.required_tags,
+            min_memory: self.min_memory,
+        }
+    }
+}
+
+impl NewTranscodingJob {
+    pub fn requirements(&self) -> JobRequirements<'_> {
+        JobRequirements {
+            required_encoders: &self.required_encoders,
+            required_variables: &self.required_variables,
+            required_tags: &self.required_tags,
+            min_memory: self.min_memory,
+        }
+    }
 }
 
 #[derive(diesel_derive_enum::DbEnum)]
 #[ExistingTypePath = "crate::schema::sql_types::JobStatus"]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
 pub enum JobStatus {
     Pending,
     Queued,
@@ -37,3 +113,18 @@ pub enum JobStatus {
     Cancelled,
     Deleted,
 }
+
+/// Full-reference quality metrics, computed by the ffmpeg filter of the same name.
+#[derive(diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::QualityMetric"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+#[value(rename_all = "snake_case")]
+pub enum QualityMetric {
+    /// `libvmaf`, from 0 to 100.
+    Vmaf,
+    /// `ssim`, from 0 to 1.
+    Ssim,
+    /// `psnr`, in dB.
+    Psnr,
+}
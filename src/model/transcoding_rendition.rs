@@ -0,0 +1,34 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A named output of a transcoding job, e.g. one step of an adaptive bitrate ladder.
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::transcoding_rendition)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TranscodingRendition {
+    pub transcoding_rendition_id: Uuid,
+    pub transcoding_job_id: Uuid,
+    pub name: String,
+    pub ffmpeg_command: String,
+    /// Order of the rendition in the job, the renditions are transcoded in this order.
+    pub position: i32,
+    pub created_at: NaiveDateTime,
+    /// ffmpeg commands run before `ffmpeg_command`, in order.
+    pub ffmpeg_passes: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::transcoding_rendition)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewTranscodingRendition {
+    pub transcoding_job_id: Uuid,
+    pub name: String,
+    pub ffmpeg_command: String,
+    pub position: i32,
+    pub ffmpeg_passes: Vec<String>,
+}
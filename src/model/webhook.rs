@@ -0,0 +1,79 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{FragmentJobStatus, JobStatus};
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::webhook_event)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookEvent {
+    pub webhook_event_id: Uuid,
+    pub event: String,
+    pub transcoding_job_id: Uuid,
+    pub transcoding_fragment_job_id: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub dispatched_at: Option<NaiveDateTime>,
+    /// Status of the job, and of the fragment job, after the event.
+    pub job_status: JobStatus,
+    pub completed_fragments: i32,
+    pub total_fragments: i32,
+    pub fragment_status: Option<FragmentJobStatus>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhook_event)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewWebhookEvent {
+    pub event: String,
+    pub transcoding_job_id: Uuid,
+    pub transcoding_fragment_job_id: Option<Uuid>,
+    pub job_status: JobStatus,
+    pub completed_fragments: i32,
+    pub total_fragments: i32,
+    pub fragment_status: Option<FragmentJobStatus>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::webhook_delivery)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub webhook_delivery_id: Uuid,
+    pub webhook_event_id: Uuid,
+    pub url: String,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+    pub next_attempt_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::webhook_delivery)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
+pub struct NewWebhookDelivery {
+    pub webhook_event_id: Uuid,
+    pub url: String,
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub next_attempt_at: NaiveDateTime,
+}
+
+#[derive(diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::WebhookDeliveryStatus"]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
@@ -0,0 +1,45 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = crate::schema::worker)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Worker {
+    pub worker_id: Uuid,
+    pub hostname: String,
+    pub ffmpeg_version: Option<String>,
+    pub encoders: Vec<String>,
+    pub filters: Vec<String>,
+    pub cpu_count: i32,
+    pub template_variables: serde_json::Value,
+    pub free_disk_space: Option<i64>,
+    pub current_fragment_job_id: Option<Uuid>,
+    pub started_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub tags: Vec<String>,
+    pub memory: Option<i64>,
+    pub draining: bool,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::worker)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_default_value = false)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewWorker {
+    pub worker_id: Uuid,
+    pub hostname: String,
+    pub ffmpeg_version: Option<String>,
+    pub encoders: Vec<String>,
+    pub filters: Vec<String>,
+    pub cpu_count: i32,
+    pub template_variables: serde_json::Value,
+    pub free_disk_space: Option<i64>,
+    pub tags: Vec<String>,
+    pub memory: Option<i64>,
+}
@@ -0,0 +1,152 @@
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+use crate::package::{concat_list, ConcatInput};
+use crate::probe;
+
+/// A fragment with the end of the previous fragment before it and the start of the next one
+/// after it, so that the encoder gets some context around the fragment boundaries.
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// The media to transcode, instead of the fragment.
+    pub path: PathBuf,
+    /// Duration of the context, in seconds.
+    pub duration: f64,
+    /// Start of the fragment in the context, in seconds.
+    pub trim_start: f64,
+    /// End of the fragment in the context, in seconds.
+    pub trim_end: f64,
+}
+
+/// Build the context of `fragment` in `dir`, with up to `overlap` seconds of its neighbours.
+///
+/// The neighbours are cut without re-encoding, so the lead-in starts at the last keyframe
+/// at least `overlap` seconds before the end of the previous fragment, and can be longer.
+pub async fn context(
+    ffmpeg_bin: &str,
+    ffprobe_bin: &str,
+    fragment: &Path,
+    previous: Option<&Path>,
+    next: Option<&Path>,
+    overlap: f64,
+    dir: &Path,
+) -> Result<Context> {
+    let fragment_duration = probe::duration(ffprobe_bin, fragment).await?;
+    let mut inputs = Vec::new();
+
+    let mut trim_start = 0.0;
+    if let Some(previous) = previous {
+        let previous_duration = probe::duration(ffprobe_bin, previous).await?;
+        let lead_in = dir.join("lead-in.mkv");
+        let start = (previous_duration - overlap).max(0.0);
+        cut(ffmpeg_bin, previous, &lead_in, Some(start), None).await?;
+        trim_start = probe::duration(ffprobe_bin, &lead_in).await?;
+        inputs.push(lead_in);
+    }
+    inputs.push(fragment.to_path_buf());
+    if let Some(next) = next {
+        let lead_out = dir.join("lead-out.mkv");
+        cut(ffmpeg_bin, next, &lead_out, None, Some(overlap)).await?;
+        inputs.push(lead_out);
+    }
+
+    let path = dir.join("context.mkv");
+    let list = dir.join("context.txt");
+    let inputs = inputs
+        .into_iter()
+        .map(|path| ConcatInput {
+            path,
+            inpoint: None,
+            outpoint: None,
+        })
+        .collect::<Vec<_>>();
+    tokio::fs::write(&list, concat_list(&inputs)?).await?;
+    let status = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y")
+        .arg("-f")
+        .arg("concat")
+        .arg("-safe")
+        .arg("0")
+        .arg("-i")
+        .arg(&list)
+        .arg("-c")
+        .arg("copy")
+        .arg(&path)
+        .status()
+        .await?;
+    if !status.success() {
+        bail!("Failed to join the overlap: status={:?}", status.code());
+    }
+
+    Ok(Context {
+        duration: probe::duration(ffprobe_bin, &path).await?,
+        path,
+        trim_start,
+        trim_end: trim_start + fragment_duration,
+    })
+}
+
+/// Copy the video of `input` into `output`, from `start` (seeking to the keyframe before it)
+/// and for `duration` seconds.
+pub(crate) async fn cut(
+    ffmpeg_bin: &str,
+    input: &Path,
+    output: &Path,
+    start: Option<f64>,
+    duration: Option<f64>,
+) -> Result<()> {
+    let mut command = Command::new(ffmpeg_bin);
+    command
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y");
+    if let Some(start) = start {
+        command.arg("-ss").arg(start.to_string());
+    }
+    command.arg("-i").arg(input);
+    if let Some(duration) = duration {
+        command.arg("-t").arg(duration.to_string());
+    }
+    let status = command
+        .arg("-map")
+        .arg("0:v")
+        .arg("-c")
+        .arg("copy")
+        .arg(output)
+        .status()
+        .await?;
+    if !status.success() {
+        bail!(
+            "Failed to cut {}: status={:?}",
+            input.display(),
+            status.code()
+        );
+    }
+    Ok(())
+}
+
+/// Add a keyframe at both ends of the fragment to the ffmpeg arguments, so that the overlap
+/// can be trimmed without re-encoding. The option is inserted before the output, or else
+/// before the last argument, e.g. the discarded output of a first pass.
+pub fn force_key_frames(args: &[String], output: &Path, context: &Context) -> Vec<String> {
+    let output = output.to_string_lossy();
+    let position = args
+        .iter()
+        .rposition(|arg| *arg == output)
+        .or_else(|| args.iter().rposition(|arg| arg.contains(output.as_ref())))
+        .unwrap_or(args.len().saturating_sub(1));
+    let mut args = args.to_vec();
+    args.splice(
+        position..position,
+        [
+            "-force_key_frames".to_string(),
+            format!("{},{}", context.trim_start, context.trim_end),
+        ],
+    );
+    args
+}
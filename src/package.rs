@@ -0,0 +1,451 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use diesel::prelude::*;
+use std::path::{Path, PathBuf};
+use tempdir::TempDir;
+use tokio::process::Command;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::storage::Storage;
+use crate::{job, model, schema, webhook, PackageCommand};
+use model::{FragmentJobStatus, TranscodingJob};
+
+/// Streaming formats produced by `package`.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PackageFormat {
+    /// HLS playlist, in `hls/index.m3u8`.
+    #[default]
+    Hls,
+    /// DASH manifest, in `dash/manifest.mpd`.
+    Dash,
+    /// Both HLS and DASH, from the same ffmpeg run.
+    Both,
+}
+
+/// Container of the HLS segments, DASH segments are always fragmented MP4.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SegmentType {
+    #[default]
+    Fmp4,
+    Ts,
+}
+
+pub async fn package(db: &mut DbConnection, cmd: PackageCommand, ffmpeg_bin: &str) -> Result<()> {
+    if cmd.format != PackageFormat::Hls && cmd.segment_type == SegmentType::Ts {
+        bail!("DASH segments cannot be MPEG-TS, use --segment-type fmp4");
+    }
+
+    let job = schema::transcoding_job::table
+        .filter(schema::transcoding_job::transcoding_job_id.eq(cmd.job_id))
+        .select(TranscodingJob::as_select())
+        .first(db)
+        .with_context(|| format!("Transcoding job not found: {}", cmd.job_id))?;
+    let fragments = schema::transcoding_fragment_job::table
+        .inner_join(schema::fragment::table)
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job.transcoding_job_id))
+        .order((
+            schema::fragment::fragment_number.asc(),
+            schema::fragment::filename.asc(),
+        ))
+        .select((
+            schema::transcoding_fragment_job::status,
+            (
+                schema::transcoding_fragment_job::trim_start,
+                schema::transcoding_fragment_job::trim_end,
+            ),
+            model::Fragment::as_select(),
+        ))
+        .load::<(FragmentJobStatus, Trim, model::Fragment)>(db)?;
+    if fragments.is_empty() {
+        bail!(
+            "Transcoding job has no fragments: {}",
+            job.transcoding_job_id
+        );
+    }
+    let completed = fragments
+        .iter()
+        .filter(|(status, _, _)| *status == FragmentJobStatus::Completed)
+        .count();
+    if completed < fragments.len() {
+        bail!(
+            "Transcoding job is not completed: {} of {} fragments",
+            completed,
+            fragments.len()
+        );
+    }
+
+    // The fragments are transcoded by the daemons into `<output_dir>/transcode-<job ID>/`,
+    // and into a sub-directory per rendition.
+    let job_dir = job_dir(&cmd.output_dir, job.transcoding_job_id);
+    let names = job::renditions(db, job.transcoding_job_id)?
+        .into_iter()
+        .map(|rendition| rendition.name)
+        .collect::<Vec<_>>();
+    let variants = if names.is_empty() {
+        vec![None]
+    } else {
+        names.iter().cloned().map(Some).collect()
+    };
+    let mut renditions = Vec::new();
+    for name in variants {
+        let dir = match &name {
+            Some(name) => job_dir.join(name),
+            None => job_dir.clone(),
+        };
+        let mut inputs = Vec::new();
+        for (_, (trim_start, trim_end), fragment) in &fragments {
+            let path = output_path(&dir, &fragment.filename);
+            if !path.is_file() {
+                bail!(
+                    "Transcoded fragment not found: {} (the outputs of every worker must be gathered in {})",
+                    path.display(),
+                    job_dir.display()
+                );
+            }
+            // Jobs with an overlap encode a part of the neighbours of every fragment.
+            inputs.push(ConcatInput {
+                path,
+                inpoint: *trim_start,
+                outpoint: *trim_end,
+            });
+        }
+        renditions.push(RenditionInput {
+            name,
+            fragments: inputs,
+        });
+    }
+
+    let durations = fragments
+        .iter()
+        .map(|(_, _, fragment)| fragment.duration)
+        .collect::<Option<Vec<_>>>();
+    let segment_duration = match (cmd.segment_duration, &durations) {
+        (Some(segment_duration), _) => segment_duration,
+        (None, Some(durations)) => nominal_duration(durations),
+        (None, None) => bail!("The fragment durations are unknown, set --segment-duration"),
+    };
+    if segment_duration <= 0.0 {
+        bail!("Invalid segment duration: {}", segment_duration);
+    }
+    if let Some(durations) = &durations {
+        let nominal = nominal_duration(durations);
+        let ratio = nominal / segment_duration;
+        if fragments.len() > 1 && (ratio - ratio.round()).abs() > 0.05 {
+            warn!(
+                "The segment duration ({}s) does not divide the fragment duration ({}s), segments will not be aligned with the fragments",
+                segment_duration, nominal
+            );
+        }
+    }
+
+    let dest = cmd.dest.unwrap_or_else(|| job_dir.join("package"));
+    let options = PackageOptions {
+        format: cmd.format,
+        segment_type: cmd.segment_type,
+        segment_duration,
+    };
+    println!(
+        "Packaging {} fragments into {} ({}s segments)",
+        fragments.len(),
+        dest.display(),
+        segment_duration
+    );
+    if renditions.len() > 1 {
+        println!("Renditions: {}", names.join(", "));
+    }
+    package_fragments(ffmpeg_bin, &renditions, &dest, &options).await?;
+
+    if options.format != PackageFormat::Dash && renditions.len() > 1 {
+        println!(
+            "HLS master playlist: {}",
+            dest.join("hls/master.m3u8").display()
+        );
+    } else if options.format != PackageFormat::Dash {
+        println!("HLS playlist: {}", dest.join("hls/index.m3u8").display());
+    }
+    if options.format != PackageFormat::Hls {
+        println!(
+            "DASH manifest: {}",
+            dest.join("dash/manifest.mpd").display()
+        );
+    }
+
+    if let Some(url) = &cmd.storage {
+        let storage = Storage::new(url, cmd.storage_token.clone())?;
+        let prefix = job_dir_name(job.transcoding_job_id);
+        let uploaded = storage.upload_dir(&dest, &prefix).await?;
+        println!(
+            "Uploaded {} files to {}",
+            uploaded,
+            storage.location(&prefix)
+        );
+    }
+    webhook::record(
+        db,
+        webhook::Event::JobAssembled,
+        job.transcoding_job_id,
+        None,
+    )?;
+    Ok(())
+}
+
+/// Start and end of a fragment in its transcoded output, if it has an overlap.
+type Trim = (Option<f64>, Option<f64>);
+
+/// Settings of the packaging of a transcoding job.
+#[derive(Debug, Clone)]
+pub struct PackageOptions {
+    pub format: PackageFormat,
+    pub segment_type: SegmentType,
+    /// Target duration of the segments, in seconds.
+    pub segment_duration: f64,
+}
+
+/// Directory of the outputs of a transcoding job, in the output directory of the daemons.
+pub fn job_dir(output_dir: &Path, transcoding_job_id: Uuid) -> PathBuf {
+    output_dir.join(job_dir_name(transcoding_job_id))
+}
+
+/// Name of the directory of a transcoding job, also its prefix in the storage.
+fn job_dir_name(transcoding_job_id: Uuid) -> String {
+    format!("transcode-{}", transcoding_job_id.as_hyphenated())
+}
+
+/// Path of a transcoded fragment, in the directory of its transcoding job.
+pub fn output_path(job_dir: &Path, filename: &str) -> PathBuf {
+    let mut path = job_dir.join(filename);
+    path.set_extension("mkv");
+    path
+}
+
+/// Duration the fragments were cut at: their median duration, the last fragment being shorter.
+///
+/// `fragment_media` cuts at the first keyframe after every boundary, so the fragments are
+/// usually a bit longer than the requested duration.
+fn nominal_duration(durations: &[f64]) -> f64 {
+    let mut durations = durations.to_vec();
+    if durations.len() > 1 {
+        durations.pop();
+    }
+    durations.sort_by(f64::total_cmp);
+    (durations[durations.len() / 2] * 10.0).round() / 10.0
+}
+
+/// The transcoded fragments of a rendition, in order.
+#[derive(Debug, Clone)]
+pub struct RenditionInput {
+    /// Name of the rendition, `None` for a job without renditions.
+    pub name: Option<String>,
+    pub fragments: Vec<ConcatInput>,
+}
+
+/// A file of a concat list, with the part of it to keep.
+#[derive(Debug, Clone)]
+pub struct ConcatInput {
+    pub path: PathBuf,
+    /// Start of the part, in seconds, the file must have a keyframe there.
+    pub inpoint: Option<f64>,
+    /// End of the part, in seconds.
+    pub outpoint: Option<f64>,
+}
+
+/// Concatenate the transcoded fragments, without re-encoding, and segment them into `dest`.
+///
+/// Every fragment starts with a keyframe, so segments of the fragment duration (or of a
+/// divisor of it) are cut on the fragment boundaries. With several renditions, only their
+/// video is packaged (`fragment_media` drops the audio anyway), each in its own variant of
+/// the HLS master playlist and representation of the DASH manifest.
+///
+/// The overlap of the fragments is trimmed by the concat demuxer: the daemon forces a
+/// keyframe at both ends of the fragment, so the trimmed fragment still starts with one.
+pub async fn package_fragments(
+    ffmpeg_bin: &str,
+    renditions: &[RenditionInput],
+    dest: &Path,
+    options: &PackageOptions,
+) -> Result<()> {
+    let tmp_dir = TempDir::new("transcodeck-package")?;
+    let segment_time = options.segment_duration.to_string();
+    let mut command = Command::new(ffmpeg_bin);
+    command
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-stats")
+        .arg("-y");
+    for (i, rendition) in renditions.iter().enumerate() {
+        let list = tmp_dir.path().join(format!("fragments-{}.txt", i));
+        tokio::fs::write(&list, concat_list(&rendition.fragments)?).await?;
+        command
+            .arg("-f")
+            .arg("concat")
+            .arg("-safe")
+            .arg("0")
+            .arg("-i")
+            .arg(&list);
+    }
+
+    let variants = renditions.len() > 1;
+    let map = |command: &mut Command| {
+        if variants {
+            for i in 0..renditions.len() {
+                command.arg("-map").arg(format!("{}:v", i));
+            }
+        } else {
+            command.arg("-map").arg("0");
+        }
+        command.arg("-c").arg("copy");
+    };
+
+    if options.format != PackageFormat::Dash {
+        let hls_dir = dest.join("hls");
+        tokio::fs::create_dir_all(&hls_dir).await?;
+        let extension = match options.segment_type {
+            SegmentType::Fmp4 => "m4s",
+            SegmentType::Ts => "ts",
+        };
+        // Every variant has its own directory, the master playlist is written above them.
+        let variant_dir = if variants {
+            for rendition in renditions {
+                let name = rendition.name.as_deref().unwrap_or_default();
+                tokio::fs::create_dir_all(hls_dir.join(name)).await?;
+            }
+            hls_dir.join("%v")
+        } else {
+            hls_dir.clone()
+        };
+        map(&mut command);
+        command
+            .arg("-f")
+            .arg("hls")
+            .arg("-hls_time")
+            .arg(&segment_time)
+            .arg("-hls_playlist_type")
+            .arg("vod")
+            .arg("-hls_flags")
+            .arg("independent_segments");
+        if options.segment_type == SegmentType::Fmp4 {
+            command
+                .arg("-hls_segment_type")
+                .arg("fmp4")
+                .arg("-hls_fmp4_init_filename")
+                .arg(if variants { "init-%v.mp4" } else { "init.mp4" });
+        }
+        if variants {
+            let stream_map = renditions
+                .iter()
+                .enumerate()
+                .map(|(i, rendition)| {
+                    format!(
+                        "v:{},name:{}",
+                        i,
+                        rendition.name.as_deref().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            command
+                .arg("-var_stream_map")
+                .arg(stream_map)
+                .arg("-master_pl_name")
+                .arg("master.m3u8");
+        }
+        command
+            .arg("-hls_segment_filename")
+            .arg(variant_dir.join(format!("segment-%05d.{}", extension)))
+            .arg(variant_dir.join("index.m3u8"));
+    }
+    if options.format != PackageFormat::Hls {
+        let dash_dir = dest.join("dash");
+        tokio::fs::create_dir_all(&dash_dir).await?;
+        map(&mut command);
+        command
+            .arg("-f")
+            .arg("dash")
+            .arg("-seg_duration")
+            .arg(&segment_time)
+            .arg("-use_template")
+            .arg("1")
+            .arg("-use_timeline")
+            .arg("1")
+            .arg("-init_seg_name")
+            .arg("init-$RepresentationID$.m4s")
+            .arg("-media_seg_name")
+            .arg("segment-$RepresentationID$-$Number%05d$.m4s");
+        if variants {
+            command.arg("-adaptation_sets").arg("id=0,streams=v");
+        }
+        command.arg(dash_dir.join("manifest.mpd"));
+    }
+
+    let status = command.status().await?;
+    if !status.success() {
+        bail!("Failed to package media: status={:?}", status.code());
+    }
+
+    if let Err(e) = tmp_dir.close() {
+        warn!("Failed to clean up temporary directory: {}", e);
+    }
+    Ok(())
+}
+
+/// List of files for the ffmpeg concat demuxer.
+pub(crate) fn concat_list(inputs: &[ConcatInput]) -> Result<String> {
+    let mut list = String::new();
+    for input in inputs {
+        let path = std::path::absolute(&input.path)?;
+        let path = path.to_string_lossy().replace('\'', "'\\''");
+        list.push_str(&format!("file '{}'\n", path));
+        if let Some(inpoint) = input.inpoint {
+            list.push_str(&format!("inpoint {}\n", inpoint));
+        }
+        if let Some(outpoint) = input.outpoint {
+            list.push_str(&format!("outpoint {}\n", outpoint));
+        }
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_concat_list_paths() {
+        let inputs = [
+            ConcatInput {
+                path: PathBuf::from("/tmp/fragment-000.mkv"),
+                inpoint: None,
+                outpoint: None,
+            },
+            ConcatInput {
+                path: PathBuf::from("/tmp/it's a fragment.mkv"),
+                inpoint: Some(1.5),
+                outpoint: Some(11.5),
+            },
+        ];
+        assert_eq!(
+            concat_list(&inputs).unwrap(),
+            "file '/tmp/fragment-000.mkv'\n\
+             file '/tmp/it'\\''s a fragment.mkv'\n\
+             inpoint 1.5\n\
+             outpoint 11.5\n"
+        );
+    }
+
+    #[test]
+    fn makes_concat_list_paths_absolute() {
+        let inputs = [ConcatInput {
+            path: PathBuf::from("fragment-000.mkv"),
+            inpoint: None,
+            outpoint: None,
+        }];
+        let path = std::env::current_dir().unwrap().join("fragment-000.mkv");
+        assert_eq!(
+            concat_list(&inputs).unwrap(),
+            format!("file '{}'\n", path.display())
+        );
+    }
+}
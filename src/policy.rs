@@ -0,0 +1,397 @@
+use anyhow::{bail, Result};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// ffmpeg options allowed by default, and whether they take a value.
+const DEFAULT_FLAGS: &[(&str, bool)] = &[
+    ("-i", true),
+    ("-y", false),
+    ("-n", false),
+    ("-hide_banner", false),
+    ("-nostats", false),
+    ("-stats", false),
+    ("-loglevel", true),
+    ("-v", true),
+    ("-f", true),
+    ("-c", true),
+    ("-codec", true),
+    ("-vcodec", true),
+    ("-acodec", true),
+    ("-scodec", true),
+    ("-map", true),
+    ("-map_metadata", true),
+    ("-map_chapters", true),
+    ("-metadata", true),
+    ("-an", false),
+    ("-vn", false),
+    ("-sn", false),
+    ("-dn", false),
+    ("-b", true),
+    ("-maxrate", true),
+    ("-minrate", true),
+    ("-bufsize", true),
+    ("-crf", true),
+    ("-qp", true),
+    ("-q", true),
+    ("-qscale", true),
+    ("-preset", true),
+    ("-tune", true),
+    ("-profile", true),
+    ("-level", true),
+    ("-pix_fmt", true),
+    ("-r", true),
+    ("-s", true),
+    ("-g", true),
+    ("-keyint_min", true),
+    ("-bf", true),
+    ("-fps_mode", true),
+    ("-threads", true),
+    ("-row-mt", true),
+    ("-tiles", true),
+    ("-cpu-used", true),
+    ("-lag-in-frames", true),
+    ("-x264-params", true),
+    ("-x265-params", true),
+    ("-svtav1-params", true),
+    ("-aom-params", true),
+    ("-ar", true),
+    ("-ac", true),
+    ("-vf", true),
+    ("-af", true),
+    ("-filter", true),
+    ("-movflags", true),
+    ("-pass", true),
+    ("-passlogfile", true),
+];
+
+/// Options whose value is a codec name.
+pub const CODEC_FLAGS: &[&str] = &["-c", "-codec", "-vcodec", "-acodec", "-scodec"];
+
+/// Options whose value is a filter graph.
+const FILTER_FLAGS: &[&str] = &["-vf", "-af", "-filter", "-filter_complex"];
+
+/// Options whose value is a path.
+const PATH_FLAGS: &[&str] = &["-passlogfile"];
+
+const DEFAULT_CODECS: &[&str] = &[
+    "copy",
+    "libx264",
+    "libx265",
+    "libsvtav1",
+    "libaom-av1",
+    "librav1e",
+    "libvpx",
+    "libvpx-vp9",
+    "ffv1",
+    "libopus",
+    "libvorbis",
+    "aac",
+    "flac",
+    "ac3",
+    "eac3",
+];
+
+const DEFAULT_FILTERS: &[&str] = &[
+    "null",
+    "copy",
+    "scale",
+    "crop",
+    "pad",
+    "fps",
+    "format",
+    "setsar",
+    "setdar",
+    "setpts",
+    "trim",
+    "transpose",
+    "hflip",
+    "vflip",
+    "yadif",
+    "bwdif",
+    "hqdn3d",
+    "nlmeans",
+    "unsharp",
+    "zscale",
+    "tonemap",
+    "colorspace",
+    "anull",
+    "aformat",
+    "aresample",
+    "asetpts",
+    "atrim",
+    "volume",
+    "loudnorm",
+];
+
+const DEFAULT_FORMATS: &[&str] = &["matroska", "mp4", "mov", "webm", "ivf", "mpegts", "null"];
+
+/// Worker-side policy for the ffmpeg arguments of a job.
+///
+/// Job commands come from the database and might be written by untrusted submitters,
+/// so when sandboxing is enabled, the rendered arguments are checked against an allow-list
+/// of options, codecs, filters and formats, and every path must stay in the directories
+/// given to [`Policy::check`].
+#[derive(Debug, Clone)]
+pub struct Policy {
+    flags: HashSet<String>,
+    value_flags: HashSet<String>,
+    codecs: HashSet<String>,
+    filters: HashSet<String>,
+    formats: HashSet<String>,
+}
+
+impl Policy {
+    /// Build the default policy, extended with extra options, codecs and filters.
+    ///
+    /// Extra options are expected to take a value.
+    pub fn new(extra_flags: &[String], extra_codecs: &[String], extra_filters: &[String]) -> Self {
+        let mut flags = HashSet::new();
+        let mut value_flags = HashSet::new();
+        for (flag, takes_value) in DEFAULT_FLAGS {
+            flags.insert(flag.to_string());
+            if *takes_value {
+                value_flags.insert(flag.to_string());
+            }
+        }
+        for flag in extra_flags {
+            let flag = format!("-{}", flag.trim_start_matches('-'));
+            flags.insert(flag.clone());
+            value_flags.insert(flag);
+        }
+
+        Policy {
+            flags,
+            value_flags,
+            codecs: DEFAULT_CODECS
+                .iter()
+                .map(|c| c.to_string())
+                .chain(extra_codecs.iter().cloned())
+                .collect(),
+            filters: DEFAULT_FILTERS
+                .iter()
+                .map(|f| f.to_string())
+                .chain(extra_filters.iter().cloned())
+                .collect(),
+            formats: DEFAULT_FORMATS.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Check the rendered ffmpeg arguments of a job.
+    ///
+    /// Inputs must be in `input_dir`, outputs in `input_dir` or `output_dir`. Relative paths
+    /// are resolved against `input_dir`, which is expected to be the working directory of ffmpeg.
+    pub fn check(&self, args: &[String], input_dir: &Path, output_dir: &Path) -> Result<()> {
+        let input_dir = std::path::absolute(input_dir)?;
+        let output_dir = std::path::absolute(output_dir)?;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            check_token(arg, &input_dir, &output_dir)?;
+
+            if !is_option(arg) {
+                // Positional arguments are output files.
+                let path = check_path(arg, &input_dir)?;
+                if !path.starts_with(&input_dir) && !path.starts_with(&output_dir) {
+                    bail!("Output path is outside of the allowed directories: {}", arg);
+                }
+                continue;
+            }
+
+            // Strip the stream specifier, e.g. `-c:v:0` is checked as `-c`.
+            let flag = arg.split(':').next().unwrap_or(arg);
+            if !self.flags.contains(flag) {
+                bail!("ffmpeg option is not allowed: {}", arg);
+            }
+            if !self.value_flags.contains(flag) {
+                continue;
+            }
+            let Some(value) = args.next() else {
+                bail!("Missing value for ffmpeg option: {}", arg);
+            };
+            check_token(value, &input_dir, &output_dir)?;
+
+            if flag == "-i" {
+                let path = check_path(value, &input_dir)?;
+                if !path.starts_with(&input_dir) {
+                    bail!("Input path is outside of the job directory: {}", value);
+                }
+            } else if PATH_FLAGS.contains(&flag) {
+                let path = check_path(value, &input_dir)?;
+                if !path.starts_with(&input_dir) {
+                    bail!("Path is outside of the job directory: {}", value);
+                }
+            } else if CODEC_FLAGS.contains(&flag) {
+                if !self.codecs.contains(value) {
+                    bail!("Codec is not allowed: {}", value);
+                }
+            } else if FILTER_FLAGS.contains(&flag) {
+                for filter in filter_names(value) {
+                    if !self.filters.contains(filter) {
+                        bail!("Filter is not allowed: {}", filter);
+                    }
+                }
+            } else if flag == "-f" && !self.formats.contains(value) {
+                bail!("Format is not allowed: {}", value);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_option(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && !arg[1..].starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Checks applied to every argument: no network URLs, no parent directories, and no
+/// absolute paths (even embedded in an option value) outside of the allowed directories.
+fn check_token(arg: &str, input_dir: &Path, output_dir: &Path) -> Result<()> {
+    if arg.contains("://") {
+        bail!("URLs are not allowed in ffmpeg arguments: {}", arg);
+    }
+    if arg
+        .split(['/', '=', ':', ',', ';'])
+        .any(|part| part == "..")
+    {
+        bail!(
+            "Parent directories are not allowed in ffmpeg arguments: {}",
+            arg
+        );
+    }
+    for part in arg.split(['=', ':', ',', ';', '\'', '"']) {
+        if part.starts_with('/') {
+            let path = Path::new(part);
+            if !path.starts_with(input_dir) && !path.starts_with(output_dir) {
+                bail!("Path is outside of the allowed directories: {}", part);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a path argument, rejecting ffmpeg protocols (`file:`, `concat:`, `pipe:`...).
+fn check_path(arg: &str, base_dir: &Path) -> Result<PathBuf> {
+    if let Some((prefix, _)) = arg.split_once(':') {
+        if !prefix.contains('/') {
+            bail!("ffmpeg protocols are not allowed: {}", arg);
+        }
+    }
+    let path = base_dir.join(arg);
+    if path.components().any(|c| c == Component::ParentDir) {
+        bail!(
+            "Parent directories are not allowed in ffmpeg arguments: {}",
+            arg
+        );
+    }
+    Ok(path)
+}
+
+/// List the filter names used in a filter graph, e.g. `[0:v]scale=1280:-2,fps=30[out]`
+/// uses `scale` and `fps`.
+fn filter_names(graph: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    for spec in graph.split([',', ';']) {
+        let mut spec = spec.trim();
+        // Skip the input pads, e.g. `[0:v]`.
+        while let Some(rest) = spec.strip_prefix('[') {
+            spec = rest.split_once(']').map_or("", |(_, s)| s).trim_start();
+        }
+        let name = spec
+            .split(['=', '@', '['])
+            .next()
+            .unwrap_or_default()
+            .trim();
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(args: &str) -> Result<()> {
+        let args = args
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        Policy::new(&[], &[], &[]).check(&args, Path::new("/tmp/job"), Path::new("/srv/out"))
+    }
+
+    fn rejection(args: &str) -> String {
+        check(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn allows_job_paths() {
+        check("-i /tmp/job/in.mkv -c:v libx264 -crf 23 /srv/out/out.mkv").unwrap();
+        check("-i in.mkv -vf scale=1280:-2,fps=30 -c:a copy out.mkv").unwrap();
+        check("-i in.mkv -pass 1 -passlogfile /tmp/job/passlog -f null -").unwrap();
+    }
+
+    #[test]
+    fn rejects_urls() {
+        assert!(rejection("-i http://example.com/in.mkv out.mkv").contains("URLs"));
+        assert!(rejection("-i in.mkv rtmp://example.com/live").contains("URLs"));
+    }
+
+    #[test]
+    fn rejects_parent_directories() {
+        assert!(rejection("-i ../other/in.mkv out.mkv").contains("Parent directories"));
+        assert!(rejection("-i in.mkv /srv/out/../../etc/out.mkv").contains("Parent directories"));
+        assert!(rejection("-i in.mkv -vf movie=..:x out.mkv").contains("Parent directories"));
+    }
+
+    #[test]
+    fn rejects_absolute_paths_outside_the_directories() {
+        assert!(rejection("-i /etc/passwd out.mkv").contains("outside"));
+        assert!(rejection("-i in.mkv /var/tmp/out.mkv").contains("outside"));
+        assert!(rejection("-i in.mkv -vf movie=/etc/passwd out.mkv").contains("outside"));
+        // Inputs must be in the job directory, not in the output directory.
+        assert!(rejection("-i /srv/out/other.mkv out.mkv").contains("outside"));
+        assert!(rejection("-i in.mkv -passlogfile /srv/out/passlog out.mkv").contains("outside"));
+    }
+
+    #[test]
+    fn rejects_protocols() {
+        assert!(rejection("-i pipe:0 out.mkv").contains("protocols"));
+        assert!(rejection("-i in.mkv file:out.mkv").contains("protocols"));
+    }
+
+    #[test]
+    fn rejects_unknown_options_codecs_filters_and_formats() {
+        assert!(rejection("-i in.mkv -dump_attachment:t out.mkv").contains("option"));
+        assert!(rejection("-i in.mkv -c:v libfoo out.mkv").contains("Codec"));
+        assert!(
+            rejection("-i in.mkv -vf [0:v]scale=640:-2,drawtext=x out.mkv").contains("drawtext")
+        );
+        assert!(rejection("-i in.mkv -f lavfi out.mkv").contains("Format"));
+        assert!(rejection("-i in.mkv -c:v").contains("Missing value"));
+    }
+
+    #[test]
+    fn allows_extra_options_codecs_and_filters() {
+        let policy = Policy::new(
+            &["svtav1-preset".to_string()],
+            &["libfoo".to_string()],
+            &["drawtext".to_string()],
+        );
+        let args = "-i in.mkv -svtav1-preset 8 -c:v libfoo -vf drawtext=x out.mkv"
+            .split_whitespace()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        policy
+            .check(&args, Path::new("/tmp/job"), Path::new("/srv/out"))
+            .unwrap();
+    }
+
+    #[test]
+    fn lists_filter_names() {
+        assert_eq!(
+            filter_names("[0:v]scale=1280:-2,fps=30[out];[out]setsar=1"),
+            ["scale", "fps", "setsar"]
+        );
+        assert_eq!(filter_names("hqdn3d@denoise"), ["hqdn3d"]);
+    }
+}
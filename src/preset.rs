@@ -0,0 +1,166 @@
+use anyhow::{bail, Context, Result};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+use utoipa::ToSchema;
+
+use crate::add_transcode::JobRequest;
+use crate::db::DbConnection;
+use crate::{model, schema, PresetCommand, PresetSubcommand};
+
+/// Named settings shared by transcoding jobs, e.g. the ffmpeg command and quality check of
+/// a delivery format. A job request naming a preset uses its settings for the ones it leaves
+/// empty.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PresetEntry {
+    pub name: String,
+    pub settings: JobRequest,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl TryFrom<model::Preset> for PresetEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(preset: model::Preset) -> Result<Self> {
+        let settings = serde_json::from_str(&preset.settings)
+            .with_context(|| format!("Invalid settings of the preset {}", preset.name))?;
+        Ok(PresetEntry {
+            name: preset.name,
+            settings,
+            created_at: preset.created_at,
+            updated_at: preset.updated_at,
+        })
+    }
+}
+
+pub async fn preset(db: &mut DbConnection, cmd: PresetCommand) -> Result<()> {
+    match cmd.cmd {
+        PresetSubcommand::List => print_presets(db),
+        PresetSubcommand::Show { name } => {
+            let preset = get_preset(db, &name)?;
+            println!("{}", serde_json::to_string_pretty(&preset.settings)?);
+            Ok(())
+        }
+        PresetSubcommand::Save { name, settings } => {
+            let settings = read_settings(&settings)?;
+            save_preset(db, &name, settings)?;
+            println!("Preset saved: {}", name);
+            Ok(())
+        }
+        PresetSubcommand::Delete { name } => {
+            delete_preset(db, &name)?;
+            println!("Preset deleted: {}", name);
+            Ok(())
+        }
+    }
+}
+
+/// Read the settings of a preset from a JSON file, or from stdin for `-`.
+fn read_settings(path: &Path) -> Result<JobRequest> {
+    let mut json = String::new();
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut json)?;
+    } else {
+        json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+    }
+    serde_json::from_str(&json).context("Invalid preset settings")
+}
+
+/// List the presets, by name.
+pub fn list_presets(db: &mut DbConnection) -> Result<Vec<PresetEntry>> {
+    schema::preset::table
+        .order(schema::preset::name.asc())
+        .select(model::Preset::as_select())
+        .load(db)?
+        .into_iter()
+        .map(PresetEntry::try_from)
+        .collect()
+}
+
+fn print_presets(db: &mut DbConnection) -> Result<()> {
+    println!("{:<24} {:<19} COMMAND", "PRESET", "UPDATED");
+    for preset in list_presets(db)? {
+        let command = match preset.settings.renditions.len() {
+            0 => preset.settings.ffmpeg_command,
+            renditions => format!("({} renditions)", renditions),
+        };
+        println!(
+            "{:<24} {:<19} {}",
+            preset.name,
+            preset.updated_at.format("%Y-%m-%d %H:%M:%S"),
+            command
+        );
+    }
+    Ok(())
+}
+
+pub fn get_preset(db: &mut DbConnection, name: &str) -> Result<PresetEntry> {
+    schema::preset::table
+        .filter(schema::preset::name.eq(name))
+        .select(model::Preset::as_select())
+        .first(db)
+        .with_context(|| format!("Preset not found: {}", name))?
+        .try_into()
+}
+
+/// Add a preset, or replace the settings of an existing one. The settings are validated
+/// when a job uses them, as the job request can complete them.
+pub fn save_preset(db: &mut DbConnection, name: &str, settings: JobRequest) -> Result<PresetEntry> {
+    if !is_valid_preset_name(name) {
+        bail!(
+            "Invalid preset name (letters, digits, '-', '_' and '.' only): {}",
+            name
+        );
+    }
+    if !settings.media_id.is_nil() {
+        bail!("The settings of a preset have no media ID");
+    }
+    if settings.preset.is_some() {
+        bail!("The settings of a preset cannot use another preset");
+    }
+    let settings = serde_json::to_string(&settings)?;
+
+    db.transaction(|db| {
+        let updated = diesel::update(schema::preset::table)
+            .filter(schema::preset::name.eq(name))
+            .set((
+                schema::preset::settings.eq(&settings),
+                schema::preset::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(db)?;
+        if updated == 0 {
+            diesel::insert_into(schema::preset::table)
+                .values(model::NewPreset {
+                    name: name.to_string(),
+                    settings: settings.clone(),
+                })
+                .execute(db)?;
+        }
+        Ok::<_, diesel::result::Error>(())
+    })?;
+    get_preset(db, name)
+}
+
+pub fn delete_preset(db: &mut DbConnection, name: &str) -> Result<()> {
+    let deleted = diesel::delete(schema::preset::table)
+        .filter(schema::preset::name.eq(name))
+        .execute(db)?;
+    if deleted == 0 {
+        return Err(diesel::result::Error::NotFound)
+            .with_context(|| format!("Preset not found: {}", name));
+    }
+    Ok(())
+}
+
+/// Preset names are used in URLs and on the command line.
+fn is_valid_preset_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
@@ -0,0 +1,79 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+use tokio::process::Command;
+
+/// Get the duration (in seconds) of a media file using ffprobe.
+pub async fn duration(ffprobe_bin: &str, input: impl AsRef<Path>) -> Result<f64> {
+    let output = Command::new(ffprobe_bin)
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(input.as_ref())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!("Failed to probe media: status={:?}", output.status.code());
+    }
+    let duration = String::from_utf8_lossy(&output.stdout);
+    duration
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| anyhow!("Invalid media duration: {}", duration.trim()))
+}
+
+/// List the types of the streams (`video`, `audio`, `subtitle`...) of a media file, using
+/// ffprobe.
+pub async fn stream_types(ffprobe_bin: &str, input: impl AsRef<Path>) -> Result<Vec<String>> {
+    let output = Command::new(ffprobe_bin)
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("stream=codec_type")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input.as_ref())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!("Failed to probe streams: status={:?}", output.status.code());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Get the pixel format (e.g. `yuv420p`) of the first video stream of a media file, using
+/// ffprobe.
+pub async fn pixel_format(ffprobe_bin: &str, input: impl AsRef<Path>) -> Result<String> {
+    let output = Command::new(ffprobe_bin)
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=pix_fmt")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input.as_ref())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to probe pixel format: status={:?}",
+            output.status.code()
+        );
+    }
+    let pixel_format = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if pixel_format.is_empty() {
+        bail!("No video stream");
+    }
+    Ok(pixel_format)
+}
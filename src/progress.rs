@@ -0,0 +1,55 @@
+/// Progress report of ffmpeg, as written with `-progress pipe:1`.
+///
+/// ffmpeg writes blocks of `key=value` lines, each block ending with a `progress=continue`
+/// line (or `progress=end` for the last one).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfmpegProgress {
+    /// Position in the output, in seconds.
+    pub out_time: Option<f64>,
+    pub fps: Option<f64>,
+    /// Encoding speed, relative to realtime.
+    pub speed: Option<f64>,
+    /// Output bitrate, in kbit/s.
+    pub bitrate: Option<f64>,
+    pub ended: bool,
+}
+
+impl FfmpegProgress {
+    /// Update the progress with a line of the report.
+    ///
+    /// Returns true when the line ends a block, and the progress is up to date.
+    pub fn update(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return false;
+        };
+        let value = value.trim();
+        match key {
+            // Despite its name, `out_time_ms` is in microseconds, like `out_time_us`.
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    self.out_time = Some(us.max(0) as f64 / 1_000_000.0);
+                }
+            }
+            "fps" => self.fps = value.parse().ok(),
+            "speed" => self.speed = value.trim_end_matches('x').trim().parse().ok(),
+            "bitrate" => self.bitrate = value.trim_end_matches("kbits/s").trim().parse().ok(),
+            "progress" => {
+                self.ended = value == "end";
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Percentage of completion, given the duration (in seconds) of the input.
+    pub fn percent(&self, duration: f64) -> Option<f64> {
+        if self.ended {
+            return Some(100.0);
+        }
+        match self.out_time {
+            Some(out_time) if duration > 0.0 => Some((out_time / duration * 100.0).min(100.0)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::model::QualityMetric;
+
+/// Score a transcoded output against its source, with a full-reference metric of ffmpeg.
+///
+/// The output is scaled to the size of the source first, so renditions of a lower
+/// resolution are compared with the source as they would be displayed.
+pub async fn score(
+    ffmpeg_bin: &str,
+    metric: QualityMetric,
+    output: &Path,
+    source: &Path,
+) -> Result<f64> {
+    let filter = match metric {
+        QualityMetric::Vmaf => "libvmaf",
+        QualityMetric::Ssim => "ssim",
+        QualityMetric::Psnr => "psnr",
+    };
+    let graph = format!(
+        "[0:v][1:v]scale2ref=flags=bicubic[distorted][reference];[distorted][reference]{}",
+        filter
+    );
+    let result = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(output)
+        .arg("-i")
+        .arg(source)
+        .arg("-lavfi")
+        .arg(graph)
+        .arg("-an")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await?;
+
+    if !result.status.success() {
+        bail!(
+            "Failed to compute the {} score: status={:?}",
+            filter,
+            result.status.code()
+        );
+    }
+    let log = String::from_utf8_lossy(&result.stderr);
+    parse_score(metric, &log).ok_or_else(|| anyhow!("No {} score in the ffmpeg logs", filter))
+}
+
+/// Find the score in the summary logged by the filter, e.g.
+/// - `[Parsed_libvmaf_1 @ 0x...] VMAF score: 93.412345`
+/// - `[Parsed_ssim_1 @ 0x...] SSIM Y:0.981 (17.2) U:0.990 (20.1) V:0.991 (20.4) All:0.985 (18.2)`
+/// - `[Parsed_psnr_1 @ 0x...] PSNR y:41.8 u:46.2 v:46.9 average:43.0 min:40.1 max:47.3`
+fn parse_score(metric: QualityMetric, log: &str) -> Option<f64> {
+    let (marker, key) = match metric {
+        QualityMetric::Vmaf => ("VMAF score:", "VMAF score:"),
+        QualityMetric::Ssim => ("SSIM ", "All:"),
+        QualityMetric::Psnr => ("PSNR ", "average:"),
+    };
+    let line = log.lines().rev().find(|line| line.contains(marker))?;
+    let (_, rest) = line.split_once(key)?;
+    let value = rest.split_whitespace().next()?;
+    // PSNR is `inf` for identical frames.
+    match value {
+        "inf" => Some(f64::INFINITY),
+        value => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_vmaf_score() {
+        let log = "[libvmaf @ 0x5581] model: vmaf_v0.6.1\n\
+                   [Parsed_libvmaf_1 @ 0x5582] VMAF score: 93.412345\n";
+        assert_eq!(parse_score(QualityMetric::Vmaf, log), Some(93.412345));
+    }
+
+    #[test]
+    fn parses_ssim_score() {
+        let log = "[Parsed_ssim_1 @ 0x5582] SSIM Y:0.981 (17.2) U:0.990 (20.1) V:0.991 (20.4) All:0.985123 (18.2)";
+        assert_eq!(parse_score(QualityMetric::Ssim, log), Some(0.985123));
+    }
+
+    #[test]
+    fn parses_psnr_score() {
+        let log =
+            "[Parsed_psnr_1 @ 0x5582] PSNR y:41.8 u:46.2 v:46.9 average:43.0 min:40.1 max:47.3";
+        assert_eq!(parse_score(QualityMetric::Psnr, log), Some(43.0));
+        let log = "[Parsed_psnr_1 @ 0x5582] PSNR y:inf u:inf v:inf average:inf min:inf max:inf";
+        assert_eq!(parse_score(QualityMetric::Psnr, log), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn uses_the_last_summary() {
+        let log =
+            "[Parsed_libvmaf_1 @ 0x1] VMAF score: 80.0\n[Parsed_libvmaf_1 @ 0x2] VMAF score: 90.0";
+        assert_eq!(parse_score(QualityMetric::Vmaf, log), Some(90.0));
+    }
+
+    #[test]
+    fn rejects_missing_scores() {
+        assert_eq!(parse_score(QualityMetric::Vmaf, "frame=  25 fps=0.0"), None);
+        let log = "[Parsed_ssim_1 @ 0x5582] SSIM Y:0.981 (17.2)";
+        assert_eq!(parse_score(QualityMetric::Ssim, log), None);
+        let log = "[Parsed_psnr_1 @ 0x5582] PSNR average:nope";
+        assert_eq!(parse_score(QualityMetric::Psnr, log), None);
+    }
+}
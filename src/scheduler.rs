@@ -0,0 +1,125 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::estimate::{self, Estimator};
+use crate::{model, schema, worker};
+use model::{FragmentJobStatus, JobStatus};
+
+/// How a worker picks the next fragment job to process.
+///
+/// Jobs at risk of missing their deadline always come first, then jobs are ordered
+/// by priority, and the policy only decides between jobs of the same priority.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SchedulingPolicy {
+    /// Oldest job first.
+    Fifo,
+    /// Job with the fewest fragments in progress first.
+    RoundRobin,
+    /// Submitter with the fewest fragments in progress first, then as round-robin.
+    #[default]
+    FairShare,
+}
+
+/// Find the next fragment job this worker should process, according to the scheduling policy.
+///
+/// The fragment job is not claimed, another worker might claim it first.
+pub fn next_fragment_job(
+    db: &mut DbConnection,
+    worker: &model::Worker,
+    policy: SchedulingPolicy,
+) -> Result<Option<model::JobResume>> {
+    // Running jobs with queued fragments that this worker can process, the fragments of a
+    // failed job stay queued
+    let candidates = schema::transcoding_fragment_job::table
+        .inner_join(schema::transcoding_job::table)
+        .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
+        .filter(schema::transcoding_job::status.eq_any([JobStatus::Queued, JobStatus::InProgress]))
+        .select(model::TranscodingJob::as_select())
+        .distinct()
+        .load(db)?
+        .into_iter()
+        .filter(|job| worker::can_run(worker, &job.requirements()))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    // Deadlines of the jobs at risk of missing them
+    let mut at_risk = HashMap::new();
+    if candidates.iter().any(|job| job.deadline.is_some()) {
+        let estimator = Estimator::load(db)?;
+        for job in &candidates {
+            let Some(deadline) = job.deadline else {
+                continue;
+            };
+            if estimate::is_at_risk(estimator.eta(db, job)?, deadline) {
+                at_risk.insert(job.transcoding_job_id, deadline);
+            }
+        }
+    }
+
+    // Fragments currently in progress, per job and per submitter
+    let running = schema::transcoding_fragment_job::table
+        .inner_join(schema::transcoding_job::table)
+        .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::InProgress))
+        .select((
+            schema::transcoding_job::transcoding_job_id,
+            schema::transcoding_job::submitter,
+        ))
+        .load::<(Uuid, Option<String>)>(db)?;
+    let mut running_by_job = HashMap::new();
+    let mut running_by_submitter = HashMap::new();
+    for (transcoding_job_id, submitter) in running {
+        *running_by_job.entry(transcoding_job_id).or_insert(0) += 1;
+        *running_by_submitter.entry(submitter).or_insert(0) += 1;
+    }
+
+    let job = candidates
+        .iter()
+        .min_by_key(|job| {
+            let running_job = running_by_job
+                .get(&job.transcoding_job_id)
+                .copied()
+                .unwrap_or(0);
+            let running_submitter = running_by_submitter
+                .get(&job.submitter)
+                .copied()
+                .unwrap_or(0);
+            let (first, second) = match policy {
+                SchedulingPolicy::Fifo => (0, 0),
+                SchedulingPolicy::RoundRobin => (running_job, 0),
+                SchedulingPolicy::FairShare => (running_submitter, running_job),
+            };
+            // Jobs at risk first, by earliest deadline
+            let deadline = at_risk.get(&job.transcoding_job_id).copied().map(Reverse);
+            (
+                Reverse(deadline),
+                Reverse(job.priority),
+                first,
+                second,
+                job.created_at,
+            )
+        })
+        .unwrap();
+
+    // Oldest queued fragment of the chosen job
+    let fragment_job = schema::transcoding_fragment_job::table
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(job.transcoding_job_id))
+        .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
+        .select((
+            schema::transcoding_fragment_job::transcoding_fragment_job_id,
+            schema::transcoding_fragment_job::transcoding_job_id,
+            schema::transcoding_fragment_job::fragment_id,
+        ))
+        .order(schema::transcoding_fragment_job::created_at.asc())
+        .first::<model::JobResume>(db)
+        .optional()?;
+    Ok(fragment_job)
+}
@@ -3,11 +3,23 @@
 pub mod sql_types {
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "fragment_job_status"))]
+    #[diesel(sqlite_type(name = "Text"))]
     pub struct FragmentJobStatus;
 
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "job_status"))]
+    #[diesel(sqlite_type(name = "Text"))]
     pub struct JobStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "quality_metric"))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct QualityMetric;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "webhook_delivery_status"))]
+    #[diesel(sqlite_type(name = "Text"))]
+    pub struct WebhookDeliveryStatus;
 }
 
 diesel::table! {
@@ -21,6 +33,7 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
+        duration -> Nullable<Float8>,
     }
 }
 
@@ -34,6 +47,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    preset (name) {
+        name -> Text,
+        settings -> Text,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::FragmentJobStatus;
@@ -46,12 +68,39 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
+        progress -> Nullable<Float8>,
+        fps -> Nullable<Float8>,
+        speed -> Nullable<Float8>,
+        bitrate -> Nullable<Float8>,
+        started_at -> Nullable<Timestamptz>,
+        completed_at -> Nullable<Timestamptz>,
+        encode_time -> Nullable<Float8>,
+        output_size -> Nullable<Int8>,
+        average_speed -> Nullable<Float8>,
+        worker_id -> Nullable<Uuid>,
+        trim_start -> Nullable<Float8>,
+        trim_end -> Nullable<Float8>,
+        quality_score -> Nullable<Float8>,
+        quality_flagged -> Bool,
+        crf -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    transcoding_fragment_job_log (transcoding_fragment_job_log_id) {
+        transcoding_fragment_job_log_id -> Uuid,
+        transcoding_fragment_job_id -> Uuid,
+        exit_status -> Nullable<Int4>,
+        message -> Text,
+        log -> Text,
+        created_at -> Timestamptz,
     }
 }
 
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::JobStatus;
+    use super::sql_types::QualityMetric;
 
     transcoding_job (transcoding_job_id) {
         transcoding_job_id -> Uuid,
@@ -61,17 +110,125 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
+        required_encoders -> Array<Text>,
+        required_variables -> Array<Text>,
+        required_tags -> Array<Text>,
+        min_memory -> Nullable<Int8>,
+        priority -> Int4,
+        submitter -> Nullable<Text>,
+        deadline -> Nullable<Timestamptz>,
+        webhook_url -> Nullable<Text>,
+        overlap -> Float8,
+        ffmpeg_passes -> Array<Text>,
+        quality_metric -> Nullable<QualityMetric>,
+        quality_threshold -> Nullable<Float8>,
+        fail_below_threshold -> Bool,
+        target_vmaf -> Nullable<Float8>,
+        crf_min -> Int4,
+        crf_max -> Int4,
+    }
+}
+
+diesel::table! {
+    transcoding_rendition (transcoding_rendition_id) {
+        transcoding_rendition_id -> Uuid,
+        transcoding_job_id -> Uuid,
+        name -> Text,
+        ffmpeg_command -> Text,
+        position -> Int4,
+        created_at -> Timestamptz,
+        ffmpeg_passes -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WebhookDeliveryStatus;
+
+    webhook_delivery (webhook_delivery_id) {
+        webhook_delivery_id -> Uuid,
+        webhook_event_id -> Uuid,
+        url -> Text,
+        payload -> Text,
+        status -> WebhookDeliveryStatus,
+        attempts -> Int4,
+        response_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        next_attempt_at -> Timestamptz,
+        created_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JobStatus;
+    use super::sql_types::FragmentJobStatus;
+
+    webhook_event (webhook_event_id) {
+        webhook_event_id -> Uuid,
+        event -> Text,
+        transcoding_job_id -> Uuid,
+        transcoding_fragment_job_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        dispatched_at -> Nullable<Timestamptz>,
+        job_status -> JobStatus,
+        completed_fragments -> Int4,
+        total_fragments -> Int4,
+        fragment_status -> Nullable<FragmentJobStatus>,
+    }
+}
+
+diesel::table! {
+    worker (worker_id) {
+        worker_id -> Uuid,
+        hostname -> Text,
+        ffmpeg_version -> Nullable<Text>,
+        encoders -> Array<Text>,
+        filters -> Array<Text>,
+        cpu_count -> Int4,
+        template_variables -> Jsonb,
+        free_disk_space -> Nullable<Int8>,
+        current_fragment_job_id -> Nullable<Uuid>,
+        started_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+        tags -> Array<Text>,
+        memory -> Nullable<Int8>,
+        draining -> Bool,
+    }
+}
+
+diesel::table! {
+    worker_token (token_hash) {
+        token_hash -> Text,
+        worker_id -> Uuid,
+        created_at -> Timestamptz,
     }
 }
 
 diesel::joinable!(fragment -> media (media_id));
 diesel::joinable!(transcoding_fragment_job -> fragment (fragment_id));
 diesel::joinable!(transcoding_fragment_job -> transcoding_job (transcoding_job_id));
+diesel::joinable!(transcoding_fragment_job_log -> transcoding_fragment_job (transcoding_fragment_job_id));
 diesel::joinable!(transcoding_job -> media (media_id));
+diesel::joinable!(transcoding_rendition -> transcoding_job (transcoding_job_id));
+diesel::joinable!(transcoding_fragment_job -> worker (worker_id));
+diesel::joinable!(webhook_delivery -> webhook_event (webhook_event_id));
+diesel::joinable!(webhook_event -> transcoding_job (transcoding_job_id));
+diesel::joinable!(webhook_event -> transcoding_fragment_job (transcoding_fragment_job_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     fragment,
     media,
+    preset,
     transcoding_fragment_job,
+    transcoding_fragment_job_log,
     transcoding_job,
+    transcoding_rendition,
+    webhook_delivery,
+    webhook_event,
+    worker,
+    worker_token,
 );
@@ -0,0 +1,258 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::probe;
+
+/// How `add-media` chooses where to cut the media into fragments.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Segmentation {
+    /// Cut at the first keyframe after every boundary, the fragments can be much longer than
+    /// the requested duration when the keyframes are far apart.
+    #[default]
+    Time,
+    /// Cut at the keyframe nearest to every boundary, as probed by ffprobe.
+    Keyframe,
+    /// Cut at the scene changes, if one is near enough to the boundary and on a keyframe,
+    /// or else at the nearest keyframe.
+    Scene,
+    /// Cut exactly every n seconds, by re-encoding the whole video (losslessly with libx264,
+    /// in the pixel format of the source, with closed GOPs) with a keyframe on every boundary.
+    /// The re-encoding takes about as long as a fast encode of the media, and the lossless
+    /// fragments are usually several times larger than the source: prefer `keyframe` or
+    /// `scene` when the keyframes of the source are frequent enough.
+    Fixed,
+}
+
+/// Where and how to cut the media.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// Timestamps of the cuts, in seconds, `None` to cut every n seconds.
+    pub split_points: Option<Vec<f64>>,
+    /// Whether the video is re-encoded, instead of copied.
+    pub reencode: bool,
+    /// Pixel format of the source, kept by the re-encoding.
+    pub pixel_format: Option<String>,
+}
+
+/// Plan the cuts of a media into fragments of about `duration` seconds.
+pub async fn plan(
+    ffmpeg_bin: &str,
+    ffprobe_bin: &str,
+    input: impl AsRef<Path>,
+    duration: f64,
+    segmentation: Segmentation,
+    scene_threshold: f64,
+) -> Result<Plan> {
+    let input = input.as_ref();
+    let split_points = match segmentation {
+        Segmentation::Time | Segmentation::Fixed => None,
+        Segmentation::Keyframe => {
+            let keyframes = keyframes(ffprobe_bin, input).await?;
+            Some(choose_split_points(&[], &keyframes, duration))
+        }
+        Segmentation::Scene => {
+            let keyframes = keyframes(ffprobe_bin, input).await?;
+            let scenes = scene_changes(ffmpeg_bin, input, scene_threshold).await?;
+            // The cut is only exact on a keyframe, encoders usually put one on scene changes.
+            let scene_keyframes = scenes
+                .into_iter()
+                .filter_map(|scene| {
+                    keyframes
+                        .iter()
+                        .copied()
+                        .find(|keyframe| (keyframe - scene).abs() < KEYFRAME_TOLERANCE)
+                })
+                .collect::<Vec<_>>();
+            Some(choose_split_points(&scene_keyframes, &keyframes, duration))
+        }
+    };
+
+    // Avoid a tiny last fragment, it is merged into the previous one.
+    let split_points = match (split_points, probe::duration(ffprobe_bin, input).await) {
+        (Some(mut points), Ok(total)) => {
+            points.retain(|point| total - point >= duration / 2.0);
+            Some(points)
+        }
+        (points, _) => points,
+    };
+
+    let reencode = segmentation == Segmentation::Fixed;
+    let pixel_format = if reencode {
+        Some(probe::pixel_format(ffprobe_bin, input).await?)
+    } else {
+        None
+    };
+
+    Ok(Plan {
+        split_points,
+        reencode,
+        pixel_format,
+    })
+}
+
+/// Maximum distance between a scene change and a keyframe for them to be the same frame.
+const KEYFRAME_TOLERANCE: f64 = 0.05;
+
+/// Choose a cut about every `duration` seconds, preferably among `preferred`, or else among
+/// `fallback`. Both lists must be sorted.
+///
+/// A cut is chosen in the window from half to one and a half `duration` after the previous
+/// one, as near as possible to `duration`. Without any candidate in the window, the cut is
+/// made at the first candidate after it.
+fn choose_split_points(preferred: &[f64], fallback: &[f64], duration: f64) -> Vec<f64> {
+    let mut points = Vec::new();
+    let mut last = 0.0;
+    loop {
+        let target = last + duration;
+        let in_window = |t: &&f64| **t > last + duration / 2.0 && **t <= last + duration * 1.5;
+        let nearest = |candidates: &[f64]| {
+            candidates
+                .iter()
+                .filter(in_window)
+                .min_by(|a, b| (*a - target).abs().total_cmp(&(*b - target).abs()))
+                .copied()
+        };
+        let point = nearest(preferred)
+            .or_else(|| nearest(fallback))
+            .or_else(|| {
+                fallback
+                    .iter()
+                    .chain(preferred)
+                    .copied()
+                    .filter(|t| *t > last + duration * 1.5)
+                    .min_by(f64::total_cmp)
+            });
+        match point {
+            Some(point) => {
+                points.push(point);
+                last = point;
+            }
+            None => break,
+        }
+    }
+    points
+}
+
+/// Timestamps (in seconds) of the keyframes of the first video stream, using ffprobe.
+pub async fn keyframes(ffprobe_bin: &str, input: impl AsRef<Path>) -> Result<Vec<f64>> {
+    let output = Command::new(ffprobe_bin)
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("packet=pts_time,flags")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input.as_ref())
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to probe keyframes: status={:?}",
+            output.status.code()
+        );
+    }
+    let mut keyframes = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (pts_time, flags) = line.split_once(',')?;
+            if !flags.contains('K') {
+                return None;
+            }
+            pts_time.parse::<f64>().ok()
+        })
+        .collect::<Vec<_>>();
+    // Packets are listed in decoding order.
+    keyframes.sort_by(f64::total_cmp);
+    Ok(keyframes)
+}
+
+/// Timestamps (in seconds) of the scene changes, using the `scene` score of the ffmpeg
+/// `select` filter. `threshold` is between 0 and 1, lower values detect more changes.
+pub async fn scene_changes(
+    ffmpeg_bin: &str,
+    input: impl AsRef<Path>,
+    threshold: f64,
+) -> Result<Vec<f64>> {
+    let output = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input.as_ref())
+        .arg("-an")
+        .arg("-vf")
+        .arg(format!("select='gt(scene,{})',showinfo", threshold))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to detect scene changes: status={:?}",
+            output.status.code()
+        );
+    }
+    // The `showinfo` filter logs a line per selected frame, with its `pts_time:<seconds>`.
+    let scenes = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.contains("showinfo"))
+        .filter_map(|line| {
+            let (_, rest) = line.split_once("pts_time:")?;
+            rest.split_whitespace().next()?.parse::<f64>().ok()
+        })
+        .collect::<Vec<_>>();
+    Ok(scenes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuts_at_the_nearest_keyframe() {
+        let keyframes = (0..=15).map(|i| i as f64 * 2.0).collect::<Vec<_>>();
+        assert_eq!(
+            choose_split_points(&[], &keyframes, 10.0),
+            [10.0, 20.0, 30.0]
+        );
+        let keyframes = [3.0, 9.0, 12.5, 19.0, 31.0];
+        assert_eq!(
+            choose_split_points(&[], &keyframes, 10.0),
+            [9.0, 19.0, 31.0]
+        );
+    }
+
+    #[test]
+    fn window_excludes_its_start_and_includes_its_end() {
+        // The window after 0 is ]5, 15].
+        assert_eq!(choose_split_points(&[], &[5.0, 15.0], 10.0), [15.0]);
+        assert_eq!(choose_split_points(&[], &[5.0], 10.0), Vec::<f64>::new());
+        assert_eq!(choose_split_points(&[], &[5.0, 15.5], 10.0), [15.5]);
+    }
+
+    #[test]
+    fn falls_back_to_the_first_candidate_after_the_window() {
+        assert_eq!(choose_split_points(&[], &[40.0], 10.0), [40.0]);
+        assert_eq!(
+            choose_split_points(&[], &[40.0, 48.0, 70.0], 10.0),
+            [40.0, 48.0, 70.0]
+        );
+    }
+
+    #[test]
+    fn prefers_scene_changes_in_the_window() {
+        let keyframes = [10.0, 13.0, 20.0];
+        assert_eq!(choose_split_points(&[13.0], &keyframes, 10.0), [13.0, 20.0]);
+        // A scene change outside of the window loses to a keyframe in it.
+        assert_eq!(
+            choose_split_points(&[16.0], &[10.0, 16.0], 10.0),
+            [10.0, 16.0]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, Instrument, Span};
+
+/// What the daemon does when it receives SIGINT or SIGTERM.
+///
+/// A second signal always stops the daemon.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Finish the current fragment job, without claiming new ones.
+    Drain,
+    /// Kill ffmpeg and return the current fragment job to the queue.
+    #[default]
+    Stop,
+}
+
+/// State of a running daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Running,
+    Draining,
+    Stopping,
+}
+
+/// Shutdown requests of the daemon, from signals or from the `worker drain` command.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    state: Arc<watch::Sender<State>>,
+}
+
+impl Shutdown {
+    /// Listen to SIGINT and SIGTERM.
+    pub fn listen(mode: ShutdownMode) -> Result<Self> {
+        let (state, _) = watch::channel(State::Running);
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+
+        let shutdown = Shutdown {
+            state: Arc::new(state),
+        };
+        let signals = shutdown.clone();
+        tokio::spawn(
+            async move {
+                loop {
+                    tokio::select! {
+                        _ = sigint.recv() => {}
+                        _ = sigterm.recv() => {}
+                    }
+                    if signals.state() == State::Running && mode == ShutdownMode::Drain {
+                        info!("Draining, the daemon will stop after the current fragment job");
+                        signals.drain();
+                    } else {
+                        info!("Stopping, the current fragment job will be returned to the queue");
+                        signals.stop();
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+        Ok(shutdown)
+    }
+
+    pub fn state(&self) -> State {
+        *self.state.borrow()
+    }
+
+    /// Finish the current fragment job, then stop.
+    pub fn drain(&self) {
+        self.state.send_if_modified(|state| {
+            let running = *state == State::Running;
+            if running {
+                *state = State::Draining;
+            }
+            running
+        });
+    }
+
+    /// Stop as soon as possible.
+    pub fn stop(&self) {
+        self.state.send_replace(State::Stopping);
+    }
+
+    /// Wait until the daemon has to stop or drain.
+    pub async fn requested(&self) {
+        let mut state = self.state.subscribe();
+        let _ = state.wait_for(|state| *state != State::Running).await;
+    }
+
+    /// Wait until the daemon has to stop.
+    pub async fn stopping(&self) {
+        let mut state = self.state.subscribe();
+        let _ = state.wait_for(|state| *state == State::Stopping).await;
+    }
+}
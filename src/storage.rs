@@ -0,0 +1,173 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_util::io::ReaderStream;
+use tracing::info;
+
+/// Where the packaged outputs are published.
+pub enum Storage {
+    /// A local directory, e.g. a mounted network share: `file:///srv/media` or `/srv/media`.
+    Local(PathBuf),
+    /// An HTTP server accepting PUT requests (WebDAV, nginx `dav_methods PUT`...), every file
+    /// is PUT under this URL, with the token as a bearer token if set.
+    Http {
+        http: reqwest::Client,
+        url: String,
+        token: Option<String>,
+    },
+}
+
+impl Storage {
+    pub fn new(url: &str, token: Option<String>) -> Result<Self> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Storage::Http {
+                http: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(300))
+                    .build()?,
+                url: url.trim_end_matches('/').to_string(),
+                token,
+            });
+        }
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        if path.contains("://") || !Path::new(path).is_absolute() {
+            bail!(
+                "Unsupported storage URL: {} (expected file://, http:// or https://)",
+                url
+            );
+        }
+        Ok(Storage::Local(PathBuf::from(path)))
+    }
+
+    /// Where the files uploaded under `prefix` are.
+    pub fn location(&self, prefix: &str) -> String {
+        match self {
+            Storage::Local(dir) => dir.join(prefix).display().to_string(),
+            Storage::Http { url, .. } => format!("{}/{}", url, prefix),
+        }
+    }
+
+    /// Upload the files of `dir`, and of its sub-directories, under `prefix`. Returns the
+    /// number of files uploaded.
+    pub async fn upload_dir(&self, dir: &Path, prefix: &str) -> Result<usize> {
+        let files = files(dir)?;
+        for file in &files {
+            let name = file
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            self.upload(&dir.join(file), &format!("{}/{}", prefix, name))
+                .await
+                .with_context(|| format!("Failed to upload {}", file.display()))?;
+        }
+        info!(files = files.len(), location = %self.location(prefix), "Uploaded");
+        Ok(files.len())
+    }
+
+    async fn upload(&self, path: &Path, name: &str) -> Result<()> {
+        match self {
+            Storage::Local(dir) => {
+                let dest = dir.join(name);
+                if let Some(parent) = dest.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::copy(path, &dest).await?;
+            }
+            Storage::Http { http, url, token } => {
+                // Streamed, the segments of long media do not fit in memory.
+                let file = tokio::fs::File::open(path).await?;
+                let length = file.metadata().await?.len();
+                let mut request = http
+                    .put(format!("{}/{}", url, name))
+                    .header(reqwest::header::CONTENT_TYPE, content_type(path))
+                    .header(reqwest::header::CONTENT_LENGTH, length)
+                    .body(reqwest::Body::wrap_stream(ReaderStream::new(file)));
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                let status = request.send().await?.status();
+                if !status.is_success() {
+                    bail!("status={}", status);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The files of `dir` and of its sub-directories, relative to `dir`, in order.
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(sub_dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir.join(&sub_dir))? {
+            let entry = entry?;
+            let path = sub_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// MIME type of the playlists, manifests and segments, for the HTTP servers to serve them.
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("mpd") => "application/dash+xml",
+        Some("m4s") => "video/iso.segment",
+        Some("mp4") => "video/mp4",
+        Some("ts") => "video/mp2t",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn parses_storage_urls() {
+        assert!(matches!(
+            Storage::new("file:///srv/media", None).unwrap(),
+            Storage::Local(dir) if dir == Path::new("/srv/media")
+        ));
+        assert!(matches!(
+            Storage::new("/srv/media", None).unwrap(),
+            Storage::Local(dir) if dir == Path::new("/srv/media")
+        ));
+        let storage = Storage::new("https://cdn.example.com/media/", None).unwrap();
+        assert_eq!(
+            storage.location("transcode-1"),
+            "https://cdn.example.com/media/transcode-1"
+        );
+    }
+
+    #[test]
+    fn rejects_unsupported_storage_urls() {
+        assert!(Storage::new("s3://bucket/media", None).is_err());
+        assert!(Storage::new("media", None).is_err());
+    }
+
+    #[test]
+    fn lists_files_recursively() {
+        let dir = TempDir::new("transcodeck-storage").unwrap();
+        std::fs::create_dir_all(dir.path().join("hls/720p")).unwrap();
+        std::fs::write(dir.path().join("hls/master.m3u8"), "").unwrap();
+        std::fs::write(dir.path().join("hls/720p/index.m3u8"), "").unwrap();
+        std::fs::write(dir.path().join("hls/720p/init.mp4"), "").unwrap();
+        assert_eq!(
+            files(dir.path()).unwrap(),
+            [
+                PathBuf::from("hls/720p/index.m3u8"),
+                PathBuf::from("hls/720p/init.mp4"),
+                PathBuf::from("hls/master.m3u8"),
+            ]
+        );
+    }
+}
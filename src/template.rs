@@ -0,0 +1,339 @@
+use anyhow::{anyhow, bail, Result};
+use leon::{Template, Values};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::iter;
+use std::path::Path;
+use tempdir::TempDir;
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::policy::{Policy, CODEC_FLAGS};
+
+/// Placeholders always provided by the daemon when rendering a job command.
+///
+/// The placeholders are case-insensitive, like the names of the worker variables, which are
+/// lowercased (e.g. `{LP}` is the `transcodeck_template_lp` variable).
+///
+/// `passlogfile` is the prefix of the statistics files of a multi-pass encode, shared by the
+/// passes of a rendition. `crf` is the value chosen by the search of jobs with a target
+/// quality, e.g. `-crf {crf}` or `-qp {crf}`.
+pub const BUILTIN_KEYS: &[&str] = &["input", "output", "passlogfile", "crf"];
+
+/// Placeholders that every ffmpeg command template must use.
+pub const REQUIRED_KEYS: &[&str] = &["input", "output"];
+
+/// Placeholders that the templates of the passes before the final command must use, their
+/// output is usually discarded (e.g. `-f null -`).
+pub const PASS_REQUIRED_KEYS: &[&str] = &["input"];
+
+/// Placeholders of a template, lowercased.
+fn keys(template: &Template<'_>) -> Vec<String> {
+    template.keys().map(|key| key.to_lowercase()).collect()
+}
+
+/// Whether a template uses a placeholder, whatever its case.
+pub fn has_key(template: &Template<'_>, key: &str) -> bool {
+    template.keys().any(|k| k.eq_ignore_ascii_case(key))
+}
+
+/// Parse an ffmpeg command template, as stored in `transcoding_job.ffmpeg_command`.
+pub fn parse(ffmpeg_command: &str) -> Result<Template<'_>> {
+    Template::parse(ffmpeg_command)
+        .map_err(|err| anyhow!("Invalid ffmpeg command template: {}", err))
+}
+
+/// Check that an ffmpeg command template can be rendered by a worker.
+///
+/// The template must use every [`REQUIRED_KEYS`] placeholder, and any other placeholder
+/// must either be a [`BUILTIN_KEYS`] or one of the declared worker variables (provided by the
+/// workers through the `transcodeck_template_*` environment variables).
+pub fn validate(ffmpeg_command: &str, worker_variables: &[String]) -> Result<()> {
+    validate_keys(ffmpeg_command, REQUIRED_KEYS, worker_variables)
+}
+
+/// Check that the template of a pass before the final command can be rendered by a worker,
+/// like [`validate`] but only requiring the [`PASS_REQUIRED_KEYS`] placeholders.
+pub fn validate_pass(ffmpeg_command: &str, worker_variables: &[String]) -> Result<()> {
+    validate_keys(ffmpeg_command, PASS_REQUIRED_KEYS, worker_variables)
+}
+
+fn validate_keys(
+    ffmpeg_command: &str,
+    required: &[&str],
+    worker_variables: &[String],
+) -> Result<()> {
+    if ffmpeg_command.trim().is_empty() {
+        bail!("ffmpeg_command cannot be empty");
+    }
+    let template = parse(ffmpeg_command)?;
+
+    for key in required {
+        if !has_key(&template, key) {
+            bail!(
+                "ffmpeg command template must use the {{{}}} placeholder",
+                key
+            );
+        }
+    }
+
+    let mut unknown = keys(&template)
+        .into_iter()
+        .filter(|key| !BUILTIN_KEYS.contains(&key.as_str()))
+        .filter(|key| !worker_variables.contains(key))
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown.dedup();
+    if !unknown.is_empty() {
+        bail!(
+            "Unknown placeholders in ffmpeg command template: {} (declare them as worker variables with --var)",
+            unknown.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// List the worker variables used by an ffmpeg command template, lowercased.
+pub fn worker_variables(template: &Template<'_>) -> Vec<String> {
+    let mut variables = keys(template)
+        .into_iter()
+        .filter(|key| !BUILTIN_KEYS.contains(&key.as_str()))
+        .collect::<Vec<_>>();
+    variables.sort();
+    variables.dedup();
+    variables
+}
+
+/// List the encoders used by an ffmpeg command template, e.g. `libx265` for `-c:v libx265`.
+///
+/// Encoders given through a placeholder cannot be known in advance and are ignored.
+pub fn encoders(ffmpeg_command: &str) -> Vec<String> {
+    let mut encoders = Vec::new();
+    let mut args = ffmpeg_command.split_whitespace();
+    while let Some(arg) = args.next() {
+        let flag = arg.split(':').next().unwrap_or(arg);
+        if !CODEC_FLAGS.contains(&flag) {
+            continue;
+        }
+        if let Some(codec) = args.next() {
+            if codec != "copy" && !codec.contains('{') && !encoders.iter().any(|e| e == codec) {
+                encoders.push(codec.to_string());
+            }
+        }
+    }
+    encoders
+}
+
+/// Values of the placeholders, looked up by their lowercased name.
+struct LowercaseValues<'a>(&'a HashMap<String, String>);
+
+impl Values for LowercaseValues<'_> {
+    fn get_value(&self, key: &str) -> Option<Cow<'_, str>> {
+        self.0
+            .get(&key.to_lowercase())
+            .map(|value| Cow::Borrowed(value.as_str()))
+    }
+}
+
+/// Render an ffmpeg command template into the arguments to pass to ffmpeg. The names of the
+/// `values` are lowercase.
+pub fn render_args(
+    template: &Template<'_>,
+    values: &HashMap<String, String>,
+) -> Result<Vec<String>> {
+    let command = template.render(&LowercaseValues(values))?;
+    Ok(command
+        .split_whitespace()
+        .map(|arg| arg.to_string())
+        .collect())
+}
+
+/// Run an ffmpeg command template, after the templates of its passes, against a tiny
+/// generated test clip.
+///
+/// `values` provides the sample values of the worker variables, `input`, `output` and
+/// `passlogfile` are filled in by this function. With a policy, every command is checked
+/// against it before being run, and can only use the temporary directory of the dry-run.
+pub async fn dry_run(
+    ffmpeg_bin: &str,
+    ffmpeg_passes: &[String],
+    ffmpeg_command: &str,
+    values: &HashMap<String, String>,
+    policy: Option<&Policy>,
+) -> Result<()> {
+    let tmp_dir = TempDir::new("transcodeck-dry-run")?;
+    let input = tmp_dir.path().join("sample.mkv");
+    let output = tmp_dir.path().join("output.mkv");
+
+    generate_sample(ffmpeg_bin, &input).await?;
+
+    let mut values = values.clone();
+    values.insert("input".into(), input.to_string_lossy().to_string());
+    values.insert("output".into(), output.to_string_lossy().to_string());
+    values.insert(
+        "passlogfile".into(),
+        tmp_dir.path().join("passlog").to_string_lossy().to_string(),
+    );
+    let commands = ffmpeg_passes
+        .iter()
+        .map(String::as_str)
+        .chain(iter::once(ffmpeg_command));
+    for command in commands {
+        let args = render_args(&parse(command)?, &values)?;
+        if let Some(policy) = policy {
+            policy
+                .check(&args, tmp_dir.path(), tmp_dir.path())
+                .map_err(|err| anyhow!("Rejected by the sandbox policy: {}", err))?;
+        }
+        let output = Command::new(ffmpeg_bin)
+            .arg("-hide_banner")
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-y")
+            .args(args)
+            .current_dir(tmp_dir.path())
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!(
+                "Dry-run of the ffmpeg command failed: status={:?}\n{}",
+                output.status.code(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    if let Err(e) = tmp_dir.close() {
+        warn!("Failed to clean up temporary directory: {}", e);
+    }
+    Ok(())
+}
+
+/// Generate a one second test clip, similar to a media fragment.
+async fn generate_sample(ffmpeg_bin: &str, output: impl AsRef<Path>) -> Result<()> {
+    let status = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y")
+        .arg("-f")
+        .arg("lavfi")
+        .arg("-i")
+        .arg("testsrc=duration=1:size=320x240:rate=25")
+        .arg("-c:v")
+        .arg("ffv1")
+        .arg(output.as_ref())
+        .status()
+        .await?;
+
+    if !status.success() {
+        bail!("Failed to generate test clip: status={:?}", status.code());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requires_input_and_output() {
+        validate("-i {input} -c:v libx264 {output}", &[]).unwrap();
+        let err = validate("-i {input} -c:v libx264 out.mkv", &[]).unwrap_err();
+        assert!(err.to_string().contains("{output}"));
+        let err = validate("-i in.mkv -c:v libx264 {output}", &[]).unwrap_err();
+        assert!(err.to_string().contains("{input}"));
+        assert!(validate("  ", &[]).is_err());
+    }
+
+    #[test]
+    fn passes_only_require_the_input() {
+        validate_pass(
+            "-i {input} -pass 1 -passlogfile {passlogfile} -f null -",
+            &[],
+        )
+        .unwrap();
+        assert!(validate_pass("-pass 1 -f null -", &[]).is_err());
+    }
+
+    #[test]
+    fn accepts_builtin_keys() {
+        validate(
+            "-i {input} -passlogfile {passlogfile} -crf {crf} {output}",
+            &[],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_undeclared_variables() {
+        let err = validate("-i {input} -svtav1-params lp={lp} {output}", &[]).unwrap_err();
+        assert!(err.to_string().contains("lp"));
+        validate(
+            "-i {input} -svtav1-params lp={lp} {output}",
+            &["lp".to_string()],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn ignores_the_case_of_the_placeholders() {
+        // The worker variables are declared and provided lowercased.
+        let command = "-i {INPUT} -svtav1-params lp={LP} -crf {Crf} {output}";
+        validate(command, &["lp".to_string()]).unwrap();
+        let template = parse(command).unwrap();
+        assert_eq!(worker_variables(&template), ["lp"]);
+        assert!(has_key(&template, "crf"));
+
+        let values = HashMap::from([
+            ("input".to_string(), "in.mkv".to_string()),
+            ("output".to_string(), "out.mkv".to_string()),
+            ("lp".to_string(), "2".to_string()),
+            ("crf".to_string(), "30".to_string()),
+        ]);
+        assert_eq!(
+            render_args(&template, &values).unwrap(),
+            [
+                "-i",
+                "in.mkv",
+                "-svtav1-params",
+                "lp=2",
+                "-crf",
+                "30",
+                "out.mkv"
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_worker_variables() {
+        let template =
+            parse("-i {input} -threads {threads} -crf {crf} {output} {lp} {lp}").unwrap();
+        assert_eq!(worker_variables(&template), ["lp", "threads"]);
+    }
+
+    #[test]
+    fn lists_encoders() {
+        assert_eq!(
+            encoders("-i {input} -c:v libx265 -c:a copy -acodec libopus -c:s {codec} {output}"),
+            ["libx265", "libopus"]
+        );
+    }
+
+    #[test]
+    fn renders_arguments() {
+        let values = HashMap::from([
+            ("input".to_string(), "in.mkv".to_string()),
+            ("output".to_string(), "out.mkv".to_string()),
+        ]);
+        let args = render_args(
+            &parse("-i {input}  -c:v libx264 {output}").unwrap(),
+            &values,
+        );
+        assert_eq!(
+            args.unwrap(),
+            ["-i", "in.mkv", "-c:v", "libx264", "out.mkv"]
+        );
+    }
+}
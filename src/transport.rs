@@ -0,0 +1,601 @@
+use anyhow::{anyhow, bail, Result};
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::scheduler::{self, SchedulingPolicy};
+use crate::{job, migrate, model, schema, webhook};
+use model::{FragmentJobStatus, JobStatus};
+
+/// How a daemon reads and updates the queue: directly in the database, or through the
+/// worker API of a `transcodeck serve` instance.
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    /// Register (or update the registration of) a worker, returns the ID of the worker.
+    async fn register(&mut self, worker: &model::NewWorker) -> Result<Uuid>;
+
+    /// Update the heartbeat of a worker, returns whether a drain was requested.
+    async fn heartbeat(&mut self, worker_id: Uuid, heartbeat: &Heartbeat) -> Result<bool>;
+
+    /// Claim the next fragment job this worker should process, according to the scheduling policy.
+    async fn claim(
+        &mut self,
+        worker_id: Uuid,
+        policy: SchedulingPolicy,
+    ) -> Result<Option<Assignment>>;
+
+    /// Update the progress of a claimed fragment job.
+    async fn progress(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        progress: &model::FragmentJobProgress,
+    ) -> Result<()>;
+
+    /// Record the probed duration of the fragment of a claimed fragment job.
+    async fn duration(&mut self, transcoding_fragment_job_id: Uuid, duration: f64) -> Result<()>;
+
+    /// Mark a claimed fragment job as completed.
+    async fn complete(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        stats: &model::FragmentJobStats,
+    ) -> Result<()>;
+
+    /// Mark a claimed fragment job as failed, and store the reason and the ffmpeg logs (if any).
+    async fn fail(&mut self, transcoding_fragment_job_id: Uuid, failure: &Failure) -> Result<()>;
+
+    /// Return an interrupted fragment job to the queue, for another worker to process.
+    async fn requeue(&mut self, transcoding_fragment_job_id: Uuid) -> Result<()>;
+
+    /// Count the fragment jobs by status, for the metrics of the daemon.
+    async fn queue_depth(&mut self) -> Result<Vec<QueueDepth>>;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub current_fragment_job_id: Option<Uuid>,
+    pub free_disk_space: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeartbeatResponse {
+    pub draining: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterResponse {
+    pub worker_id: Uuid,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimRequest {
+    pub schedule: SchedulingPolicy,
+}
+
+/// A fragment job claimed by a worker, with everything needed to transcode it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Assignment {
+    pub transcoding_fragment_job_id: Uuid,
+    pub transcoding_job_id: Uuid,
+    pub fragment: model::Fragment,
+    pub ffmpeg_command: String,
+    /// ffmpeg commands run before `ffmpeg_command`, in order.
+    #[serde(default)]
+    pub ffmpeg_passes: Vec<String>,
+    /// Renditions of the job, transcoded instead of the ffmpeg command if not empty.
+    #[serde(default)]
+    pub renditions: Vec<model::TranscodingRendition>,
+    /// Seconds of the previous and next fragments to encode around the fragment.
+    #[serde(default)]
+    pub overlap: f64,
+    /// The fragments before and after this one, if the job has an overlap.
+    #[serde(default)]
+    pub previous: Option<model::Fragment>,
+    #[serde(default)]
+    pub next: Option<model::Fragment>,
+    /// Quality check of the outputs, if the job has a quality metric.
+    #[serde(default)]
+    pub quality_metric: Option<model::QualityMetric>,
+    #[serde(default)]
+    pub quality_threshold: Option<f64>,
+    #[serde(default)]
+    pub fail_below_threshold: bool,
+    /// VMAF score targeted by the search of the `{crf}` placeholder, between `crf_min` and
+    /// `crf_max`.
+    #[serde(default)]
+    pub target_vmaf: Option<f64>,
+    #[serde(default)]
+    pub crf_min: i32,
+    #[serde(default)]
+    pub crf_max: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DurationRequest {
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Failure {
+    pub message: String,
+    pub exit_status: Option<i32>,
+    pub log: String,
+    /// Quality score of the output, if it is below the threshold of the job.
+    #[serde(default)]
+    pub quality_score: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueDepth {
+    pub status: FragmentJobStatus,
+    pub count: i64,
+}
+
+/// An error returned by the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// The fragment of the same media with the given number, if any.
+fn neighbour(
+    db: &mut DbConnection,
+    fragment: &model::Fragment,
+    fragment_number: i32,
+) -> QueryResult<Option<model::Fragment>> {
+    schema::fragment::table
+        .filter(schema::fragment::media_id.eq(fragment.media_id))
+        .filter(schema::fragment::fragment_number.eq(fragment_number))
+        .filter(schema::fragment::deleted_at.is_null())
+        .select(model::Fragment::as_select())
+        .first(db)
+        .optional()
+}
+
+/// Direct access to the database, the daemon needs the database credentials.
+pub struct DbTransport<'a> {
+    db: &'a mut DbConnection,
+}
+
+impl<'a> DbTransport<'a> {
+    /// Use a database connection, its schema must match this binary.
+    pub fn new(db: &'a mut DbConnection) -> Result<Self> {
+        migrate::check_schema(db)?;
+        Ok(DbTransport { db })
+    }
+
+    /// Use a database connection whose schema was already checked.
+    pub fn unchecked(db: &'a mut DbConnection) -> Self {
+        DbTransport { db }
+    }
+}
+
+impl Transport for DbTransport<'_> {
+    async fn register(&mut self, worker: &model::NewWorker) -> Result<Uuid> {
+        // Not an upsert, as `ON CONFLICT` is not supported by every backend.
+        let updated = diesel::update(schema::worker::table)
+            .filter(schema::worker::worker_id.eq(worker.worker_id))
+            .set((
+                worker,
+                schema::worker::current_fragment_job_id.eq(None::<Uuid>),
+                schema::worker::started_at.eq(diesel::dsl::now),
+                schema::worker::last_seen_at.eq(diesel::dsl::now),
+                schema::worker::draining.eq(false),
+            ))
+            .execute(self.db)?;
+        if updated == 0 {
+            diesel::insert_into(schema::worker::table)
+                .values(worker)
+                .execute(self.db)?;
+        }
+        Ok(worker.worker_id)
+    }
+
+    async fn heartbeat(&mut self, worker_id: Uuid, heartbeat: &Heartbeat) -> Result<bool> {
+        let draining = diesel::update(schema::worker::table)
+            .filter(schema::worker::worker_id.eq(worker_id))
+            .set((
+                schema::worker::last_seen_at.eq(diesel::dsl::now),
+                schema::worker::current_fragment_job_id.eq(heartbeat.current_fragment_job_id),
+                schema::worker::free_disk_space.eq(heartbeat.free_disk_space),
+            ))
+            .returning(schema::worker::draining)
+            .get_result(self.db)?;
+        Ok(draining)
+    }
+
+    async fn claim(
+        &mut self,
+        worker_id: Uuid,
+        policy: SchedulingPolicy,
+    ) -> Result<Option<Assignment>> {
+        let worker = schema::worker::table
+            .filter(schema::worker::worker_id.eq(worker_id))
+            .select(model::Worker::as_select())
+            .first(self.db)?;
+
+        loop {
+            // Searching for a fragment job that is queued, and that this worker can process
+            let Some(model::JobResume {
+                transcoding_fragment_job_id,
+                transcoding_job_id,
+                fragment_id,
+            }) = scheduler::next_fragment_job(self.db, &worker, policy)?
+            else {
+                return Ok(None);
+            };
+
+            // Updating the fragment job to in progress, if it is still queued. The conditional
+            // update is atomic on every backend, no row locking (`SKIP LOCKED`) is needed.
+            let changed = diesel::update(schema::transcoding_fragment_job::table)
+                .filter(
+                    schema::transcoding_fragment_job::transcoding_fragment_job_id
+                        .eq(transcoding_fragment_job_id),
+                )
+                .filter(schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued))
+                .set((
+                    schema::transcoding_fragment_job::status.eq(FragmentJobStatus::InProgress),
+                    schema::transcoding_fragment_job::started_at.eq(diesel::dsl::now),
+                    schema::transcoding_fragment_job::worker_id.eq(worker_id),
+                ))
+                .execute(self.db)?;
+            if changed == 0 {
+                // Claimed by another worker in the meantime
+                continue;
+            }
+
+            webhook::record(
+                self.db,
+                webhook::Event::FragmentStarted,
+                transcoding_job_id,
+                Some(transcoding_fragment_job_id),
+            )?;
+
+            let fragment = schema::fragment::table
+                .filter(schema::fragment::fragment_id.eq(fragment_id))
+                .select(model::Fragment::as_select())
+                .first(self.db)?;
+            let job = schema::transcoding_job::table
+                .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
+                .select(model::TranscodingJob::as_select())
+                .first(self.db)?;
+            let overlap = job.overlap;
+            let renditions = job::renditions(self.db, transcoding_job_id)?;
+            let (previous, next) = match fragment.fragment_number {
+                Some(number) if overlap > 0.0 => (
+                    neighbour(self.db, &fragment, number - 1)?,
+                    neighbour(self.db, &fragment, number + 1)?,
+                ),
+                _ => (None, None),
+            };
+
+            // Update the parent transcoding job to in progress, if it is still queued.
+            if diesel::update(schema::transcoding_job::table)
+                .set(schema::transcoding_job::status.eq(JobStatus::InProgress))
+                .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
+                .filter(schema::transcoding_job::status.eq(JobStatus::Queued))
+                .execute(self.db)?
+                > 0
+            {
+                info!(%transcoding_job_id, "Started transcoding job");
+                webhook::record(
+                    self.db,
+                    webhook::Event::JobStarted,
+                    transcoding_job_id,
+                    None,
+                )?;
+            }
+
+            return Ok(Some(Assignment {
+                transcoding_fragment_job_id,
+                transcoding_job_id,
+                fragment,
+                ffmpeg_command: job.ffmpeg_command,
+                ffmpeg_passes: job.ffmpeg_passes,
+                renditions,
+                overlap,
+                previous,
+                next,
+                quality_metric: job.quality_metric,
+                quality_threshold: job.quality_threshold,
+                fail_below_threshold: job.fail_below_threshold,
+                target_vmaf: job.target_vmaf,
+                crf_min: job.crf_min,
+                crf_max: job.crf_max,
+            }));
+        }
+    }
+
+    async fn progress(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        progress: &model::FragmentJobProgress,
+    ) -> Result<()> {
+        diesel::update(schema::transcoding_fragment_job::table)
+            .set(progress)
+            .filter(
+                schema::transcoding_fragment_job::transcoding_fragment_job_id
+                    .eq(transcoding_fragment_job_id),
+            )
+            .execute(self.db)?;
+        Ok(())
+    }
+
+    async fn duration(&mut self, transcoding_fragment_job_id: Uuid, duration: f64) -> Result<()> {
+        let fragment_id = schema::transcoding_fragment_job::table
+            .filter(
+                schema::transcoding_fragment_job::transcoding_fragment_job_id
+                    .eq(transcoding_fragment_job_id),
+            )
+            .select(schema::transcoding_fragment_job::fragment_id)
+            .first::<Uuid>(self.db)?;
+        diesel::update(schema::fragment::table)
+            .filter(schema::fragment::fragment_id.eq(fragment_id))
+            .set(schema::fragment::duration.eq(duration))
+            .execute(self.db)?;
+        Ok(())
+    }
+
+    async fn complete(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        stats: &model::FragmentJobStats,
+    ) -> Result<()> {
+        self.db.transaction(|db| {
+            diesel::update(schema::transcoding_fragment_job::table)
+                .set((
+                    schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Completed),
+                    schema::transcoding_fragment_job::completed_at.eq(diesel::dsl::now),
+                    stats,
+                ))
+                .filter(
+                    schema::transcoding_fragment_job::transcoding_fragment_job_id
+                        .eq(transcoding_fragment_job_id),
+                )
+                .execute(db)?;
+            let transcoding_job_id = transcoding_job_id(db, transcoding_fragment_job_id)?;
+            webhook::record(
+                db,
+                webhook::Event::FragmentCompleted,
+                transcoding_job_id,
+                Some(transcoding_fragment_job_id),
+            )?;
+
+            // The transcoding job is completed with its last fragment.
+            let remaining = schema::transcoding_fragment_job::table
+                .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(transcoding_job_id))
+                .filter(schema::transcoding_fragment_job::status.ne(FragmentJobStatus::Completed))
+                .count()
+                .get_result::<i64>(db)?;
+            if remaining == 0
+                && diesel::update(schema::transcoding_job::table)
+                    .set(schema::transcoding_job::status.eq(JobStatus::Completed))
+                    .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
+                    .filter(schema::transcoding_job::status.ne(JobStatus::Completed))
+                    .execute(db)?
+                    > 0
+            {
+                info!(%transcoding_job_id, "Completed transcoding job");
+                webhook::record(db, webhook::Event::JobCompleted, transcoding_job_id, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn fail(&mut self, transcoding_fragment_job_id: Uuid, failure: &Failure) -> Result<()> {
+        self.db.transaction(|db| {
+            diesel::update(schema::transcoding_fragment_job::table)
+                .set((
+                    schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Failed),
+                    schema::transcoding_fragment_job::quality_score.eq(failure.quality_score),
+                    schema::transcoding_fragment_job::quality_flagged
+                        .eq(failure.quality_score.is_some()),
+                ))
+                .filter(
+                    schema::transcoding_fragment_job::transcoding_fragment_job_id
+                        .eq(transcoding_fragment_job_id),
+                )
+                .execute(db)?;
+            diesel::insert_into(schema::transcoding_fragment_job_log::table)
+                .values(&model::NewTranscodingFragmentJobLog {
+                    transcoding_fragment_job_id,
+                    exit_status: failure.exit_status,
+                    message: failure.message.clone(),
+                    log: failure.log.clone(),
+                })
+                .execute(db)?;
+            let transcoding_job_id = transcoding_job_id(db, transcoding_fragment_job_id)?;
+            webhook::record(
+                db,
+                webhook::Event::FragmentFailed,
+                transcoding_job_id,
+                Some(transcoding_fragment_job_id),
+            )?;
+
+            // The transcoding job fails with its first failed fragment, until it is retried.
+            if diesel::update(schema::transcoding_job::table)
+                .set(schema::transcoding_job::status.eq(JobStatus::Failed))
+                .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
+                .filter(
+                    schema::transcoding_job::status
+                        .eq(JobStatus::Queued)
+                        .or(schema::transcoding_job::status.eq(JobStatus::InProgress)),
+                )
+                .execute(db)?
+                > 0
+            {
+                webhook::record(db, webhook::Event::JobFailed, transcoding_job_id, None)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn requeue(&mut self, transcoding_fragment_job_id: Uuid) -> Result<()> {
+        diesel::update(schema::transcoding_fragment_job::table)
+            .set((
+                schema::transcoding_fragment_job::status.eq(FragmentJobStatus::Queued),
+                schema::transcoding_fragment_job::started_at.eq(None::<chrono::NaiveDateTime>),
+                schema::transcoding_fragment_job::worker_id.eq(None::<Uuid>),
+                &model::FragmentJobProgress::default(),
+            ))
+            .filter(
+                schema::transcoding_fragment_job::transcoding_fragment_job_id
+                    .eq(transcoding_fragment_job_id),
+            )
+            .execute(self.db)?;
+        let transcoding_job_id = transcoding_job_id(self.db, transcoding_fragment_job_id)?;
+        webhook::record(
+            self.db,
+            webhook::Event::FragmentQueued,
+            transcoding_job_id,
+            Some(transcoding_fragment_job_id),
+        )?;
+        Ok(())
+    }
+
+    async fn queue_depth(&mut self) -> Result<Vec<QueueDepth>> {
+        Ok(schema::transcoding_fragment_job::table
+            .group_by(schema::transcoding_fragment_job::status)
+            .select((
+                schema::transcoding_fragment_job::status,
+                diesel::dsl::count_star(),
+            ))
+            .load::<(FragmentJobStatus, i64)>(self.db)?
+            .into_iter()
+            .map(|(status, count)| QueueDepth { status, count })
+            .collect())
+    }
+}
+
+fn transcoding_job_id(
+    db: &mut DbConnection,
+    transcoding_fragment_job_id: Uuid,
+) -> QueryResult<Uuid> {
+    schema::transcoding_fragment_job::table
+        .filter(
+            schema::transcoding_fragment_job::transcoding_fragment_job_id
+                .eq(transcoding_fragment_job_id),
+        )
+        .select(schema::transcoding_fragment_job::transcoding_job_id)
+        .first(db)
+}
+
+/// Access through the worker API of a `transcodeck serve` instance, authenticated with
+/// a worker token, the daemon does not need the database credentials.
+pub struct ApiTransport {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl ApiTransport {
+    pub fn new(url: &str, token: &str) -> Self {
+        ApiTransport {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<reqwest::Response> {
+        let response = self
+            .http
+            .post(format!("{}/api/worker/{}", self.url, path))
+            .bearer_auth(&self.token)
+            .json(body)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let error = response
+                .json::<ErrorResponse>()
+                .await
+                .map(|response| response.error)
+                .unwrap_or_default();
+            bail!("Worker API request {} failed: {} {}", path, status, error);
+        }
+        Ok(response)
+    }
+
+    async fn post_json<T: DeserializeOwned>(&self, path: &str, body: &impl Serialize) -> Result<T> {
+        self.post(path, body)
+            .await?
+            .json()
+            .await
+            .map_err(|err| anyhow!("Invalid response to worker API request {}: {}", path, err))
+    }
+}
+
+impl Transport for ApiTransport {
+    async fn register(&mut self, worker: &model::NewWorker) -> Result<Uuid> {
+        let response: RegisterResponse = self.post_json("register", worker).await?;
+        Ok(response.worker_id)
+    }
+
+    async fn heartbeat(&mut self, _worker_id: Uuid, heartbeat: &Heartbeat) -> Result<bool> {
+        let response: HeartbeatResponse = self.post_json("heartbeat", heartbeat).await?;
+        Ok(response.draining)
+    }
+
+    async fn claim(
+        &mut self,
+        _worker_id: Uuid,
+        policy: SchedulingPolicy,
+    ) -> Result<Option<Assignment>> {
+        let response = self
+            .post("claim", &ClaimRequest { schedule: policy })
+            .await?;
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        Ok(Some(response.json().await.map_err(|err| {
+            anyhow!("Invalid response to worker API request claim: {}", err)
+        })?))
+    }
+
+    async fn progress(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        progress: &model::FragmentJobProgress,
+    ) -> Result<()> {
+        let path = format!("fragment-jobs/{}/progress", transcoding_fragment_job_id);
+        self.post(&path, progress).await?;
+        Ok(())
+    }
+
+    async fn duration(&mut self, transcoding_fragment_job_id: Uuid, duration: f64) -> Result<()> {
+        let path = format!("fragment-jobs/{}/duration", transcoding_fragment_job_id);
+        self.post(&path, &DurationRequest { duration }).await?;
+        Ok(())
+    }
+
+    async fn complete(
+        &mut self,
+        transcoding_fragment_job_id: Uuid,
+        stats: &model::FragmentJobStats,
+    ) -> Result<()> {
+        let path = format!("fragment-jobs/{}/complete", transcoding_fragment_job_id);
+        self.post(&path, stats).await?;
+        Ok(())
+    }
+
+    async fn fail(&mut self, transcoding_fragment_job_id: Uuid, failure: &Failure) -> Result<()> {
+        let path = format!("fragment-jobs/{}/fail", transcoding_fragment_job_id);
+        self.post(&path, failure).await?;
+        Ok(())
+    }
+
+    async fn requeue(&mut self, transcoding_fragment_job_id: Uuid) -> Result<()> {
+        let path = format!("fragment-jobs/{}/requeue", transcoding_fragment_job_id);
+        self.post(&path, &()).await?;
+        Ok(())
+    }
+
+    async fn queue_depth(&mut self) -> Result<Vec<QueueDepth>> {
+        self.post_json("queue", &()).await
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+use tokio::process::Command;
+
+use crate::probe;
+
+/// Maximum difference between the duration of an output and the duration of what was
+/// encoded, in seconds, or else as a fraction of the duration for longer fragments.
+const DURATION_TOLERANCE: f64 = 0.5;
+const DURATION_TOLERANCE_RATIO: f64 = 0.02;
+
+/// Check that ffmpeg wrote a complete output, before the fragment job is completed.
+///
+/// The output must exist and not be empty, have a video stream, last `duration` seconds (if
+/// known) and decode without any error.
+pub async fn output(
+    ffmpeg_bin: &str,
+    ffprobe_bin: &str,
+    output: &Path,
+    duration: Option<f64>,
+) -> Result<()> {
+    match tokio::fs::metadata(output).await {
+        Ok(metadata) if metadata.len() == 0 => bail!("Output is empty"),
+        Ok(_) => {}
+        Err(err) => bail!("Output is missing: {}", err),
+    }
+
+    let streams = probe::stream_types(ffprobe_bin, output).await?;
+    if !streams.iter().any(|stream| stream == "video") {
+        bail!(
+            "Output has no video stream (streams: {})",
+            streams.join(", ")
+        );
+    }
+
+    if let Some(expected) = duration {
+        let actual = probe::duration(ffprobe_bin, output).await?;
+        let tolerance = DURATION_TOLERANCE.max(expected * DURATION_TOLERANCE_RATIO);
+        if (actual - expected).abs() > tolerance {
+            bail!(
+                "Output duration is {:.3}s instead of {:.3}s",
+                actual,
+                expected
+            );
+        }
+    }
+
+    // Truncated or corrupted outputs make the decoder log errors, without failing.
+    let result = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-i")
+        .arg(output)
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await?;
+    let errors = String::from_utf8_lossy(&result.stderr);
+    if !result.status.success() || !errors.trim().is_empty() {
+        match errors.lines().find(|line| !line.trim().is_empty()) {
+            Some(error) => bail!("Output does not decode cleanly: {}", error.trim()),
+            None => bail!(
+                "Output does not decode cleanly: status={:?}",
+                result.status.code()
+            ),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,467 @@
+use anyhow::Result;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::{model, schema, WebhookCommand, WebhookOptions, WebhookSubcommand};
+use model::{FragmentJobStatus, JobStatus, WebhookDelivery, WebhookDeliveryStatus};
+
+/// Interval between two runs of the dispatcher.
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of attempts before a delivery is given up.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Delay before the first retry of a delivery, doubled after every failed attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Maximum delay between two attempts of a delivery.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+
+/// A delivery is reserved by a dispatcher for this long while it is being sent.
+const DELIVERY_LEASE: Duration = Duration::from_secs(120);
+
+/// Whether a global webhook is configured, in which case the events of every job are
+/// recorded, instead of only the ones of the jobs with their own webhook URL.
+static GLOBAL_WEBHOOK: AtomicBool = AtomicBool::new(false);
+
+/// Set at startup, from the webhook URL of the configuration (`--webhook-url`,
+/// `TRANSCODECK_WEBHOOK_URL` or `[webhooks] url`).
+pub fn set_global_webhook(configured: bool) {
+    GLOBAL_WEBHOOK.store(configured, Ordering::Relaxed);
+}
+
+/// A change of state of a transcoding job, or of one of its fragment jobs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    JobQueued,
+    JobStarted,
+    JobCompleted,
+    JobFailed,
+    JobCancelled,
+    /// The fragments of a completed job were packaged for streaming.
+    JobAssembled,
+    FragmentQueued,
+    FragmentStarted,
+    FragmentCompleted,
+    FragmentFailed,
+}
+
+impl Event {
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::JobQueued => "job.queued",
+            Event::JobStarted => "job.started",
+            Event::JobCompleted => "job.completed",
+            Event::JobFailed => "job.failed",
+            Event::JobCancelled => "job.cancelled",
+            Event::JobAssembled => "job.assembled",
+            Event::FragmentQueued => "fragment.queued",
+            Event::FragmentStarted => "fragment.started",
+            Event::FragmentCompleted => "fragment.completed",
+            Event::FragmentFailed => "fragment.failed",
+        }
+    }
+}
+
+/// Record an event, to be delivered to the webhooks by a dispatcher, with the statuses of
+/// the job and of the fragment job after the change. The events of the jobs without webhook
+/// are not recorded, unless a global webhook is configured.
+pub fn record(
+    db: &mut DbConnection,
+    event: Event,
+    transcoding_job_id: Uuid,
+    transcoding_fragment_job_id: Option<Uuid>,
+) -> QueryResult<()> {
+    let (job_status, webhook_url) = schema::transcoding_job::table
+        .filter(schema::transcoding_job::transcoding_job_id.eq(transcoding_job_id))
+        .select((
+            schema::transcoding_job::status,
+            schema::transcoding_job::webhook_url,
+        ))
+        .first::<(JobStatus, Option<String>)>(db)?;
+    if webhook_url.is_none() && !GLOBAL_WEBHOOK.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let fragments = schema::transcoding_fragment_job::table
+        .filter(schema::transcoding_fragment_job::transcoding_job_id.eq(transcoding_job_id))
+        .select((
+            schema::transcoding_fragment_job::transcoding_fragment_job_id,
+            schema::transcoding_fragment_job::status,
+        ))
+        .load::<(Uuid, FragmentJobStatus)>(db)?;
+    let fragment_status = fragments
+        .iter()
+        .find(|(id, _)| Some(*id) == transcoding_fragment_job_id)
+        .map(|(_, status)| status.clone());
+
+    diesel::insert_into(schema::webhook_event::table)
+        .values(&model::NewWebhookEvent {
+            event: event.name().to_string(),
+            transcoding_job_id,
+            transcoding_fragment_job_id,
+            job_status,
+            completed_fragments: fragments
+                .iter()
+                .filter(|(_, status)| *status == FragmentJobStatus::Completed)
+                .count() as i32,
+            total_fragments: fragments.len() as i32,
+            fragment_status,
+        })
+        .execute(db)?;
+    Ok(())
+}
+
+/// JSON body POSTed to the webhooks.
+///
+/// The statuses and fragment counts are the ones right after the event.
+#[derive(Debug, Clone, Serialize)]
+pub struct Payload {
+    pub id: Uuid,
+    pub event: String,
+    pub created_at: NaiveDateTime,
+    pub transcoding_job_id: Uuid,
+    pub media_id: Uuid,
+    pub status: JobStatus,
+    pub completed_fragments: usize,
+    pub total_fragments: usize,
+    pub transcoding_fragment_job_id: Option<Uuid>,
+    pub fragment_id: Option<Uuid>,
+    pub fragment_status: Option<FragmentJobStatus>,
+}
+
+/// A delivery of an event to a webhook, as listed by `webhook log`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeliveryEntry {
+    pub event: String,
+    pub transcoding_job_id: Uuid,
+    #[serde(flatten)]
+    pub delivery: WebhookDelivery,
+}
+
+/// Delivers the recorded events to the global webhook and to the webhooks of the jobs.
+pub struct Dispatcher {
+    http: reqwest::Client,
+    url: Option<String>,
+    secret: Option<String>,
+}
+
+impl Dispatcher {
+    pub fn new(options: &WebhookOptions) -> Result<Self> {
+        Ok(Dispatcher {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            url: options.webhook_url.clone(),
+            secret: options.webhook_secret.clone(),
+        })
+    }
+
+    /// Dispatch the events and deliver them until the process stops.
+    pub async fn run(self, db: Arc<Mutex<DbConnection>>) {
+        loop {
+            if let Err(err) = self.dispatch(&db).await {
+                error!("Webhook dispatch failed: {:#}", err);
+            }
+            tokio::time::sleep(DISPATCH_INTERVAL).await;
+        }
+    }
+
+    async fn dispatch(&self, db: &Mutex<DbConnection>) -> Result<()> {
+        self.create_deliveries(&mut *db.lock().await)?;
+        self.deliver(db).await
+    }
+
+    /// Create a delivery for every webhook of the new events.
+    fn create_deliveries(&self, db: &mut DbConnection) -> Result<()> {
+        let events = schema::webhook_event::table
+            .filter(schema::webhook_event::dispatched_at.is_null())
+            .order(schema::webhook_event::created_at.asc())
+            .select(model::WebhookEvent::as_select())
+            .limit(100)
+            .load(db)?;
+
+        for event in events {
+            let now = Utc::now().naive_utc();
+            db.transaction(|db| {
+                // Another dispatcher might have taken the event in the meantime.
+                let claimed = diesel::update(schema::webhook_event::table)
+                    .filter(schema::webhook_event::webhook_event_id.eq(event.webhook_event_id))
+                    .filter(schema::webhook_event::dispatched_at.is_null())
+                    .set(schema::webhook_event::dispatched_at.eq(now))
+                    .execute(db)?;
+                if claimed == 0 {
+                    return Ok(());
+                }
+
+                let job = schema::transcoding_job::table
+                    .filter(
+                        schema::transcoding_job::transcoding_job_id.eq(event.transcoding_job_id),
+                    )
+                    .select(model::TranscodingJob::as_select())
+                    .first(db)?;
+                let mut urls = self.url.iter().cloned().collect::<Vec<_>>();
+                if let Some(url) = &job.webhook_url {
+                    if !urls.contains(url) {
+                        urls.push(url.clone());
+                    }
+                }
+                if urls.is_empty() {
+                    return Ok(());
+                }
+
+                let payload = serde_json::to_string(&payload(db, &event, job)?)?;
+                // One insert per row, multi-row inserts are not supported by every backend.
+                for url in urls {
+                    diesel::insert_into(schema::webhook_delivery::table)
+                        .values(&model::NewWebhookDelivery {
+                            webhook_event_id: event.webhook_event_id,
+                            url,
+                            payload: payload.clone(),
+                            status: WebhookDeliveryStatus::Pending,
+                            next_attempt_at: now,
+                        })
+                        .execute(db)?;
+                }
+                anyhow::Ok(())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Send the pending deliveries that are due, the database is only locked between requests.
+    async fn deliver(&self, db: &Mutex<DbConnection>) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let deliveries = schema::webhook_delivery::table
+            .filter(schema::webhook_delivery::status.eq(WebhookDeliveryStatus::Pending))
+            .filter(schema::webhook_delivery::next_attempt_at.le(now))
+            .order(schema::webhook_delivery::next_attempt_at.asc())
+            .select(WebhookDelivery::as_select())
+            .limit(20)
+            .load(&mut *db.lock().await)?;
+
+        for delivery in deliveries {
+            // Reserve the delivery, so that another dispatcher does not send it at the same time.
+            let lease = now + chrono::Duration::from_std(DELIVERY_LEASE)?;
+            let reserved = diesel::update(schema::webhook_delivery::table)
+                .filter(
+                    schema::webhook_delivery::webhook_delivery_id.eq(delivery.webhook_delivery_id),
+                )
+                .filter(schema::webhook_delivery::status.eq(WebhookDeliveryStatus::Pending))
+                .filter(schema::webhook_delivery::next_attempt_at.eq(delivery.next_attempt_at))
+                .set(schema::webhook_delivery::next_attempt_at.eq(lease))
+                .execute(&mut *db.lock().await)?;
+            if reserved == 0 {
+                continue;
+            }
+
+            let result = self.send(&delivery).await;
+            let attempts = delivery.attempts + 1;
+            let now = Utc::now().naive_utc();
+            let (status, response_status, error) = match result {
+                Ok(status) if status.is_success() => (
+                    WebhookDeliveryStatus::Delivered,
+                    Some(status.as_u16()),
+                    None,
+                ),
+                Ok(status) => (
+                    WebhookDeliveryStatus::Pending,
+                    Some(status.as_u16()),
+                    Some(format!("Unexpected response status: {}", status)),
+                ),
+                Err(err) => (WebhookDeliveryStatus::Pending, None, Some(err.to_string())),
+            };
+            let status = if status == WebhookDeliveryStatus::Pending && attempts >= MAX_ATTEMPTS {
+                WebhookDeliveryStatus::Failed
+            } else {
+                status
+            };
+            if let Some(error) = &error {
+                warn!(
+                    webhook_delivery_id = %delivery.webhook_delivery_id,
+                    url = %delivery.url,
+                    attempts,
+                    "Webhook delivery failed: {}",
+                    error
+                );
+            }
+
+            diesel::update(schema::webhook_delivery::table)
+                .filter(
+                    schema::webhook_delivery::webhook_delivery_id.eq(delivery.webhook_delivery_id),
+                )
+                .set((
+                    schema::webhook_delivery::attempts.eq(attempts),
+                    schema::webhook_delivery::response_status.eq(response_status.map(i32::from)),
+                    schema::webhook_delivery::error.eq(error),
+                    schema::webhook_delivery::next_attempt_at.eq(now + retry_delay(attempts)),
+                    schema::webhook_delivery::delivered_at
+                        .eq((status == WebhookDeliveryStatus::Delivered).then_some(now)),
+                    schema::webhook_delivery::status.eq(status),
+                ))
+                .execute(&mut *db.lock().await)?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, delivery: &WebhookDelivery) -> Result<reqwest::StatusCode> {
+        let event = serde_json::from_str::<serde_json::Value>(&delivery.payload)?
+            .get("event")
+            .and_then(|event| event.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut request = self
+            .http
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Transcodeck-Event", event)
+            .header(
+                "X-Transcodeck-Delivery",
+                delivery.webhook_delivery_id.to_string(),
+            )
+            .body(delivery.payload.clone());
+        if let Some(secret) = &self.secret {
+            let timestamp = Utc::now().timestamp();
+            request = request
+                .header("X-Transcodeck-Timestamp", timestamp.to_string())
+                .header(
+                    "X-Transcodeck-Signature",
+                    format!("sha256={}", sign(secret, timestamp, &delivery.payload)),
+                );
+        }
+        Ok(request.send().await?.status())
+    }
+}
+
+fn payload(
+    db: &mut DbConnection,
+    event: &model::WebhookEvent,
+    job: model::TranscodingJob,
+) -> Result<Payload> {
+    let fragment_id = match event.transcoding_fragment_job_id {
+        Some(fragment_job_id) => Some(
+            schema::transcoding_fragment_job::table
+                .filter(
+                    schema::transcoding_fragment_job::transcoding_fragment_job_id
+                        .eq(fragment_job_id),
+                )
+                .select(schema::transcoding_fragment_job::fragment_id)
+                .first::<Uuid>(db)?,
+        ),
+        None => None,
+    };
+
+    Ok(Payload {
+        id: event.webhook_event_id,
+        event: event.event.clone(),
+        created_at: event.created_at,
+        transcoding_job_id: job.transcoding_job_id,
+        media_id: job.media_id,
+        status: event.job_status.clone(),
+        completed_fragments: event.completed_fragments as usize,
+        total_fragments: event.total_fragments as usize,
+        transcoding_fragment_job_id: event.transcoding_fragment_job_id,
+        fragment_id,
+        fragment_status: event.fragment_status.clone(),
+    })
+}
+
+/// Delay before the next attempt of a delivery, after the given number of attempts.
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    let delay = RETRY_DELAY
+        .saturating_mul(1 << (attempts - 1).clamp(0, 16))
+        .min(MAX_RETRY_DELAY);
+    chrono::Duration::from_std(delay).unwrap()
+}
+
+/// HMAC-SHA256 of `<timestamp>.<payload>`, as hexadecimal. The timestamp lets the receivers
+/// reject the old deliveries, which could be replayed.
+fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Load the webhook deliveries, newest first, optionally only the ones of a transcoding job.
+pub fn deliveries(db: &mut DbConnection, job_id: Option<Uuid>) -> Result<Vec<DeliveryEntry>> {
+    let mut query = schema::webhook_delivery::table
+        .inner_join(schema::webhook_event::table)
+        .order(schema::webhook_delivery::created_at.desc())
+        .select((
+            WebhookDelivery::as_select(),
+            schema::webhook_event::event,
+            schema::webhook_event::transcoding_job_id,
+        ))
+        .into_boxed();
+    if let Some(job_id) = job_id {
+        query = query.filter(schema::webhook_event::transcoding_job_id.eq(job_id));
+    }
+    Ok(query
+        .load::<(WebhookDelivery, String, Uuid)>(db)?
+        .into_iter()
+        .map(|(delivery, event, transcoding_job_id)| DeliveryEntry {
+            event,
+            transcoding_job_id,
+            delivery,
+        })
+        .collect())
+}
+
+pub async fn webhook(db: DbConnection, cmd: WebhookCommand) -> Result<()> {
+    match cmd.cmd {
+        WebhookSubcommand::Dispatch(options) => {
+            let dispatcher = Dispatcher::new(&options)?;
+            info!("Dispatching the webhook events");
+            tokio::select! {
+                _ = dispatcher.run(Arc::new(Mutex::new(db))) => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            info!("Webhook dispatcher stopped");
+        }
+        WebhookSubcommand::Log { job_id } => {
+            let mut db = db;
+            println!(
+                "{:<36} {:<20} {:<10} {:>8} {:>8} {:<19} URL",
+                "DELIVERY", "EVENT", "STATUS", "ATTEMPTS", "RESPONSE", "CREATED"
+            );
+            for entry in deliveries(&mut db, job_id)? {
+                let delivery = &entry.delivery;
+                println!(
+                    "{:<36} {:<20} {:<10} {:>8} {:>8} {:<19} {}",
+                    delivery.webhook_delivery_id,
+                    entry.event,
+                    format!("{:?}", delivery.status),
+                    delivery.attempts,
+                    delivery
+                        .response_status
+                        .map(|status| status.to_string())
+                        .unwrap_or_else(|| "-".into()),
+                    delivery.created_at.format("%Y-%m-%d %H:%M:%S"),
+                    delivery.url,
+                );
+                if let Some(error) = &delivery.error {
+                    if delivery.status != WebhookDeliveryStatus::Delivered {
+                        println!("  {}", error);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
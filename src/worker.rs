@@ -0,0 +1,364 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
+use diesel::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use crate::db::DbConnection;
+use crate::transport::{Heartbeat, Transport};
+use crate::{job, model, schema, WorkerCommand, WorkerSubcommand, WorkersCommand};
+
+/// Minimum interval between two heartbeats of a worker.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A worker is considered offline when it has not been seen for this long.
+pub const OFFLINE_AFTER: Duration = Duration::from_secs(60);
+
+/// Capabilities of the ffmpeg build of a worker.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    pub ffmpeg_version: Option<String>,
+    pub encoders: Vec<String>,
+    pub filters: Vec<String>,
+}
+
+/// Detect the version, encoders and filters of an ffmpeg build.
+pub async fn detect_capabilities(ffmpeg_bin: &str) -> Result<Capabilities> {
+    let version = ffmpeg_output(ffmpeg_bin, "-version").await?;
+    let encoders = ffmpeg_output(ffmpeg_bin, "-encoders").await?;
+    let filters = ffmpeg_output(ffmpeg_bin, "-filters").await?;
+
+    Ok(Capabilities {
+        // e.g. `ffmpeg version 6.1.1 Copyright (c) 2000-2023 the FFmpeg developers`
+        ffmpeg_version: version
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(2))
+            .map(|version| version.to_string()),
+        // e.g. ` V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC`, after a `------` line
+        encoders: encoders
+            .lines()
+            .skip_while(|line| !line.trim().starts_with("---"))
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .map(|name| name.to_string())
+            .collect(),
+        // e.g. ` ..C scale             V->V       Scale the input video size and/or convert the image format.`
+        filters: filters
+            .lines()
+            .filter(|line| {
+                line.split_whitespace()
+                    .nth(2)
+                    .is_some_and(|io| io.contains("->"))
+            })
+            .filter_map(|line| line.split_whitespace().nth(1))
+            .map(|name| name.to_string())
+            .collect(),
+    })
+}
+
+async fn ffmpeg_output(ffmpeg_bin: &str, arg: &str) -> Result<String> {
+    let output = Command::new(ffmpeg_bin)
+        .arg("-hide_banner")
+        .arg(arg)
+        .output()
+        .await?;
+    if !output.status.success() {
+        bail!(
+            "Failed to run ffmpeg {}: status={:?}",
+            arg,
+            output.status.code()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Registration of a running daemon as a worker.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub worker_id: Uuid,
+    /// Whether a drain was requested through the `worker drain` command.
+    pub drain_requested: bool,
+    output_dir: PathBuf,
+    current_fragment_job_id: Option<Uuid>,
+    last_heartbeat: Instant,
+}
+
+impl Registration {
+    /// Register (or update the registration of) a worker.
+    pub async fn register(
+        transport: &mut impl Transport,
+        worker_id: Uuid,
+        ffmpeg_bin: &str,
+        output_dir: &Path,
+        template_values: &HashMap<String, String>,
+        tags: &[String],
+    ) -> Result<Self> {
+        let capabilities = detect_capabilities(ffmpeg_bin).await?;
+        let worker = model::NewWorker {
+            worker_id,
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            ffmpeg_version: capabilities.ffmpeg_version,
+            encoders: capabilities.encoders,
+            filters: capabilities.filters,
+            cpu_count: std::thread::available_parallelism()
+                .map(|n| n.get() as i32)
+                .unwrap_or(1),
+            template_variables: serde_json::to_value(template_values)?,
+            free_disk_space: free_disk_space(output_dir),
+            tags: tags.to_vec(),
+            memory: total_memory(),
+        };
+        let worker_id = transport.register(&worker).await?;
+
+        Ok(Registration {
+            worker_id,
+            drain_requested: false,
+            output_dir: output_dir.to_path_buf(),
+            current_fragment_job_id: None,
+            last_heartbeat: Instant::now(),
+        })
+    }
+
+    /// Update the `last_seen_at` heartbeat of the worker, and the fragment job it is working on,
+    /// and check whether a drain was requested.
+    ///
+    /// Heartbeats are throttled to [`HEARTBEAT_INTERVAL`], unless the current fragment job changed.
+    pub async fn heartbeat(
+        &mut self,
+        transport: &mut impl Transport,
+        current_fragment_job_id: Option<Uuid>,
+    ) -> Result<()> {
+        if current_fragment_job_id == self.current_fragment_job_id
+            && self.last_heartbeat.elapsed() < HEARTBEAT_INTERVAL
+        {
+            return Ok(());
+        }
+
+        let heartbeat = Heartbeat {
+            current_fragment_job_id,
+            free_disk_space: free_disk_space(&self.output_dir),
+        };
+        self.drain_requested = transport.heartbeat(self.worker_id, &heartbeat).await?;
+        self.current_fragment_job_id = current_fragment_job_id;
+        self.last_heartbeat = Instant::now();
+        Ok(())
+    }
+}
+
+fn free_disk_space(path: &Path) -> Option<i64> {
+    fs2::available_space(path).ok().map(|space| space as i64)
+}
+
+/// Whether a worker is still sending heartbeats.
+pub fn is_online(worker: &model::Worker) -> bool {
+    let offline_after = chrono::Duration::from_std(OFFLINE_AFTER).unwrap();
+    Utc::now().naive_utc() - worker.last_seen_at < offline_after
+}
+
+/// Whether a worker meets the requirements of a transcoding job.
+pub fn can_run(worker: &model::Worker, job: &model::JobRequirements) -> bool {
+    job.required_encoders
+        .iter()
+        .all(|encoder| worker.encoders.contains(encoder))
+        && job
+            .required_tags
+            .iter()
+            .all(|tag| worker.tags.contains(tag))
+        && job
+            .required_variables
+            .iter()
+            .all(|var| worker.template_variables.get(var).is_some())
+        && match (job.min_memory, worker.memory) {
+            (Some(min_memory), Some(memory)) => memory >= min_memory,
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
+}
+
+pub async fn workers(db: &mut DbConnection, cmd: WorkersCommand) -> Result<()> {
+    let workers = schema::worker::table
+        .order(schema::worker::last_seen_at.desc())
+        .select(model::Worker::as_select())
+        .load(db)?;
+    let now = Utc::now().naive_utc();
+
+    println!(
+        "{:<36} {:<20} {:<8} {:>4} {:<10} {:>10} {:<36} {:>10}",
+        "WORKER", "HOSTNAME", "STATUS", "CPUS", "FFMPEG", "FREE DISK", "CURRENT JOB", "LAST SEEN"
+    );
+    for worker in workers.iter().filter(|w| cmd.all || is_online(w)) {
+        println!(
+            "{:<36} {:<20} {:<8} {:>4} {:<10} {:>10} {:<36} {:>10}",
+            worker.worker_id,
+            worker.hostname,
+            if !is_online(worker) {
+                "offline"
+            } else if worker.draining {
+                "draining"
+            } else {
+                "online"
+            },
+            worker.cpu_count,
+            worker.ffmpeg_version.as_deref().unwrap_or("-"),
+            worker
+                .free_disk_space
+                .map(format_size)
+                .unwrap_or_else(|| "-".into()),
+            worker
+                .current_fragment_job_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| "-".into()),
+            job::format_duration((now - worker.last_seen_at).num_seconds() as f64) + " ago",
+        );
+        if cmd.verbose {
+            println!("  Encoders: {}", worker.encoders.join(", "));
+            println!("  Filters: {}", worker.filters.join(", "));
+            println!("  Template variables: {}", worker.template_variables);
+            println!("  Tags: {}", worker.tags.join(", "));
+            println!(
+                "  Memory: {}",
+                worker.memory.map(format_size).unwrap_or_else(|| "-".into())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn worker(db: &mut DbConnection, cmd: WorkerCommand) -> Result<()> {
+    match cmd.cmd {
+        WorkerSubcommand::Drain { worker_id } => {
+            let changed = diesel::update(schema::worker::table)
+                .filter(schema::worker::worker_id.eq(worker_id))
+                .set(schema::worker::draining.eq(true))
+                .execute(db)?;
+            if changed == 0 {
+                bail!("Worker not found: {}", worker_id);
+            }
+            println!(
+                "Drain requested, worker {} will stop after its current fragment job.",
+                worker_id
+            );
+        }
+        WorkerSubcommand::Token { worker_id } => {
+            let worker_id = worker_id.unwrap_or_else(Uuid::new_v4);
+            let token = generate_token();
+            diesel::insert_into(schema::worker_token::table)
+                .values(&model::NewWorkerToken {
+                    token_hash: hash_token(&token),
+                    worker_id,
+                })
+                .execute(db)?;
+            println!("Worker ID: {}", worker_id);
+            println!("Token: {}", token);
+            println!("The token is not stored, keep it safe: it cannot be displayed again.");
+        }
+        WorkerSubcommand::Revoke { worker_id } => {
+            let revoked = diesel::delete(schema::worker_token::table)
+                .filter(schema::worker_token::worker_id.eq(worker_id))
+                .execute(db)?;
+            if revoked == 0 {
+                bail!("No token found for worker: {}", worker_id);
+            }
+            println!("Revoked {} token(s) of worker {}.", revoked, worker_id);
+        }
+    }
+    Ok(())
+}
+
+/// Generate a random worker token, as 64 hexadecimal characters.
+fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    to_hex(&bytes)
+}
+
+/// Hash of a worker token, only the hashes are stored in the database.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Total memory of the machine, in bytes.
+fn total_memory() -> Option<i64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    // e.g. `MemTotal:       16318712 kB`
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<i64>().ok()?;
+    Some(kb * 1024)
+}
+
+/// Parse a size in bytes, with an optional binary unit suffix, e.g. `512M` or `8G`.
+pub fn parse_size(size: &str) -> Result<i64> {
+    let size = size.trim();
+    let (number, unit) = match size.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let multiplier: i64 = match unit
+        .trim()
+        .to_uppercase()
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => bail!("Invalid size unit: {}", unit),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| anyhow!("Invalid size: {}", size))?;
+    Ok((number * multiplier as f64) as i64)
+}
+
+pub fn format_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("8G").unwrap(), 8 << 30);
+        assert_eq!(parse_size("8GiB").unwrap(), 8 << 30);
+        assert_eq!(parse_size("2 gb").unwrap(), 2 << 30);
+        assert_eq!(parse_size("1.5K").unwrap(), 1536);
+        assert_eq!(parse_size("1T").unwrap(), 1 << 40);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512.0 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(8 << 30), "8.0 GiB");
+    }
+}